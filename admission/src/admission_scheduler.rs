@@ -0,0 +1,280 @@
+// Admits patients from the waitlist automatically on a cron schedule, so nobody
+// has to do so by hand. Admins can turn the schedule on and off while the app
+// runs, and the outcome of every scheduled run is recorded.
+
+use std::{error::Error, fmt::Display, str::FromStr, sync::{Arc, atomic::{AtomicBool, Ordering}}};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use common::user::User;
+use cron::Schedule;
+use serde::Serialize;
+use tracing::{warn, error, info};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::patient_services::PatientService;
+
+/// Runs every hour on the hour if no schedule is configured. Fields are
+/// second, minute, hour, day of month, month, and day of week.
+pub const DEFAULT_SCHEDULE: &str = "0 0 * * * *";
+
+/// who scheduled runs are recorded as being made by
+const SCHEDULER_ACTOR: &str = "admission-scheduler";
+
+pub struct AdmissionScheduler {
+    schedule: Schedule,
+    enabled: AtomicBool,
+
+    /// shared with the routes, as the service itself makes scheduled and
+    /// manual runs take turns
+    patients: Arc<PatientService>,
+    runs: Box<dyn AdmissionRunRepository>
+}
+
+impl AdmissionScheduler {
+    /// creates a scheduler for the given cron expression, which starts disabled
+    pub fn new<T>(schedule: &str, patients: Arc<PatientService>, runs: T) -> Result<Self, ScheduleError>
+    where
+        T: AdmissionRunRepository + 'static
+    {
+        let schedule = Schedule::from_str(schedule)
+            .map_err(|e| ScheduleError::InvalidSchedule(format!("{}: {}", schedule, e)))?;
+
+        Ok(Self {
+            schedule,
+            enabled: AtomicBool::new(false),
+            patients,
+            runs: Box::new(runs)
+        })
+    }
+
+    /// returns a copy of this scheduler, except enabled or disabled as given
+    pub fn with_enabled(self, enabled: bool) -> Self {
+        self.set_enabled(enabled);
+        self
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn status(&self) -> ScheduleStatus {
+        let enabled = self.enabled.load(Ordering::SeqCst);
+        ScheduleStatus {
+            schedule: self.schedule.to_string(),
+            enabled,
+            next_run_at: self.schedule.upcoming(Utc).next().filter(|_| enabled)
+        }
+    }
+
+    /// returns the given number of most recent scheduled runs, newest first
+    pub async fn get_recent_runs(&self, limit: usize) -> Result<Vec<AdmissionRun>, ScheduleError> {
+        self.runs.get_recent_runs(limit)
+            .await
+    }
+
+    /// Admits patients at each time in the schedule while enabled, forever.
+    /// Each run finishes before the next is scheduled, so runs never overlap,
+    /// and any times missed while a run was going are skipped.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            let Some(next) = self.schedule.upcoming(Utc).next() else {
+                warn!(schedule = %self.schedule, "Admission schedule has no more upcoming runs");
+                break;
+            };
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            if self.enabled.load(Ordering::SeqCst) {
+                self.run_now().await;
+            }
+        }
+    }
+
+    /// admits patients from the waitlist, then records how it went
+    async fn run_now(&self) -> AdmissionRun {
+        let started_at = Utc::now();
+        let result = self.patients.admit_patients_from_waitlist(None, &User::new(SCHEDULER_ACTOR))
+            .await;
+        let run = match result {
+            Ok(result) => AdmissionRun::restore(Uuid::new_v4(), started_at, Utc::now(), result.admitted().len(), result.waitlisted().len(), None),
+            Err(e) => AdmissionRun::restore(Uuid::new_v4(), started_at, Utc::now(), 0, 0, Some(e.to_string()))
+        };
+
+        info!(run_id = %run.id, admitted = run.admitted, waitlisted = run.waitlisted, error = ?run.error, "Scheduled admission run finished");
+
+        if let Err(e) = self.runs.store_run(&run).await {
+            error!(run_id = %run.id, error = %e, "Failed to record scheduled admission run");
+        }
+        run
+    }
+}
+
+/// whether scheduled runs are happening, and when the next one is
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleStatus {
+    /// a cron expression of second, minute, hour, day of month, month, and day
+    /// of week, in UTC
+    schedule: String,
+    enabled: bool,
+
+    /// None if disabled
+    next_run_at: Option<DateTime<Utc>>
+}
+
+/// the outcome of a scheduled admission run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionRun {
+    id: Uuid,
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>,
+
+    /// how many patients were admitted
+    admitted: usize,
+
+    /// how many patients were left on the waitlist
+    waitlisted: usize,
+
+    /// why the run failed, or None if it succeeded
+    error: Option<String>
+}
+
+impl AdmissionRun {
+    /// recreates a run which was previously stored
+    pub fn restore(
+        id: Uuid,
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+        admitted: usize,
+        waitlisted: usize,
+        error: Option<String>
+    ) -> Self {
+        Self {
+            id,
+            started_at,
+            finished_at,
+            admitted,
+            waitlisted,
+            error
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    pub fn finished_at(&self) -> DateTime<Utc> {
+        self.finished_at
+    }
+
+    pub fn admitted(&self) -> usize {
+        self.admitted
+    }
+
+    pub fn waitlisted(&self) -> usize {
+        self.waitlisted
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+/// backing store for the outcomes of scheduled admission runs
+#[async_trait]
+pub trait AdmissionRunRepository: Send + Sync {
+    async fn store_run(&self, run: &AdmissionRun) -> Result<(), ScheduleError>;
+
+    /// returns up to the given number of the most recent runs, newest first
+    async fn get_recent_runs(&self, limit: usize) -> Result<Vec<AdmissionRun>, ScheduleError>;
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    InvalidSchedule(String),
+    Repository(Box<dyn Error + 'static>)
+}
+
+impl ScheduleError {
+    pub fn repository(inner: impl Error + 'static) -> Self {
+        Self::Repository(Box::new(inner))
+    }
+}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSchedule(message) => write!(f, "Invalid admission schedule {}", message),
+            Self::Repository(inner) => write!(f, "Repository error: {}", inner)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::complement_service::ComplementService;
+    use mockall::mock;
+
+    use super::*;
+    use crate::{hospital_services::tests::MockDummy as MockHospitals, patient_services::tests::{MockPatients, MockComplements, events_accepting}};
+
+    mock! {
+        Runs {
+
+        }
+
+        #[async_trait]
+        impl AdmissionRunRepository for Runs {
+            async fn store_run(&self, run: &AdmissionRun) -> Result<(), ScheduleError>;
+            async fn get_recent_runs(&self, limit: usize) -> Result<Vec<AdmissionRun>, ScheduleError>;
+        }
+    }
+
+    fn patients_with_empty_waitlist() -> Arc<PatientService> {
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .returning(|| Ok(Vec::new()));
+        let mut hospitals = MockHospitals::new();
+        hospitals.expect_get_all_hospitals()
+            .returning(|| Ok(Vec::new()));
+        Arc::new(PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(MockComplements::new())))
+    }
+
+    #[test]
+    fn new_given_invalid_schedule_fails() {
+        let result = AdmissionScheduler::new("every tuesday", patients_with_empty_waitlist(), MockRuns::new());
+
+        assert!(matches!(result, Err(ScheduleError::InvalidSchedule(_))));
+    }
+
+    #[test]
+    fn status_given_disabled_has_no_next_run() {
+        let sut = AdmissionScheduler::new(DEFAULT_SCHEDULE, patients_with_empty_waitlist(), MockRuns::new())
+            .unwrap();
+
+        assert!(sut.status().next_run_at.is_none());
+        sut.set_enabled(true);
+        assert!(sut.status().next_run_at.unwrap() > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn run_now_records_outcome() {
+        let mut runs = MockRuns::new();
+        runs.expect_store_run()
+            .once()
+            .returning(|_| Ok(()));
+        let sut = AdmissionScheduler::new(DEFAULT_SCHEDULE, patients_with_empty_waitlist(), runs)
+            .unwrap();
+
+        let run = sut.run_now().await;
+
+        assert_eq!((0, 0, None), (run.admitted(), run.waitlisted(), run.error()));
+    }
+}
@@ -0,0 +1,271 @@
+// Every error the API returns is converted into an ApiError, so clients always
+// receive an RFC 7807 problem details document, no matter which layer failed.
+// https://www.rfc-editor.org/rfc/rfc7807
+
+use std::fmt::Display;
+
+use actix_web::{ResponseError, HttpResponse, http::{StatusCode, header::ContentType}, web::{ServiceConfig, JsonConfig, QueryConfig, PathConfig}};
+use serde::Serialize;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    admission_scheduler::ScheduleError,
+    authentication::openid::OpenIdError,
+    hospital_services::{RepositoryError, TransferError, HospitalManagementError},
+    idempotency_services::IdempotencyError,
+    patient_services::PatientError,
+    user_services::UserError,
+    webhook_services::WebhookError
+};
+
+/// the media type of problem details documents
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// an error as described to API clients
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    /// identifies the kind of problem, derived from code
+    #[serde(rename = "type")]
+    problem_type: String,
+
+    /// a short summary which is the same for every problem of this kind
+    title: String,
+    status: u16,
+
+    /// explains this particular occurrence of the problem
+    detail: String,
+
+    /// a stable, machine-readable name for this kind of problem, which
+    /// clients can match on
+    code: String
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &str, title: &str, detail: impl ToString) -> Self {
+        Self {
+            problem_type: format!("urn:problem-type:admission:{}", code),
+            title: title.to_owned(),
+            status: status.as_u16(),
+            detail: detail.to_string(),
+            code: code.to_owned()
+        }
+    }
+
+    pub fn bad_request(code: &str, title: &str, detail: impl ToString) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, title, detail)
+    }
+
+    pub fn not_found(code: &str, title: &str, detail: impl ToString) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, title, detail)
+    }
+
+    pub fn conflict(code: &str, title: &str, detail: impl ToString) -> Self {
+        Self::new(StatusCode::CONFLICT, code, title, detail)
+    }
+
+    /// for when the client's If-Match does not match what is stored
+    pub fn precondition_failed(detail: impl ToString) -> Self {
+        Self::new(StatusCode::PRECONDITION_FAILED, "stale-version", "Precondition failed", detail)
+    }
+
+    /// Creates an error for something that went wrong on the server. The cause
+    /// is logged rather than returned, as it may contain sensitive details
+    /// such as SQL or connection strings.
+    pub fn internal(cause: impl Display) -> Self {
+        error!(%cause, "Internal server error");
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal-error", "Internal server error", "An unexpected error occurred")
+    }
+}
+
+/// Makes actix report malformed JSON bodies, query strings, and paths as
+/// problem details, rather than as plain text.
+pub fn configure_extractor_errors(cfg: &mut ServiceConfig) {
+    cfg.app_data(JsonConfig::default()
+        .error_handler(|e, _req| ApiError::bad_request("invalid-body", "Invalid request body", e).into()));
+    cfg.app_data(QueryConfig::default()
+        .error_handler(|e, _req| ApiError::bad_request("invalid-query", "Invalid query string", e).into()));
+    cfg.app_data(PathConfig::default()
+        .error_handler(|e, _req| ApiError::not_found("not-found", "Not found", e).into()));
+}
+
+/// handles requests which do not match any API route
+pub async fn not_found() -> HttpResponse {
+    ApiError::not_found("not-found", "Not found", "No such resource").error_response()
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.title, self.code, self.detail)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .insert_header(ContentType(PROBLEM_JSON.parse().expect("problem+json should be a valid media type")))
+            .body(serde_json::to_string(self).expect("problem details should always serialize"))
+    }
+}
+
+impl From<RepositoryError> for ApiError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::InvalidHospitalName(_) => Self::not_found("hospital-not-found", "Hospital not found", error),
+            RepositoryError::DuplicateHospitalName(_) => Self::conflict("hospital-name-taken", "Hospital name taken", error),
+            RepositoryError::InvalidCursor(_) => Self::bad_request("invalid-cursor", "Invalid cursor", error),
+            RepositoryError::StaleVersion(_) => Self::precondition_failed(error),
+            RepositoryError::HospitalFull(_) => Self::conflict("hospital-full", "Hospital full", error),
+            RepositoryError::HospitalNotEmpty(_, _) => Self::conflict("hospital-not-empty", "Hospital still has patients", error),
+            RepositoryError::PatientNotAdmitted(_, _) => Self::not_found("patient-not-admitted", "Patient not admitted", error),
+            RepositoryError::Other(_) | RepositoryError::Tiberius(_) => Self::internal(error)
+        }
+    }
+}
+
+impl From<PatientError> for ApiError {
+    fn from(error: PatientError) -> Self {
+        match error {
+            PatientError::AlreadyExists(_) => Self::conflict("patient-already-exists", "Patient already exists", error),
+            PatientError::NotFound(_) => Self::not_found("patient-not-found", "Patient not found", error),
+            PatientError::NotWaitlisted(_) => Self::conflict("patient-not-waitlisted", "Patient not on the waitlist", error),
+            PatientError::Stale(_) => Self::precondition_failed(error),
+            PatientError::HospitalUnavailable(_) => Self::conflict("hospital-unavailable", "Hospital unavailable", error),
+            PatientError::Invalid(_) => Self::bad_request("invalid-patient", "Invalid patient", error),
+            // the hospital repository's client errors stay client errors
+            PatientError::Repository(inner) => match inner.downcast::<RepositoryError>() {
+                Ok(hospital_error) => Self::from(*hospital_error),
+                Err(inner) => Self::internal(PatientError::Repository(inner))
+            },
+            PatientError::Unsupported => Self::internal(error)
+        }
+    }
+}
+
+impl From<TransferError> for ApiError {
+    fn from(error: TransferError) -> Self {
+        match error {
+            TransferError::HospitalNotFound(_) => Self::not_found("hospital-not-found", "Hospital not found", error),
+            TransferError::PatientNotAdmitted(_, _) => Self::not_found("patient-not-admitted", "Patient not admitted", error),
+            TransferError::SameHospital(_) => Self::bad_request("same-hospital", "Patient already in hospital", error),
+            TransferError::Disallowed(_, _) => Self::conflict("hospital-disallowed", "Patient may not be admitted", error),
+            TransferError::HospitalFull(_) => Self::conflict("hospital-full", "Hospital full", error),
+            TransferError::Repository(inner) => Self::from(inner)
+        }
+    }
+}
+
+impl From<HospitalManagementError> for ApiError {
+    fn from(error: HospitalManagementError) -> Self {
+        match error {
+            HospitalManagementError::NotFound(_) => Self::not_found("hospital-not-found", "Hospital not found", error),
+            HospitalManagementError::InvalidName(_) => Self::bad_request("invalid-hospital-name", "Invalid hospital name", error),
+            HospitalManagementError::NameTaken(_) => Self::conflict("hospital-name-taken", "Hospital name taken", error),
+            HospitalManagementError::StillAdmitted(_, _) => Self::conflict("hospital-not-empty", "Hospital still has patients", error),
+            HospitalManagementError::Repository(inner) => Self::from(inner)
+        }
+    }
+}
+
+impl From<IdempotencyError> for ApiError {
+    fn from(error: IdempotencyError) -> Self {
+        match error {
+            IdempotencyError::InvalidKey(_) => Self::bad_request("invalid-idempotency-key", "Invalid idempotency key", error),
+            IdempotencyError::KeyReused(_) => Self::new(StatusCode::UNPROCESSABLE_ENTITY, "idempotency-key-reused", "Idempotency key reused", error),
+            IdempotencyError::Repository(_) => Self::internal(error)
+        }
+    }
+}
+
+impl From<WebhookError> for ApiError {
+    fn from(error: WebhookError) -> Self {
+        match error {
+            WebhookError::NotFound(_) => Self::not_found("webhook-not-found", "Webhook not found", error),
+            WebhookError::Invalid(_) => Self::bad_request("invalid-webhook", "Invalid webhook", error),
+            WebhookError::Repository(_) => Self::internal(error)
+        }
+    }
+}
+
+impl From<ScheduleError> for ApiError {
+    fn from(error: ScheduleError) -> Self {
+        match error {
+            ScheduleError::InvalidSchedule(_) => Self::bad_request("invalid-schedule", "Invalid schedule", error),
+            ScheduleError::Repository(_) => Self::internal(error)
+        }
+    }
+}
+
+impl From<UserError> for ApiError {
+    fn from(error: UserError) -> Self {
+        Self::internal(error)
+    }
+}
+
+impl From<OpenIdError> for ApiError {
+    fn from(error: OpenIdError) -> Self {
+        match error {
+            OpenIdError::BadCsrfToken => Self::bad_request("bad-csrf-token", "Bad CSRF token", error),
+            // may contain a trace from the provider, so don't disclose it
+            OpenIdError::Other(_) => {
+                warn!(%error, "OpenID authentication failed");
+                Self::bad_request("openid-authentication-failed", "Authentication failed", "The OpenID provider did not authenticate you")
+            },
+            OpenIdError::BadIssuer(_) => Self::internal(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+
+    use super::*;
+
+    #[test]
+    fn internal_errors_do_not_leak_their_cause() {
+        let sut = ApiError::from(RepositoryError::other("SELECT * FROM rust.Secrets"));
+
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, sut.status_code());
+        assert!(!sut.detail.contains("Secrets"));
+    }
+
+    #[test]
+    fn patient_errors_map_to_matching_status_codes() {
+        let id = uuid::Uuid::new_v4();
+
+        assert_eq!(StatusCode::NOT_FOUND, ApiError::from(PatientError::NotFound(id)).status_code());
+        assert_eq!(StatusCode::CONFLICT, ApiError::from(PatientError::NotWaitlisted(id)).status_code());
+        assert_eq!(StatusCode::BAD_REQUEST, ApiError::from(PatientError::Invalid(String::from("Foo"))).status_code());
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, ApiError::from(PatientError::Unsupported).status_code());
+    }
+
+    #[test]
+    fn patient_errors_wrapping_hospital_errors_keep_their_status_codes() {
+        let invalid_cursor = PatientError::repository(RepositoryError::InvalidCursor(String::from("Foo")));
+        let unknown_hospital = PatientError::repository(RepositoryError::invalid_hospital_name("Foo"));
+        let failed_query = PatientError::repository(RepositoryError::other("Foo"));
+
+        assert_eq!(StatusCode::BAD_REQUEST, ApiError::from(invalid_cursor).status_code());
+        assert_eq!(StatusCode::NOT_FOUND, ApiError::from(unknown_hospital).status_code());
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, ApiError::from(failed_query).status_code());
+    }
+
+    #[test]
+    fn error_response_is_problem_json() {
+        let sut = ApiError::not_found("patient-not-found", "Patient not found", "Foo");
+
+        let response = sut.error_response();
+
+        assert_eq!(PROBLEM_JSON, response.headers().get("content-type").unwrap());
+        let body = response.into_body().try_into_bytes().unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("patient-not-found", json["code"]);
+        assert_eq!(404, json["status"]);
+        assert_eq!("urn:problem-type:admission:patient-not-found", json["type"]);
+    }
+}
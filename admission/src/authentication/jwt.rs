@@ -1,18 +1,16 @@
 // this file demonstrates how to use JSON web tokens to authenticate users
 
-use std::env;
-
-use actix_web::{dev::ServiceRequest, Error, error::{ErrorInternalServerError, ErrorUnauthorized}, web::{ServiceConfig, post, Json, self}};
+use actix_web::{dev::ServiceRequest, Error, HttpMessage, http::StatusCode, web::{ServiceConfig, post, Json, self}};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use chrono::{Utc, Duration};
 use jsonwebtoken::{encode, EncodingKey, Header, decode, DecodingKey, Validation, Algorithm};
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
-use common::user::{User, LoginRequest};
-use tokio::sync::Mutex;
+use common::{user::{User, LoginRequest}, logging::Redacted};
+use tracing::debug;
 
-use crate::user_services::UserService;
+use crate::{api_error::ApiError, user_services::UserService};
 
 const ISSUER: &str = "https://example.com";
 
@@ -31,24 +29,30 @@ pub fn configure_jwt_routes(cfg: &mut ServiceConfig) {
 }
 
 async fn jwt_login_handler(
-    users: web::Data<Mutex<UserService>>,
+    users: web::Data<UserService>,
+    secret: web::Data<JwtSecret>,
     login_request: Json<LoginRequest>
-) -> actix_web::Result<String> {
+) -> Result<String, ApiError> {
     // a production system would verify the user's credentials
 
-    let mut mutex = users.lock().await;
-    let user = mutex.get_user_by_email(&login_request.email())
-        .await
-        .map_err(ErrorInternalServerError)?;
+    let user = users.get_user_by_email(&login_request.email())
+        .await?;
 
-    match make_token(&user) {
-        Ok(token) => Ok(token),
-        Err(e) => Err(ErrorInternalServerError(e))
-    }
+    make_token(&user, &secret)
+        .map_err(ApiError::internal)
 } 
 
+/// the key tokens are signed with, which must be registered as app data
+pub struct JwtSecret(String);
+
+impl JwtSecret {
+    pub fn new(secret: &str) -> Self {
+        Self(String::from(secret))
+    }
+}
+
 /// creates a JWT for the given user
-pub fn make_token(user: &User) -> Result<String, jsonwebtoken::errors::Error> {
+pub fn make_token(user: &User, secret: &JwtSecret) -> Result<String, jsonwebtoken::errors::Error> {
     let now = Utc::now();
     let later = now.checked_add_signed(Duration::minutes(30)).unwrap();
 
@@ -64,38 +68,40 @@ pub fn make_token(user: &User) -> Result<String, jsonwebtoken::errors::Error> {
     encode(
         &Header::default(), 
         &claims, 
-        &EncodingKey::from_secret(load_secret().as_ref())
+        &EncodingKey::from_secret(secret.0.as_ref())
     )
 }
 
-fn load_secret() -> String {
-    env::var("JWT_SECRET").expect("Don't forget to set the JWT_SECRET environment variable!")
-}
-
 /// Usage: wrap(HttpAuthentication::Bearer(jwt_auth_middleware))
 pub async fn jwt_auth_middleware(
     request: ServiceRequest, 
     bearer: BearerAuth
 ) -> Result<ServiceRequest, (Error, ServiceRequest)> {
-    match decode_token(bearer.token()) {
+    let secret = request.app_data::<web::Data<JwtSecret>>()
+        .expect("JwtSecret should be registered as app data");
+    match decode_token(bearer.token(), secret) {
         Ok(claims) => {
-            println!("Claims: {:#?}", claims);
-            println!("Request: {:#?}", request);
-
             // check if any of the user's groups are authorized to perform the request
-            if is_get(&request) || claims.user.groups().iter().any(|g| is_group_authorized(g, &request)) {
-                println!("Authorized.");
+            if is_open_to_everyone(&request) || claims.user.groups().iter().any(|g| is_group_authorized(g, &request)) {
+                debug!(subject = %claims.sub, token = %Redacted(bearer.token()), "Authorized");
+                // lets handlers know who is making the request using web::ReqData<User>
+                request.extensions_mut().insert(claims.user);
                 Ok(request)
             } else {
-                println!("Unauthorized.");
-                Err((ErrorUnauthorized("You do not belong to any group authorized to access this resource"), request))
+                debug!(subject = %claims.sub, "Forbidden, as the user does not belong to an authorized group");
+                let error = ApiError::new(StatusCode::FORBIDDEN, "forbidden", "Forbidden", "You do not belong to any group authorized to access this resource");
+                Err((error.into(), request))
             }
         },
-        Err(e) => Err((ErrorInternalServerError(e), request))
+        Err(e) => {
+            debug!(token = %Redacted(bearer.token()), error = %e, "Rejected invalid token");
+            let error = ApiError::new(StatusCode::UNAUTHORIZED, "invalid-token", "Invalid token", e);
+            Err((error.into(), request))
+        }
     }
 }
 
-fn decode_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+fn decode_token(token: &str, secret: &JwtSecret) -> Result<Claims, jsonwebtoken::errors::Error> {
     let mut validator = Validation::new(Algorithm::HS256);
     validator.set_audience(&[ISSUER]); // reject if audience doesn't match
     validator.set_issuer(&[ISSUER]); // reject if issuer doesn't match
@@ -103,7 +109,7 @@ fn decode_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     // automatically validates the expiration date
     let result = decode::<Claims>(
         token, 
-        &DecodingKey::from_secret(load_secret().as_ref()), 
+        &DecodingKey::from_secret(secret.0.as_ref()), 
         &validator
     );
 
@@ -113,10 +119,58 @@ fn decode_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     }
 }
 
-fn is_get(request: &ServiceRequest) -> bool {
-    request.method() == Method::GET
+/// the resource which requires the admin group to even read, as webhook URLs
+/// and delivery logs are only for admins
+const ADMIN_ONLY_RESOURCE: &str = "webhooks";
+
+/// any signed-in user may GET, except for the admin-only resource
+fn is_open_to_everyone(request: &ServiceRequest) -> bool {
+    request.method() == Method::GET && !request.path().split('/').any(|segment| segment == ADMIN_ONLY_RESOURCE)
 }
 
 fn is_group_authorized(group: &str, _request: &ServiceRequest) -> bool {
     group == "admin"
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, dev::Service, test::{init_service, TestRequest}};
+    use actix_web_httpauth::middleware::HttpAuthentication;
+
+    use super::*;
+
+    /// sends a GET to the given path with a token for the given user, returning
+    /// the response status
+    async fn get_as(user: &User, path: &str) -> StatusCode {
+        let secret = JwtSecret::new("secret");
+        let token = make_token(user, &secret).unwrap();
+        let app = init_service(App::new()
+            .app_data(web::Data::new(secret))
+            .wrap(HttpAuthentication::bearer(jwt_auth_middleware))
+            .default_service(web::to(HttpResponse::Ok))
+        ).await;
+        let req = TestRequest::get()
+            .uri(path)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request();
+        match app.call(req).await {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code()
+        }
+    }
+
+    #[actix_web::test]
+    async fn jwt_auth_middleware_given_a_get_lets_any_user_through() {
+        assert_eq!(StatusCode::OK, get_as(&User::new("foo@bar.baz"), "/api/v1/hospitals").await);
+    }
+
+    #[actix_web::test]
+    async fn jwt_auth_middleware_given_a_webhook_get_requires_admin() {
+        let mut admin = User::new("admin@bar.baz");
+        admin.add_to_group("admin");
+
+        assert_eq!(StatusCode::FORBIDDEN, get_as(&User::new("foo@bar.baz"), "/api/v1/webhooks").await);
+        assert_eq!(StatusCode::FORBIDDEN, get_as(&User::new("foo@bar.baz"), "/api/v1/webhooks/1/deliveries").await);
+        assert_eq!(StatusCode::OK, get_as(&admin, "/api/v1/webhooks").await);
+    }
+}
@@ -3,18 +3,17 @@
 // https://github.com/ramosbugs/openidconnect-rs/blob/main/examples/google.rs
 // https://openid.net/specs/openid-connect-basic-1_0.html
 
-use std::{env, fmt::{Display, Debug}, error::Error};
+use std::{fmt::{Display, Debug}, error::Error};
 
 use actix_session::Session;
-use actix_web::{web::{ServiceConfig, get, self}, HttpResponse, error};
+use actix_web::{web::{ServiceConfig, get, self}, HttpResponse};
 use openidconnect::{core::{CoreProviderMetadata, CoreClient, CoreResponseType, CoreAuthPrompt}, IssuerUrl, reqwest::async_http_client, ClientId, RedirectUrl, CsrfToken, Nonce, AuthenticationFlow, Scope, ClientSecret, AuthorizationCode};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
 
-use crate::user_services::UserService;
+use crate::{api_error::ApiError, user_services::UserService, config::OpenIdConfig};
 use common::user::User;
-use super::jwt::make_token;
+use super::jwt::{make_token, JwtSecret};
 
 /// used by main to set up the openid routes
 pub fn configure_openid_routes(cfg: &mut ServiceConfig) {
@@ -26,7 +25,7 @@ pub fn configure_openid_routes(cfg: &mut ServiceConfig) {
 async fn login_handler(
     service: web::Data<OpenIdService>,
     session: Session
-) -> actix_web::Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
 
     let url_etc = service.generate_auth_url();
     
@@ -34,9 +33,9 @@ async fn login_handler(
     // single-use nonce and state, which will be verified when handling the
     // openid server response
     session.insert("openid-nonce", url_etc.nonce.secret())
-        .map_err(error::ErrorInternalServerError)?;
+        .map_err(ApiError::internal)?;
     session.insert("openid-state", url_etc.state.secret())
-        .map_err(error::ErrorInternalServerError)?;
+        .map_err(ApiError::internal)?;
 
     Ok(HttpResponse::SeeOther() // redirects user to authentication URL
         .append_header(("Location", url_etc.url.to_string()))
@@ -48,18 +47,19 @@ async fn login_handler(
 /// we can use to obtain information granted in the scopes
 async fn handle_auth_callback(
     service: web::Data<OpenIdService>,
-    users: web::Data<Mutex<UserService>>,
+    users: web::Data<UserService>,
+    secret: web::Data<JwtSecret>,
     session: Session,
     openid_response: web::Query<AuthenticationCallbackParameters>
-) -> actix_web::Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
 
     let nonce: String = session.get("openid-nonce")
-        .map_err(error::ErrorBadRequest)?
-        .ok_or_else(|| error::ErrorBadRequest("openid-nonce not set"))?;
+        .map_err(|e| ApiError::bad_request("openid-session-invalid", "Invalid OpenID session", e))?
+        .ok_or_else(|| ApiError::bad_request("openid-session-invalid", "Invalid OpenID session", "openid-nonce not set"))?;
     
     let state: String = session.get("openid-state")
-        .map_err(error::ErrorBadRequest)?
-        .ok_or_else(|| error::ErrorBadRequest("openid-state not set"))?;
+        .map_err(|e| ApiError::bad_request("openid-session-invalid", "Invalid OpenID session", e))?
+        .ok_or_else(|| ApiError::bad_request("openid-session-invalid", "Invalid OpenID session", "openid-state not set"))?;
     
     session.purge(); // no longer need session
 
@@ -68,18 +68,13 @@ async fn handle_auth_callback(
             old_nonce: nonce, 
             old_state: state 
         })
-        .await
-        .map_err(error::ErrorBadRequest)?;
+        .await?;
 
-    let mut lock = users.lock()
-        .await;
+    let new_user = users.get_user_by_email(&email)
+        .await?;
     
-    let new_user = lock.get_user_by_email(&email)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
-    
-    let jwt = make_token(&new_user)
-        .map_err(error::ErrorInternalServerError)?;
+    let jwt = make_token(&new_user, &secret)
+        .map_err(ApiError::internal)?;
     
     Ok(HttpResponse::Ok().json(CallbackResponse {
         jwt,
@@ -96,35 +91,48 @@ struct CallbackResponse {
 /// provides services related to OpenID
 #[derive(Debug)]
 pub struct OpenIdService {
-    client: CoreClient
+    client: CoreClient,
+
+    /// kept so readiness checks can make sure discovery still works
+    issuer_url: IssuerUrl
 }
 
 impl OpenIdService {
     
-    /// reads the environment variables and creates a new OpenIdService accordingly
-    pub async fn from_env() -> Result<Self, OpenIdError> {
-        let options = OpenIdOptions::from_env()?;
-        let issuer_url = IssuerUrl::new(options.url.to_owned())
-            .map_err(|_| OpenIdError::BadIssuer(options.url.to_owned()))?;
+    /// Discovers the configured provider, which redirects users back to the
+    /// given public URL of this app once they log in.
+    pub async fn new(config: &OpenIdConfig, public_url: &str) -> Result<Self, OpenIdError> {
+        let issuer_url = IssuerUrl::new(config.url.to_owned())
+            .map_err(|_| OpenIdError::BadIssuer(config.url.to_owned()))?;
         
-        let provider_document = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
+        let provider_document = CoreProviderMetadata::discover_async(issuer_url.clone(), async_http_client)
             .await
             .expect("Expected issuer to provide a provider document");
     
         let client = CoreClient::from_provider_metadata(
                 provider_document,
-                ClientId::new(options.client_id),
-                options.client_secret.map(ClientSecret::new)
+                ClientId::new(config.client_id.to_owned()),
+                config.client_secret.to_owned().map(ClientSecret::new)
             )
             .set_redirect_uri(
-                RedirectUrl::new(String::from("http://localhost:8080/openid")).expect("Expected valid URL")
+                RedirectUrl::new(public_url.trim_end_matches('/').to_owned() + "/openid")
+                    .map_err(OpenIdError::other)?
             );
 
         Ok(Self {
-            client
+            client,
+            issuer_url
         })
     }
 
+    /// makes sure the provider document can still be discovered
+    pub async fn check_discovery(&self) -> Result<(), OpenIdError> {
+        CoreProviderMetadata::discover_async(self.issuer_url.clone(), async_http_client)
+            .await
+            .map_err(OpenIdError::other)?;
+        Ok(())
+    }
+
     /// creates a new authorization URL and associated security parameters    
     fn generate_auth_url(&self) -> OpenIdUrl {
         
@@ -181,35 +189,9 @@ impl OpenIdService {
     }
 }
 
-#[derive(Debug)]
-pub struct OpenIdOptions {
-    url: String,
-    client_id: String,
-    client_secret: Option<String>
-}
-
-impl OpenIdOptions {
-
-    pub fn from_env() -> Result<Self, OpenIdError> {
-        let url = env::var("OPENID_URL")
-            .map_err(|_| OpenIdError::missing_env("OPENID_URL"))?;
-        let id = env::var("OPENID_CLIENT_ID")
-            .map_err(|_| OpenIdError::missing_env("OPENID_CLIENT_ID"))?;
-        let maybe_secret = env::var("OPENID_CLIENT_SECRET")
-            .ok(); // can be None
-
-        Ok(OpenIdOptions { 
-            url, 
-            client_id: id, 
-            client_secret: maybe_secret 
-        })
-    }
-}
-
 #[derive(Debug)]
 pub enum OpenIdError {
     Other(String),
-    MissingEnv(String),
     BadCsrfToken,
     BadIssuer(String)
 }
@@ -228,17 +210,12 @@ impl OpenIdError {
         }
         Self::other(msg)
     }
-
-    fn missing_env(name: &str) -> Self {
-        Self::MissingEnv(String::from(name))
-    }
 }
 
 impl Display for OpenIdError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Other(msg) => write!(f, "Other error message: {}", msg),
-            Self::MissingEnv(name) => write!(f, "Missing environment variable: {}", name),
             Self::BadCsrfToken => write!(f, "Bad CSRF token"), // don't disclose sensitive info
             Self::BadIssuer(url) => write!(f, "Bad issuer URL: {}", url)
         }
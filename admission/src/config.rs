@@ -0,0 +1,302 @@
+// Everything admission can be configured with, read from the [admission] and
+// [logging] sections of the configuration file. Each setting can be overridden
+// by the environment variable named beside it.
+
+use std::str::FromStr;
+
+use common::config::{Settings, Overrides, Problems, LoggingConfig};
+use cron::Schedule;
+use serde::Deserialize;
+
+use crate::{patient_services::admission_strategy::AdmissionStrategyKind, idempotency_services::DEFAULT_WINDOW_HOURS, webhook_services::{DEFAULT_MAX_ATTEMPTS, DEFAULT_INITIAL_DELAY, MAX_ATTEMPTS}};
+
+/// other binaries' sections are ignored, so they can share a file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub admission: AdmissionConfig
+}
+
+impl Settings for Config {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        self.logging.override_from(environment);
+        self.admission.override_from(environment);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        self.logging.validate(problems);
+        self.admission.validate(problems);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    /// where to listen for requests (ADMISSION_HOST)
+    pub host: String,
+
+    /// (ADMISSION_PORT)
+    pub port: u16,
+
+    /// where users reach this service, which OpenID redirects them back to
+    /// (ADMISSION_PUBLIC_URL)
+    pub public_url: String,
+
+    /// (COMPLEMENT_URL)
+    pub complement_url: String,
+
+    /// signs JSON web tokens (JWT_SECRET)
+    pub jwt_secret: String,
+
+    /// used when admission runs don't ask for a strategy (ADMISSION_STRATEGY)
+    pub default_strategy: AdmissionStrategyKind,
+
+    /// how long Idempotency-Keys are remembered (IDEMPOTENCY_WINDOW_HOURS)
+    pub idempotency_window_hours: i64,
+
+    /// When to admit patients from the waitlist, as a cron expression. If set,
+    /// scheduled runs start enabled. (ADMISSION_SCHEDULE)
+    pub schedule: Option<String>,
+
+    pub database: DatabaseConfig,
+    pub openid: OpenIdConfig,
+    pub webhooks: WebhookConfig
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("127.0.0.1"),
+            port: 8080,
+            public_url: String::from("http://localhost:8080"),
+            complement_url: String::from("http://localhost:8081"),
+            jwt_secret: String::new(),
+            default_strategy: AdmissionStrategyKind::default(),
+            idempotency_window_hours: DEFAULT_WINDOW_HOURS,
+            schedule: None,
+            database: DatabaseConfig::default(),
+            openid: OpenIdConfig::default(),
+            webhooks: WebhookConfig::default()
+        }
+    }
+}
+
+impl Settings for AdmissionConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set("ADMISSION_HOST", &mut self.host);
+        environment.set("ADMISSION_PORT", &mut self.port);
+        environment.set("ADMISSION_PUBLIC_URL", &mut self.public_url);
+        environment.set("COMPLEMENT_URL", &mut self.complement_url);
+        environment.set("JWT_SECRET", &mut self.jwt_secret);
+        environment.set("ADMISSION_STRATEGY", &mut self.default_strategy);
+        environment.set("IDEMPOTENCY_WINDOW_HOURS", &mut self.idempotency_window_hours);
+        environment.set_optional("ADMISSION_SCHEDULE", &mut self.schedule);
+        self.database.override_from(environment);
+        self.openid.override_from(environment);
+        self.webhooks.override_from(environment);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        problems.require("admission.host", "ADMISSION_HOST", &self.host);
+        problems.require_url("admission.public_url", "ADMISSION_PUBLIC_URL", &self.public_url);
+        problems.require_url("admission.complement_url", "COMPLEMENT_URL", &self.complement_url);
+        problems.require("admission.jwt_secret", "JWT_SECRET", &self.jwt_secret);
+        if self.idempotency_window_hours < 1 {
+            problems.add("admission.idempotency_window_hours must be at least 1");
+        }
+        if let Some(Err(e)) = self.schedule.as_deref().map(Schedule::from_str) {
+            problems.add(format!("admission.schedule is not a valid cron expression: {}", e));
+        }
+        self.database.validate(problems);
+        self.openid.validate(problems);
+        self.webhooks.validate(problems);
+    }
+}
+
+/// the [admission.database] section
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// (TIBERIUS_HOST)
+    pub host: String,
+
+    /// (TIBERIUS_PORT)
+    pub port: u16,
+
+    /// (TIBERIUS_DATABASE)
+    pub name: String,
+
+    /// (TIBERIUS_USERNAME)
+    pub username: String,
+
+    /// (TIBERIUS_PASSWORD)
+    pub password: String
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("localhost"),
+            port: 1433,
+            name: String::from("RustDB"),
+            username: String::new(),
+            password: String::new()
+        }
+    }
+}
+
+impl Settings for DatabaseConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set("TIBERIUS_HOST", &mut self.host);
+        environment.set("TIBERIUS_PORT", &mut self.port);
+        environment.set("TIBERIUS_DATABASE", &mut self.name);
+        environment.set("TIBERIUS_USERNAME", &mut self.username);
+        environment.set("TIBERIUS_PASSWORD", &mut self.password);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        problems.require("admission.database.host", "TIBERIUS_HOST", &self.host);
+        problems.require("admission.database.name", "TIBERIUS_DATABASE", &self.name);
+        problems.require("admission.database.username", "TIBERIUS_USERNAME", &self.username);
+        problems.require("admission.database.password", "TIBERIUS_PASSWORD", &self.password);
+    }
+}
+
+/// the [admission.openid] section
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenIdConfig {
+    /// the provider, such as https://example.com/ (OPENID_URL)
+    pub url: String,
+
+    /// the app's ID registered with the provider (OPENID_CLIENT_ID)
+    pub client_id: String,
+
+    /// (OPENID_CLIENT_SECRET)
+    pub client_secret: Option<String>
+}
+
+impl Settings for OpenIdConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set("OPENID_URL", &mut self.url);
+        environment.set("OPENID_CLIENT_ID", &mut self.client_id);
+        environment.set_optional("OPENID_CLIENT_SECRET", &mut self.client_secret);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        problems.require_url("admission.openid.url", "OPENID_URL", &self.url);
+        problems.require("admission.openid.client_id", "OPENID_CLIENT_ID", &self.client_id);
+    }
+}
+
+/// the [admission.webhooks] section
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// how many times to try delivering each event (WEBHOOK_MAX_ATTEMPTS)
+    pub max_attempts: u32,
+
+    /// how long to wait before the first retry, doubling for each one after
+    /// (WEBHOOK_INITIAL_DELAY_SECONDS)
+    pub initial_delay_seconds: u64
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_delay_seconds: DEFAULT_INITIAL_DELAY.as_secs()
+        }
+    }
+}
+
+impl Settings for WebhookConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set("WEBHOOK_MAX_ATTEMPTS", &mut self.max_attempts);
+        environment.set("WEBHOOK_INITIAL_DELAY_SECONDS", &mut self.initial_delay_seconds);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        if !(1..=MAX_ATTEMPTS).contains(&self.max_attempts) {
+            problems.add(format!("admission.webhooks.max_attempts must be between 1 and {}", MAX_ATTEMPTS));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use common::config::parse;
+
+    use super::*;
+
+    fn environment(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'static {
+        let vars: HashMap<String, String> = vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    const REQUIRED: [(&str, &str); 5] = [
+        ("JWT_SECRET", "secret"),
+        ("TIBERIUS_USERNAME", "user"),
+        ("TIBERIUS_PASSWORD", "password"),
+        ("OPENID_URL", "https://example.com/"),
+        ("OPENID_CLIENT_ID", "client")
+    ];
+
+    #[test]
+    fn parse_given_nothing_reports_every_missing_setting() {
+        let result = parse::<Config, _>("", environment(&[]));
+
+        let message = result.unwrap_err().to_string();
+        for (name, _) in REQUIRED {
+            assert!(message.contains(name), "{} should be reported in {}", name, message);
+        }
+    }
+
+    #[test]
+    fn parse_given_environment_overrides_file() {
+        let file = "
+            [admission]
+            port = 9000
+            default_strategy = \"round-robin\"
+
+            [admission.database]
+            name = \"OtherDB\"
+        ";
+        let mut vars = REQUIRED.to_vec();
+        vars.push(("ADMISSION_PORT", "9100"));
+
+        let config = parse::<Config, _>(file, environment(&vars)).unwrap().admission;
+
+        assert_eq!(9100, config.port);
+        assert_eq!(AdmissionStrategyKind::RoundRobin, config.default_strategy);
+        assert_eq!("OtherDB", config.database.name);
+        assert_eq!("secret", config.jwt_secret);
+    }
+
+    #[test]
+    fn parse_given_invalid_overrides_reports_all_of_them() {
+        let mut vars = REQUIRED.to_vec();
+        vars.push(("ADMISSION_PORT", "eighty"));
+        vars.push(("ADMISSION_SCHEDULE", "every tuesday"));
+
+        let message = parse::<Config, _>("", environment(&vars)).unwrap_err().to_string();
+
+        assert!(message.contains("ADMISSION_PORT"));
+        assert!(message.contains("admission.schedule"));
+    }
+
+    #[test]
+    fn parse_given_too_many_webhook_attempts_reports_it() {
+        let mut vars = REQUIRED.to_vec();
+        vars.push(("WEBHOOK_MAX_ATTEMPTS", "64"));
+
+        let message = parse::<Config, _>("", environment(&vars)).unwrap_err().to_string();
+
+        assert!(message.contains("admission.webhooks.max_attempts"));
+    }
+}
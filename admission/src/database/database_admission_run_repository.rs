@@ -0,0 +1,111 @@
+// Implements AdmissionRunRepository for an MSSQL database.
+
+use async_trait::async_trait;
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use tiberius::ExecuteResult;
+
+use crate::admission_scheduler::{AdmissionRunRepository, AdmissionRun, ScheduleError};
+
+use super::helpers;
+
+pub struct DatabaseAdmissionRunRepository {
+    pool: Pool<ConnectionManager> // internally uses an Arc
+}
+
+impl DatabaseAdmissionRunRepository {
+    pub fn new(pool: Pool<ConnectionManager>) -> Self {
+        Self {
+            pool
+        }
+    }
+
+    pub async fn setup(&self) -> Result<ExecuteResult, ScheduleError> {
+        let q = "
+            IF OBJECT_ID(N'rust.Admission_runs', N'U') IS NOT NULL
+                DROP TABLE rust.Admission_runs;
+
+            CREATE TABLE rust.Admission_runs (
+                RunID uniqueidentifier PRIMARY KEY NOT NULL,
+                StartedAt datetimeoffset NOT NULL,
+                FinishedAt datetimeoffset NOT NULL,
+                Admitted int NOT NULL,
+                Waitlisted int NOT NULL,
+                Error nvarchar(max), -- null if the run succeeded
+                INDEX IX_Admission_runs_StartedAt (StartedAt)
+            );
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(ScheduleError::repository)?;
+
+        let result = conn.execute(q, &[])
+            .await
+            .map_err(ScheduleError::repository)?;
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl AdmissionRunRepository for DatabaseAdmissionRunRepository {
+    async fn store_run(&self, run: &AdmissionRun) -> Result<(), ScheduleError> {
+        let q = "
+            INSERT INTO rust.Admission_runs (RunID, StartedAt, FinishedAt, Admitted, Waitlisted, Error)
+            VALUES (@P1, @P2, @P3, @P4, @P5, @P6);
+        ";
+
+        let admitted = i32::try_from(run.admitted())
+            .map_err(ScheduleError::repository)?;
+        let waitlisted = i32::try_from(run.waitlisted())
+            .map_err(ScheduleError::repository)?;
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(ScheduleError::repository)?;
+
+        conn.execute(q, &[&run.id(), &run.started_at(), &run.finished_at(), &admitted, &waitlisted, &run.error()])
+            .await
+            .map_err(ScheduleError::repository)?;
+
+        Ok(())
+    }
+
+    async fn get_recent_runs(&self, limit: usize) -> Result<Vec<AdmissionRun>, ScheduleError> {
+        let q = "
+            SELECT TOP (@P1) RunID, StartedAt, FinishedAt, Admitted, Waitlisted, Error
+              FROM rust.Admission_runs
+             ORDER BY StartedAt DESC;
+        ";
+
+        let limit = i32::try_from(limit)
+            .map_err(ScheduleError::repository)?;
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(ScheduleError::repository)?;
+
+        let result = conn.query(q, &[&limit])
+            .await
+            .map_err(ScheduleError::repository)?;
+
+        let runs = helpers::map(
+            result,
+            |row| {
+                let admitted: i32 = row.get("Admitted").expect("Admitted cannot be null");
+                let waitlisted: i32 = row.get("Waitlisted").expect("Waitlisted cannot be null");
+                AdmissionRun::restore(
+                    row.get("RunID").expect("RunID cannot be null"),
+                    row.get("StartedAt").expect("StartedAt cannot be null"),
+                    row.get("FinishedAt").expect("FinishedAt cannot be null"),
+                    admitted.try_into().expect("Admitted should not be negative"),
+                    waitlisted.try_into().expect("Waitlisted should not be negative"),
+                    row.get::<&str, &str>("Error").map(String::from)
+                )
+            })
+            .await;
+
+        Ok(runs)
+    }
+}
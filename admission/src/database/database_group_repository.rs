@@ -17,7 +17,7 @@ impl DatabaseGroupRepository {
         }
     }
 
-    pub async fn setup(&mut self) -> Result<(), UserError> {
+    pub async fn setup(&self) -> Result<(), UserError> {
         let q = "
             IF OBJECT_ID(N'rust.EmailGroup', N'U') IS NOT NULL
                 DROP TABLE rust.EmailGroup;
@@ -51,7 +51,7 @@ impl DatabaseGroupRepository {
 
 #[async_trait]
 impl GroupRepository for DatabaseGroupRepository {
-    async fn add_email_to_group(&mut self, email: &str, group: &str) -> Result<(), UserError> {
+    async fn add_email_to_group(&self, email: &str, group: &str) -> Result<(), UserError> {
         let q = "
             INSERT INTO rust.EmailGroup (Email, GroupName)
             VALUES (@P1, @P2);
@@ -69,7 +69,7 @@ impl GroupRepository for DatabaseGroupRepository {
         Ok(())
     }
 
-    async fn get_groups_by_email(&mut self, email: &str) -> Result<Vec<String>, UserError> {
+    async fn get_groups_by_email(&self, email: &str) -> Result<Vec<String>, UserError> {
         let q = "
             SELECT GroupName
               FROM rust.EmailGroup
@@ -6,12 +6,16 @@
 
 use std::{collections::HashMap, sync::Arc};
 
+use async_stream::try_stream;
 use async_trait::async_trait;
 use bb8::Pool;
+use chrono::{DateTime, Utc};
 use bb8_tiberius::ConnectionManager;
+use common::patient::{Patient, Priority};
+use futures_util::{stream::LocalBoxStream, TryStreamExt};
 use tiberius::ExecuteResult;
 
-use crate::{patient_services::PatientRepository, hospital_services::{HospitalRepository, RepositoryError}};
+use crate::{hospital_services::{HospitalRepository, HospitalEvent, RepositoryError, HospitalQuery, HospitalSort, RosterLine, RelocationPolicy, CLOSED_DISCHARGE_REASON}, pagination::{Page, Cursor}};
 use common::hospital::Hospital;
 
 use super::{database_patient_repository::DatabasePatientRepository, helpers};
@@ -32,25 +36,46 @@ impl DatabaseHospitalRepository {
         }
     }
 
-    pub async fn setup(&mut self) -> Result<ExecuteResult, RepositoryError> {
+    pub async fn setup(&self) -> Result<ExecuteResult, RepositoryError> {
         let content = "
+            IF OBJECT_ID(N'rust.Hospital_events', N'U') IS NOT NULL
+                DROP TABLE rust.Hospital_events;
+
             IF OBJECT_ID(N'rust.Hospitals', N'U') IS NOT NULL
                 DROP TABLE rust.Hospitals;
             
             CREATE TABLE rust.Hospitals (
                 HospitalID int IDENTITY(1, 1) PRIMARY KEY NOT NULL,
-                Name varchar(16) NOT NULL
+                Name varchar(16) NOT NULL,
+                Capacity int, -- if null, the hospital has no bed limit
+                ClosedAt datetimeoffset, -- null while the hospital is open
+                RowVersion rowversion NOT NULL, -- changes on every write
+
+                -- closed hospitals keep their names, so discharge records stay unambiguous
+                CONSTRAINT UQ_Hospitals_Name UNIQUE (Name)
+            );
+
+            -- no foreign key to rust.Hospitals, as the log must outlive the rows it describes
+            CREATE TABLE rust.Hospital_events (
+                EventID bigint IDENTITY(1, 1) PRIMARY KEY NOT NULL,
+                HospitalID int,
+                Kind varchar(16) NOT NULL,
+                Name varchar(16) NOT NULL,
+                PreviousName varchar(16), -- null unless renamed
+                Actor nvarchar(320) NOT NULL, -- long enough for any email address
+                OccurredAt datetimeoffset NOT NULL,
+                INDEX IX_Hospital_events_HospitalID (HospitalID)
             );
             
             SET IDENTITY_INSERT rust.Hospitals ON; -- allow script to set hospital IDs
             
-            INSERT INTO rust.Hospitals (HospitalID, Name)
+            INSERT INTO rust.Hospitals (HospitalID, Name, Capacity)
             VALUES
-                (1, 'Atascadero'),
-                (2, 'Coalinga'),
-                (3, 'Metropolitan'),
-                (4, 'Napa'),
-                (5, 'Patton');
+                (1, 'Atascadero', 5),
+                (2, 'Coalinga', 3),
+                (3, 'Metropolitan', 4),
+                (4, 'Napa', 5),
+                (5, 'Patton', 2);
             
             SET IDENTITY_INSERT rust.Hospitals OFF;
         ";
@@ -65,24 +90,90 @@ impl DatabaseHospitalRepository {
         
         Ok(r)
     }
+
+    /// Called when an update to a patient in a hospital which was guarded by
+    /// the patient's version changed nothing. If the patient is still in that
+    /// hospital, only a newer version could have stopped the update.
+    async fn check_patient_version(&self, patient_id: uuid::Uuid, hospital_name: &str, version: Option<u64>) -> Result<(), RepositoryError> {
+        let q = "
+            SELECT COUNT(*)
+              FROM rust.Patients
+             WHERE PatientID = @P1
+               AND HospitalID = (
+                   SELECT HospitalID
+                     FROM rust.Hospitals
+                    WHERE UPPER(Name) = @P2
+               );
+        ";
+
+        if version.is_none() {
+            return Ok(());
+        }
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(RepositoryError::other)?;
+
+        let count: i32 = conn.query(q, &[&patient_id, &hospital_name.to_uppercase()])
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .into_row()
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .and_then(|row| row.get(0))
+            .unwrap_or(0);
+        if count > 0 {
+            return Err(RepositoryError::StaleVersion(patient_id.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// loads every patient admitted in the given rows with one query, rather
+    /// than a query per patient
+    async fn admitted_patients(&self, rows: &[HospitalPatientMapping]) -> Result<HashMap<uuid::Uuid, Patient>, RepositoryError> {
+        let ids = rows.iter()
+            .filter_map(|row| row.patient_id)
+            .collect();
+        self.patients.get_patients_by_ids(ids)
+            .await
+            .map_err(RepositoryError::other)
+    }
 }
 
 #[derive(Debug)]
 struct HospitalPatientMapping {
     hospital_id: i32,
     hospital_name: String,
-    patient_id: Option<uuid::Uuid>
+    capacity: Option<i32>,
+    patient_id: Option<uuid::Uuid>,
+    version: Option<u64>
+}
+
+impl HospitalPatientMapping {
+    fn to_hospital(&self) -> Hospital {
+        let mut h = Hospital::new(&self.hospital_name)
+            .with_id(self.hospital_id.try_into().unwrap());
+        if let Some(capacity) = self.capacity {
+            h = h.with_capacity(capacity.try_into().unwrap_or(0));
+        }
+        if let Some(version) = self.version {
+            h = h.with_version(version);
+        }
+        h
+    }
 }
 
 #[async_trait]
 impl HospitalRepository for DatabaseHospitalRepository {
-    async fn get_all_hospitals(&mut self) -> Result<Vec<Hospital>, RepositoryError> {
+    async fn get_all_hospitals(&self) -> Result<Vec<Hospital>, RepositoryError> {
         let q = "
-            SELECT h.HospitalID 'Hospital ID', h.Name 'Hospital Name', p.PatientID 'Patient ID'
+            SELECT h.HospitalID 'Hospital ID', h.Name 'Hospital Name', h.Capacity 'Capacity', p.PatientID 'Patient ID', CAST(h.RowVersion AS bigint) 'Row Version'
             FROM rust.Hospitals as h
                  LEFT JOIN -- include hospitals with no patients
                  rust.Patients as p
                  ON h.HospitalID = p.HospitalID
+             WHERE h.ClosedAt IS NULL
             ;
         ";
 
@@ -100,36 +191,131 @@ impl HospitalRepository for DatabaseHospitalRepository {
             |row| HospitalPatientMapping {
                 hospital_id: row.get(0).expect("hospital ID should be non-null"),
                 hospital_name: row.get::<&str, usize>(1).map(String::from).expect("hospital name should be non-null"),
-                patient_id: row.get(2)
+                capacity: row.get(2),
+                patient_id: row.get(3),
+                version: helpers::row_version(&row)
             })
             .await;
 
+        drop(conn);
+
+        let mut patients = self.admitted_patients(&rows).await?;
         let mut hm: HashMap<i32, Hospital> = HashMap::new();
         for row in rows {
             let e = hm.entry(row.hospital_id)
-                .or_insert_with(|| Hospital::new(&row.hospital_name).with_id(row.hospital_id.try_into().unwrap()));
+                .or_insert_with(|| row.to_hospital());
             
-            if let Some(id) = row.patient_id {
-                let p = self.patients.get_patient_by_id(id)
-                    .await
-                    .map_err(RepositoryError::other)?
-                    .expect("patient should exist for this ID")
-                    .admit_to(&row.hospital_name);
-                e.add_patient(p);
+            // skip patients discharged since the hospitals were read
+            if let Some(p) = row.patient_id.and_then(|id| patients.remove(&id)) {
+                e.add_patient(p.admit_to(&row.hospital_name));
             }
         }
 
         Ok(hm.values().map(|href| href.to_owned()).collect())
     }
 
-    async fn get_hospital(&mut self, name: &str) -> Result<Option<Hospital>, RepositoryError> {
+    async fn get_hospitals(&self, query: &HospitalQuery) -> Result<Page<Hospital>, RepositoryError> {
+        let sort = query.sort();
+        let sort_key = match sort {
+            HospitalSort::Name | HospitalSort::NameDescending => "h.Name",
+            HospitalSort::Capacity | HospitalSort::CapacityDescending => "ISNULL(h.Capacity, 2147483647)"
+        };
+        let direction = if sort.is_descending() { "DESC" } else { "ASC" };
+
+        // select the page of hospitals first, so patients don't count towards
+        // the page size
+        let q = format!("
+            WITH page AS (
+                SELECT TOP (@P1) h.HospitalID, h.Name, h.Capacity, h.RowVersion, {key} AS SortKey
+                  FROM rust.Hospitals AS h
+                 WHERE h.ClosedAt IS NULL
+                   AND (@P2 IS NULL OR UPPER(h.Name) LIKE @P2 ESCAPE '\\')
+                   AND {after}
+                 ORDER BY {key} {direction}, h.HospitalID {direction}
+            )
+            SELECT page.HospitalID 'Hospital ID', page.Name 'Hospital Name', page.Capacity 'Capacity', p.PatientID 'Patient ID', CAST(page.RowVersion AS bigint) 'Row Version'
+              FROM page
+                   LEFT JOIN
+                   rust.Patients AS p
+                   ON page.HospitalID = p.HospitalID AND @P5 = 1
+             ORDER BY page.SortKey {direction}, page.HospitalID {direction}
+            ;
+        ", key = sort_key, direction = direction, after = helpers::keyset_condition(&[sort_key, "h.HospitalID"], 3, sort.is_descending()));
+
+        // cursors are the sort key, then the hospital ID
+        let (after_key, after_id) = match query.cursor() {
+            Some(cursor) => match cursor.values() {
+                [key, id] => (Some(key.to_owned()), Some(id.parse::<i32>().map_err(|_| RepositoryError::InvalidCursor(cursor.encode()))?)),
+                _ => return Err(RepositoryError::InvalidCursor(cursor.encode()))
+            },
+            None => (None, None)
+        };
+        let after_capacity: Option<i32> = match (sort, after_key.as_ref()) {
+            (HospitalSort::Capacity | HospitalSort::CapacityDescending, Some(key)) => Some(key.parse()
+                .map_err(|_| RepositoryError::InvalidCursor(key.to_owned()))?),
+            _ => None
+        };
+
+        let limit = query.limit();
+        let fetch = (limit + 1) as i32; // one extra to check for another page
+        let name_filter = query.name_filter().map(|name| helpers::contains_pattern(&name));
+        let include_patients = query.include_patients() as i32;
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(RepositoryError::other)?;
+
+        let query_result = match sort {
+            HospitalSort::Name | HospitalSort::NameDescending => conn.query(&q, &[&fetch, &name_filter, &after_key, &after_id, &include_patients]).await,
+            HospitalSort::Capacity | HospitalSort::CapacityDescending => conn.query(&q, &[&fetch, &name_filter, &after_capacity, &after_id, &include_patients]).await
+        }.map_err(RepositoryError::tiberius)?;
+
+        let rows = helpers::map(
+            query_result,
+            |row| HospitalPatientMapping {
+                hospital_id: row.get(0).expect("hospital ID should be non-null"),
+                hospital_name: row.get::<&str, usize>(1).map(String::from).expect("hospital name should be non-null"),
+                capacity: row.get(2),
+                patient_id: row.get(3),
+                version: helpers::row_version(&row)
+            })
+            .await;
+        drop(conn);
+
+        // keep the order hospitals first appear in, as the query sorts them
+        let mut patients = self.admitted_patients(&rows).await?;
+        let mut hospitals: Vec<Hospital> = Vec::new();
+        for row in rows {
+            if hospitals.last().and_then(Hospital::id) != Some(row.hospital_id as u32) {
+                hospitals.push(row.to_hospital());
+            }
+            if let Some(p) = row.patient_id.and_then(|id| patients.remove(&id)) {
+                hospitals.last_mut()
+                    .expect("hospital was just added")
+                    .add_patient(p.admit_to(&row.hospital_name));
+            }
+        }
+
+        Ok(Page::from_rows(hospitals, limit, |h| {
+            let key = match sort {
+                HospitalSort::Name | HospitalSort::NameDescending => h.name(),
+                HospitalSort::Capacity | HospitalSort::CapacityDescending => h.capacity()
+                    .map_or(i32::MAX, |c| i32::try_from(c).unwrap_or(i32::MAX))
+                    .to_string()
+            };
+            Cursor::new(&[key, h.id().unwrap_or_default().to_string()])
+        }))
+    }
+
+    async fn get_hospital(&self, name: &str) -> Result<Option<Hospital>, RepositoryError> {
         let q = "
-            SELECT h.HospitalID 'Hospital ID', h.Name 'Hospital Name', p.PatientID 'Patient ID'
+            SELECT h.HospitalID 'Hospital ID', h.Name 'Hospital Name', h.Capacity 'Capacity', p.PatientID 'Patient ID', CAST(h.RowVersion AS bigint) 'Row Version'
             FROM rust.Hospitals as h
                  LEFT JOIN -- include hospitals with no patients
                  rust.Patients as p
                  ON h.HospitalID = p.HospitalID
              WHERE UPPER(h.Name) = @P1
+               AND h.ClosedAt IS NULL
             ;
         ";
 
@@ -146,7 +332,9 @@ impl HospitalRepository for DatabaseHospitalRepository {
             |row| HospitalPatientMapping {
                 hospital_id: row.get(0).expect("hospital ID should be non-null"),
                 hospital_name: row.get::<&str, usize>(1).map(String::from).expect("hospital name should be non-null"),
-                patient_id: row.get(2)
+                capacity: row.get(2),
+                patient_id: row.get(3),
+                version: helpers::row_version(&row)
             })
             .await;
         
@@ -155,44 +343,417 @@ impl HospitalRepository for DatabaseHospitalRepository {
             return Ok(None);
         }
 
-        let mut h = Hospital::new(&rows[0].hospital_name).with_id(rows[0].hospital_id.try_into().unwrap());
-        for id in rows.iter().filter_map(|row| row.patient_id) {
-            let p = self.patients.get_patient_by_id(id)
-                .await
-                .map_err(RepositoryError::other)?
-                .expect("patient should exist for this ID")
-                .admit_to(&h.name());
-            h.add_patient(p);
+        drop(client);
+
+        let mut patients = self.admitted_patients(&rows).await?;
+        let mut h = rows[0].to_hospital();
+        for p in rows.iter().filter_map(|row| row.patient_id.and_then(|id| patients.remove(&id))) {
+            h.add_patient(p.admit_to(&h.name()));
         }
         
         Ok(Some(h))
     }
 
-    async fn remove_patient_from_hospital(&mut self, patient_id: uuid::Uuid, hospital_name: &str) -> Result<Hospital, RepositoryError> {
+    fn stream_roster(&self, name_filter: Option<String>, hospital_name: Option<String>) -> LocalBoxStream<'static, Result<RosterLine, RepositoryError>> {
+        let exists = "
+            SELECT COUNT(*)
+              FROM rust.Hospitals
+             WHERE UPPER(Name) = @P1
+               AND ClosedAt IS NULL;
+        ";
+        let q = "
+            SELECT h.Name 'Hospital Name', p.PatientID 'Patient ID', p.Name 'Patient Name', p.Priority 'Priority', p.AdmittedAt 'Admitted At'
+              FROM rust.Hospitals AS h
+                   JOIN
+                   rust.Patients AS p
+                   ON h.HospitalID = p.HospitalID
+             WHERE h.ClosedAt IS NULL
+               AND (@P1 IS NULL OR UPPER(h.Name) LIKE @P1 ESCAPE '\\')
+               AND (@P2 IS NULL OR UPPER(h.Name) = @P2)
+             ORDER BY h.Name, p.Name, p.PatientID;
+        ";
+
+        // the stream outlives this repository's borrow, so it needs its own
+        // connection rather than one borrowed from the pool
+        let pool = self.pool.clone();
+        let name_filter = name_filter.map(|name| helpers::contains_pattern(&name));
+        let upper_name = hospital_name.as_ref().map(|name| name.to_uppercase());
+
+        Box::pin(try_stream! {
+            let mut conn = pool.get_owned()
+                .await
+                .map_err(RepositoryError::other)?;
+
+            if let (Some(name), Some(upper_name)) = (&hospital_name, &upper_name) {
+                let count: i32 = conn.query(exists, &[upper_name])
+                    .await
+                    .map_err(RepositoryError::tiberius)?
+                    .into_row()
+                    .await
+                    .map_err(RepositoryError::tiberius)?
+                    .and_then(|row| row.get(0))
+                    .unwrap_or(0);
+                if count == 0 {
+                    Err(RepositoryError::invalid_hospital_name(name))?;
+                }
+            }
+
+            let mut rows = conn.query(q, &[&name_filter, &upper_name])
+                .await
+                .map_err(RepositoryError::tiberius)?
+                .into_row_stream();
+            while let Some(row) = rows.try_next().await.map_err(RepositoryError::tiberius)? {
+                yield RosterLine::new(
+                    row.get("Hospital Name").expect("hospital name should be non-null"),
+                    row.get("Patient ID").expect("patient ID should be non-null"),
+                    row.get("Patient Name").expect("patient name should be non-null"),
+                    row.get::<u8, &str>("Priority").and_then(Priority::from_rank).unwrap_or_default(),
+                    row.get("Admitted At")
+                );
+            }
+        })
+    }
+
+    async fn discharge_patient(&self, patient_id: uuid::Uuid, hospital_name: &str, reason: &str, discharged_at: DateTime<Utc>, version: Option<u64>) -> Result<Hospital, RepositoryError> {
+        // keep the patient, but take them off the hospital's roster
         let q = "
-            DELETE FROM rust.Patients
+            UPDATE rust.Patients
+               SET DischargedFromHospitalID = HospitalID,
+                   HospitalID = NULL,
+                   DischargeReason = @P3,
+                   DischargedAt = @P4
              WHERE PatientID = @P1
                AND HospitalID = (
                    SELECT HospitalID
                      FROM rust.Hospitals
                     WHERE UPPER(Name) = @P2
                )
+               AND (@P5 IS NULL OR CAST(RowVersion AS bigint) = @P5)
             ;
         ";
         
-        {
+        let result = {
             let mut conn = self.pool.get()
                 .await
                 .map_err(RepositoryError::other)?;
 
-            let _result = conn.execute(q, &[&patient_id, &hospital_name.to_uppercase()])
+            conn.execute(q, &[&patient_id, &hospital_name.to_uppercase(), &reason, &discharged_at, &helpers::version_param(version)])
                 .await
-                .map_err(RepositoryError::tiberius)?;
+                .map_err(RepositoryError::tiberius)?
             // drops borrow of self here
+        };
+        let discharged = result.total() > 0;
+        if !discharged {
+            self.check_patient_version(patient_id, hospital_name, version).await?;
         }
 
-        self.get_hospital(hospital_name)
+        let hospital = self.get_hospital(hospital_name)
             .await?
-            .ok_or_else(|| RepositoryError::invalid_hospital_name(hospital_name))
+            .ok_or_else(|| RepositoryError::invalid_hospital_name(hospital_name))?;
+        if !discharged {
+            return Err(RepositoryError::PatientNotAdmitted(patient_id, hospital.name()));
+        }
+        Ok(hospital)
     }
-}
\ No newline at end of file
+
+    async fn transfer_patient(&self, patient_id: uuid::Uuid, from: &str, to: &str, version: Option<u64>) -> Result<Hospital, RepositoryError> {
+        let q = "
+            UPDATE rust.Patients
+               SET HospitalID = (
+                   SELECT HospitalID
+                     FROM rust.Hospitals
+                    WHERE UPPER(Name) = @P3
+               )
+             WHERE PatientID = @P1
+               AND HospitalID = (
+                   SELECT HospitalID
+                     FROM rust.Hospitals
+                    WHERE UPPER(Name) = @P2
+               )
+               AND (@P4 IS NULL OR CAST(RowVersion AS bigint) = @P4)
+               AND EXISTS ( -- locks the target, so concurrent transfers can't both take its last bed
+                   SELECT 1
+                     FROM rust.Hospitals AS h WITH (UPDLOCK, HOLDLOCK)
+                    WHERE UPPER(h.Name) = @P3
+                      AND h.ClosedAt IS NULL
+                      AND (h.Capacity IS NULL OR h.Capacity > (
+                          SELECT COUNT(*)
+                            FROM rust.Patients AS p
+                           WHERE p.HospitalID = h.HospitalID
+                      ))
+               )
+            ;
+        ";
+
+        let result = {
+            let mut conn = self.pool.get()
+                .await
+                .map_err(RepositoryError::other)?;
+
+            conn.execute(q, &[&patient_id, &from.to_uppercase(), &to.to_uppercase(), &helpers::version_param(version)])
+                .await
+                .map_err(RepositoryError::tiberius)?
+        };
+        let moved = result.total() > 0;
+        if !moved {
+            self.check_patient_version(patient_id, from, version).await?;
+        }
+
+        let target = self.get_hospital(to)
+            .await?
+            .ok_or_else(|| RepositoryError::invalid_hospital_name(to))?;
+        if !moved {
+            // with the right version and a free bed, only the patient leaving
+            // the source hospital first could have stopped the transfer
+            if target.has_room() {
+                return Err(RepositoryError::PatientNotAdmitted(patient_id, from.to_owned()));
+            }
+            return Err(RepositoryError::HospitalFull(target.name()));
+        }
+        Ok(target)
+    }
+
+    async fn create_hospital(&self, name: &str, capacity: Option<u32>) -> Result<Hospital, RepositoryError> {
+        let exists = "
+            SELECT COUNT(*)
+              FROM rust.Hospitals
+             WHERE UPPER(Name) = @P1;
+        ";
+        let insert = "
+            INSERT INTO rust.Hospitals (Name, Capacity)
+            OUTPUT INSERTED.HospitalID
+            VALUES (@P1, @P2);
+        ";
+        let stored_capacity = capacity.map(|c| i32::try_from(c).unwrap_or(i32::MAX));
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(RepositoryError::other)?;
+
+        let count: i32 = conn.query(exists, &[&name.to_uppercase()])
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .into_row()
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .and_then(|row| row.get(0))
+            .unwrap_or(0);
+        if count > 0 {
+            return Err(RepositoryError::DuplicateHospitalName(name.to_owned()));
+        }
+
+        // the check above can race another request, so the UNIQUE constraint
+        // has the final say
+        let id: i32 = conn.query(insert, &[&name, &stored_capacity])
+            .await
+            .map_err(|e| duplicate_name_or(e, name))?
+            .into_row()
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .and_then(|row| row.get(0))
+            .ok_or_else(|| RepositoryError::other("inserted hospital should have an ID"))?;
+
+        let h = Hospital::new(name)
+            .with_id(id.try_into().unwrap());
+        Ok(match capacity {
+            Some(capacity) => h.with_capacity(capacity),
+            None => h
+        })
+    }
+
+    async fn rename_hospital(&self, name: &str, new_name: &str, version: Option<u64>) -> Result<Hospital, RepositoryError> {
+        // a hospital may change the case of its own name
+        let taken = "
+            SELECT COUNT(*)
+              FROM rust.Hospitals
+             WHERE UPPER(Name) = @P2
+               AND UPPER(Name) <> @P1;
+        ";
+        let rename = "
+            UPDATE rust.Hospitals
+               SET Name = @P2
+             WHERE UPPER(Name) = @P1
+               AND ClosedAt IS NULL
+               AND (@P3 IS NULL OR CAST(RowVersion AS bigint) = @P3);
+        ";
+        let exists = "
+            SELECT COUNT(*)
+              FROM rust.Hospitals
+             WHERE UPPER(Name) = @P1
+               AND ClosedAt IS NULL;
+        ";
+
+        {
+            let mut conn = self.pool.get()
+                .await
+                .map_err(RepositoryError::other)?;
+
+            let count: i32 = conn.query(taken, &[&name.to_uppercase(), &new_name.to_uppercase()])
+                .await
+                .map_err(RepositoryError::tiberius)?
+                .into_row()
+                .await
+                .map_err(RepositoryError::tiberius)?
+                .and_then(|row| row.get(0))
+                .unwrap_or(0);
+            if count > 0 {
+                return Err(RepositoryError::DuplicateHospitalName(new_name.to_owned()));
+            }
+
+            let result = conn.execute(rename, &[&name.to_uppercase(), &new_name, &helpers::version_param(version)])
+                .await
+                .map_err(|e| duplicate_name_or(e, new_name))?;
+            if result.total() == 0 {
+                // either there's no such hospital, or it changed since it was read
+                let count: i32 = conn.query(exists, &[&name.to_uppercase()])
+                    .await
+                    .map_err(RepositoryError::tiberius)?
+                    .into_row()
+                    .await
+                    .map_err(RepositoryError::tiberius)?
+                    .and_then(|row| row.get(0))
+                    .unwrap_or(0);
+                return Err(match count {
+                    0 => RepositoryError::invalid_hospital_name(name),
+                    _ => RepositoryError::StaleVersion(name.to_owned())
+                });
+            }
+        }
+
+        self.get_hospital(new_name)
+            .await?
+            .ok_or_else(|| RepositoryError::invalid_hospital_name(new_name))
+    }
+
+    async fn close_hospital(&self, name: &str, relocation: Option<RelocationPolicy>, patient_ids: &[uuid::Uuid], closed_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+        // Relocates only the patients the service read, so it can record each
+        // of them, then checks for patients as it closes, so none can be
+        // admitted or transferred in after the service checked. XACT_ABORT
+        // rolls the whole batch back on any error, and the batch rolls itself
+        // back if anyone is left, so the hospital is never half emptied.
+        let q = "
+            SET XACT_ABORT ON;
+            BEGIN TRANSACTION;
+
+            DECLARE @HospitalID int = (
+                SELECT HospitalID
+                  FROM rust.Hospitals WITH (UPDLOCK, HOLDLOCK)
+                 WHERE UPPER(Name) = @P1
+                   AND ClosedAt IS NULL
+            );
+
+            -- keep WaitlistedAt, so they return to their original place in line
+            IF @P3 = 'waitlist'
+                UPDATE rust.Patients
+                   SET HospitalID = NULL,
+                       AdmittedAt = NULL
+                 WHERE HospitalID = @HospitalID
+                   AND PatientID IN (SELECT CAST(value AS uniqueidentifier) FROM OPENJSON(@P4));
+
+            IF @P3 = 'discharge'
+                UPDATE rust.Patients
+                   SET DischargedFromHospitalID = HospitalID,
+                       HospitalID = NULL,
+                       DischargeReason = @P5,
+                       DischargedAt = @P2
+                 WHERE HospitalID = @HospitalID
+                   AND PatientID IN (SELECT CAST(value AS uniqueidentifier) FROM OPENJSON(@P4));
+
+            UPDATE rust.Hospitals
+               SET ClosedAt = @P2
+             WHERE HospitalID = @HospitalID
+               AND NOT EXISTS (
+                   SELECT 1
+                     FROM rust.Patients
+                    WHERE HospitalID = @HospitalID
+               );
+
+            IF @@ROWCOUNT = 0
+            BEGIN
+                ROLLBACK TRANSACTION;
+                SELECT CAST(0 AS bit) 'Closed';
+                RETURN;
+            END;
+
+            COMMIT TRANSACTION;
+            SELECT CAST(1 AS bit) 'Closed';
+        ";
+        let why_not = "
+            SELECT h.Name, (
+                       SELECT COUNT(*)
+                         FROM rust.Patients AS p
+                        WHERE p.HospitalID = h.HospitalID
+                   )
+              FROM rust.Hospitals AS h
+             WHERE UPPER(h.Name) = @P1
+               AND h.ClosedAt IS NULL;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(RepositoryError::other)?;
+
+        let policy = relocation.map(|r| match r {
+            RelocationPolicy::Waitlist => "waitlist",
+            RelocationPolicy::Discharge => "discharge"
+        });
+        let ids = serde_json::json!(patient_ids).to_string();
+        let closed: bool = conn.query(q, &[&name.to_uppercase(), &closed_at, &policy, &ids, &CLOSED_DISCHARGE_REASON])
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .into_row()
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .and_then(|row| row.get(0))
+            .unwrap_or(false);
+        if closed {
+            return Ok(());
+        }
+
+        // either there's no such open hospital, or patients are still in it
+        let row = conn.query(why_not, &[&name.to_uppercase()])
+            .await
+            .map_err(RepositoryError::tiberius)?
+            .into_row()
+            .await
+            .map_err(RepositoryError::tiberius)?;
+        Err(match row {
+            Some(row) => RepositoryError::HospitalNotEmpty(
+                row.get::<&str, usize>(0).unwrap_or(name).to_owned(),
+                row.get::<i32, usize>(1).unwrap_or_default() as usize
+            ),
+            None => RepositoryError::invalid_hospital_name(name)
+        })
+    }
+
+    async fn append_event(&self, event: &HospitalEvent) -> Result<(), RepositoryError> {
+        let q = "
+            INSERT INTO rust.Hospital_events (HospitalID, Kind, Name, PreviousName, Actor, OccurredAt)
+            VALUES (@P1, @P2, @P3, @P4, @P5, @P6);
+        ";
+
+        let hospital_id = event.hospital_id()
+            .map(|id| i32::try_from(id).map_err(RepositoryError::other))
+            .transpose()?;
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(RepositoryError::other)?;
+
+        conn.execute(q, &[&hospital_id, &event.kind().to_string(), &event.hospital(), &event.previous_name(), &event.actor(), &event.occurred_at()])
+            .await
+            .map_err(RepositoryError::tiberius)?;
+
+        Ok(())
+    }
+}
+
+/// reports a unique violation as the given name being taken, as hospital names
+/// are the only unique column written
+fn duplicate_name_or(error: tiberius::error::Error, name: &str) -> RepositoryError {
+    if helpers::is_unique_violation(&error) {
+        RepositoryError::DuplicateHospitalName(name.to_owned())
+    } else {
+        RepositoryError::tiberius(error)
+    }
+}
@@ -0,0 +1,131 @@
+// Implements IdempotencyRepository for an MSSQL database.
+
+use async_trait::async_trait;
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use chrono::{DateTime, Utc};
+use tiberius::ExecuteResult;
+
+use crate::idempotency_services::{IdempotencyRepository, StoredResponse, IdempotencyError};
+
+pub struct DatabaseIdempotencyRepository {
+    pool: Pool<ConnectionManager> // internally uses an Arc
+}
+
+impl DatabaseIdempotencyRepository {
+    pub fn new(pool: Pool<ConnectionManager>) -> Self {
+        Self {
+            pool
+        }
+    }
+
+    pub async fn setup(&self) -> Result<ExecuteResult, IdempotencyError> {
+        // keys are only unique per user, so one user cannot replay another's
+        // response by guessing their key
+        let q = "
+            IF OBJECT_ID(N'rust.Idempotency_keys', N'U') IS NOT NULL
+                DROP TABLE rust.Idempotency_keys;
+
+            CREATE TABLE rust.Idempotency_keys (
+                IdempotencyKey varchar(64) NOT NULL,
+                Actor varchar(64) NOT NULL,
+                Endpoint varchar(64) NOT NULL,
+                Fingerprint char(64) NOT NULL,
+                StatusCode int NOT NULL,
+                Body nvarchar(max) NOT NULL,
+                CreatedAt datetimeoffset NOT NULL,
+                CONSTRAINT PK_Idempotency_keys PRIMARY KEY (Actor, IdempotencyKey),
+                INDEX IX_Idempotency_keys_CreatedAt (CreatedAt)
+            );
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        let result = conn.execute(q, &[])
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for DatabaseIdempotencyRepository {
+    async fn get_response(&self, key: &str, actor: &str) -> Result<Option<StoredResponse>, IdempotencyError> {
+        let q = "
+            SELECT IdempotencyKey, Actor, Endpoint, Fingerprint, StatusCode, Body, CreatedAt
+              FROM rust.Idempotency_keys
+             WHERE Actor = @P1
+               AND IdempotencyKey = @P2;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        let result = conn.query(q, &[&actor, &key])
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        let row = result.into_row()
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        Ok(row.map(|row| {
+            let status: i32 = row.get("StatusCode").expect("StatusCode cannot be null");
+            StoredResponse::restore(
+                row.get("IdempotencyKey").expect("IdempotencyKey cannot be null"),
+                row.get("Actor").expect("Actor cannot be null"),
+                row.get("Endpoint").expect("Endpoint cannot be null"),
+                row.get("Fingerprint").expect("Fingerprint cannot be null"),
+                status.try_into().expect("StatusCode should be a valid HTTP status"),
+                row.get("Body").expect("Body cannot be null"),
+                row.get("CreatedAt").expect("CreatedAt cannot be null")
+            )
+        }))
+    }
+
+    async fn store_response(&self, response: &StoredResponse) -> Result<(), IdempotencyError> {
+        let q = "
+            INSERT INTO rust.Idempotency_keys (IdempotencyKey, Actor, Endpoint, Fingerprint, StatusCode, Body, CreatedAt)
+            VALUES (@P1, @P2, @P3, @P4, @P5, @P6, @P7);
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        conn.execute(q, &[
+                &response.key(),
+                &response.actor(),
+                &response.endpoint(),
+                &response.fingerprint(),
+                &i32::from(response.status()),
+                &response.body(),
+                &response.created_at()
+            ])
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        Ok(())
+    }
+
+    async fn delete_responses_before(&self, cutoff: DateTime<Utc>) -> Result<(), IdempotencyError> {
+        let q = "
+            DELETE FROM rust.Idempotency_keys
+             WHERE CreatedAt < @P1;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        conn.execute(q, &[&cutoff])
+            .await
+            .map_err(IdempotencyError::repository)?;
+
+        Ok(())
+    }
+}
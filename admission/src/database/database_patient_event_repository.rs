@@ -0,0 +1,140 @@
+// Implements PatientEventRepository for an MSSQL database.
+// Each event stores the before and after state of its patient as JSON, so the
+// log stays readable even after the patient's own row has changed.
+
+use async_trait::async_trait;
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use chrono::{DateTime, Utc};
+use common::patient::Patient;
+use tiberius::ExecuteResult;
+
+use crate::patient_services::{PatientEventRepository, PatientEvent, PatientError, PatientEventKind};
+
+use super::helpers;
+
+pub struct DatabasePatientEventRepository {
+    pool: Pool<ConnectionManager> // internally uses an Arc
+}
+
+impl DatabasePatientEventRepository {
+    pub fn new(pool: Pool<ConnectionManager>) -> Self {
+        Self {
+            pool
+        }
+    }
+
+    pub async fn setup(&self) -> Result<ExecuteResult, PatientError> {
+        // no foreign key to rust.Patients, as the log must outlive the rows it
+        // describes
+        let q = "
+            IF OBJECT_ID(N'rust.Patient_events', N'U') IS NOT NULL
+                DROP TABLE rust.Patient_events;
+
+            CREATE TABLE rust.Patient_events (
+                EventID bigint IDENTITY(1, 1) PRIMARY KEY NOT NULL,
+                PatientID uniqueidentifier NOT NULL,
+                Kind varchar(16) NOT NULL,
+                Actor nvarchar(320) NOT NULL, -- long enough for any email address
+                OccurredAt datetimeoffset NOT NULL,
+                BeforeJson nvarchar(max), -- null if the patient is new
+                AfterJson nvarchar(max) NOT NULL,
+                INDEX IX_Patient_events_PatientID (PatientID)
+            );
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let result = conn.execute(q, &[])
+            .await
+            .map_err(PatientError::repository)?;
+
+        Ok(result)
+    }
+}
+
+struct PatientEventMapping {
+    patient_id: uuid::Uuid,
+    kind: String,
+    actor: String,
+    occurred_at: DateTime<Utc>,
+    before: Option<String>,
+    after: String
+}
+
+impl PatientEventMapping {
+    fn to_event(&self) -> Result<PatientEvent, PatientError> {
+        let kind: PatientEventKind = self.kind.parse()
+            .map_err(|e: String| PatientError::Repository(e.into()))?;
+        let before: Option<Patient> = match self.before {
+            Some(ref json) => Some(serde_json::from_str(json).map_err(PatientError::repository)?),
+            None => None
+        };
+        let after: Patient = serde_json::from_str(&self.after)
+            .map_err(PatientError::repository)?;
+
+        Ok(PatientEvent::restore(self.patient_id, kind, &self.actor, self.occurred_at, before, after))
+    }
+}
+
+#[async_trait]
+impl PatientEventRepository for DatabasePatientEventRepository {
+    async fn append_event(&self, event: &PatientEvent) -> Result<(), PatientError> {
+        let q = "
+            INSERT INTO rust.Patient_events (PatientID, Kind, Actor, OccurredAt, BeforeJson, AfterJson)
+            VALUES (@P1, @P2, @P3, @P4, @P5, @P6);
+        ";
+
+        let before = match event.before() {
+            Some(ref patient) => Some(serde_json::to_string(patient).map_err(PatientError::repository)?),
+            None => None
+        };
+        let after = serde_json::to_string(&event.after())
+            .map_err(PatientError::repository)?;
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        conn.execute(q, &[&event.patient_id(), &event.kind().to_string(), &event.actor(), &event.occurred_at(), &before, &after])
+            .await
+            .map_err(PatientError::repository)?;
+
+        Ok(())
+    }
+
+    async fn get_events_for_patient(&self, patient_id: uuid::Uuid) -> Result<Vec<PatientEvent>, PatientError> {
+        let q = "
+            SELECT PatientID, Kind, Actor, OccurredAt, BeforeJson, AfterJson
+              FROM rust.Patient_events
+             WHERE PatientID = @P1
+             ORDER BY EventID;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let result = conn.query(q, &[&patient_id])
+            .await
+            .map_err(PatientError::repository)?;
+
+        let rows: Vec<PatientEventMapping> = helpers::map(
+            result,
+            |row| PatientEventMapping {
+                patient_id: row.get("PatientID").expect("PatientID cannot be null"),
+                kind: row.get::<&str, &str>("Kind").map(String::from).expect("Kind cannot be null"),
+                actor: row.get::<&str, &str>("Actor").map(String::from).expect("Actor cannot be null"),
+                occurred_at: row.get("OccurredAt").expect("OccurredAt cannot be null"),
+                before: row.get::<&str, &str>("BeforeJson").map(String::from),
+                after: row.get::<&str, &str>("AfterJson").map(String::from).expect("AfterJson cannot be null")
+            })
+            .await;
+
+        rows.iter()
+            .map(PatientEventMapping::to_event)
+            .collect()
+    }
+}
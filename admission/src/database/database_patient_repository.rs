@@ -1,13 +1,15 @@
-use std::collections::{HashSet, HashMap};
+use std::collections::HashMap;
 
+use async_stream::try_stream;
 use async_trait::async_trait;
 use bb8::Pool;
-use bb8_tiberius::ConnectionManager;
-use common::patient::Patient;
-use futures_util::Future;
+use chrono::{DateTime, Utc, SecondsFormat};
+use bb8_tiberius::{ConnectionManager, rt};
+use common::patient::{Patient, Priority, Discharge};
+use futures_util::{Future, stream::LocalBoxStream, TryStreamExt};
 use tiberius::ExecuteResult;
 
-use crate::patient_services::{PatientRepository, PatientError};
+use crate::{patient_services::{PatientRepository, PatientError, WaitlistQuery, WaitlistSort, WaitlistLine}, pagination::{Page, Cursor}};
 
 use super::helpers;
 
@@ -22,11 +24,14 @@ impl DatabasePatientRepository {
         }
     }
 
-    pub async fn setup<F, Fut>(&mut self, setup_hospitals: F) -> Result<ExecuteResult, PatientError>
+    pub async fn setup<F, Fut>(&self, setup_hospitals: F) -> Result<ExecuteResult, PatientError>
     where
         F: FnOnce() -> Fut,
         Fut: Future<Output = ()> {
         let q1 = "
+            IF OBJECT_ID(N'rust.Patient_preferred_hospitals', N'U') IS NOT NULL
+                DROP TABLE rust.Patient_preferred_hospitals;
+
             IF OBJECT_ID(N'rust.Patient_disallowed_hospitals', N'U') IS NOT NULL
                 DROP TABLE rust.Patient_disallowed_hospitals;
             
@@ -45,14 +50,24 @@ impl DatabasePatientRepository {
         setup_hospitals().await;
 
         let q2 = "
-            -- if HospitalID is null, patient is on the waitlist
+            -- if HospitalID, DischargedAt, and WithdrawnAt are null, patient is on the waitlist
             CREATE TABLE rust.Patients (
                 PatientID uniqueidentifier PRIMARY KEY NOT NULL,
                 Name varchar(32) NOT NULL,
                 HospitalID int,
+                Priority tinyint NOT NULL DEFAULT 2, -- 0 is urgent, 1 is high, 2 is normal
+                WaitlistedAt datetimeoffset NOT NULL DEFAULT SYSDATETIMEOFFSET(),
+                AdmittedAt datetimeoffset, -- null while on the waitlist
+                DischargedFromHospitalID int,
+                DischargeReason varchar(256),
+                DischargedAt datetimeoffset, -- null unless discharged
+                WithdrawnAt datetimeoffset, -- null unless withdrawn from the waitlist
+                RowVersion rowversion NOT NULL, -- changes on every write
                 CONSTRAINT FK_Patients_Hospitals FOREIGN KEY (HospitalID)
                     REFERENCES rust.Hospitals (HospitalID)
-                    ON DELETE CASCADE
+                    ON DELETE CASCADE,
+                CONSTRAINT FK_Patients_Discharged_Hospitals FOREIGN KEY (DischargedFromHospitalID)
+                    REFERENCES rust.Hospitals (HospitalID)
             );
 
             CREATE TABLE rust.Patient_disallowed_hospitals (
@@ -69,12 +84,28 @@ impl DatabasePatientRepository {
                     REFERENCES rust.Hospitals (HospitalID)
                     --ON DELETE CASCADE
             );
-            
-            INSERT INTO rust.Patients (PatientID, Name, HospitalID)
+
+            -- Rank orders a patient's preferences, lowest first
+            CREATE TABLE rust.Patient_preferred_hospitals (
+                PatientID uniqueidentifier NOT NULL,
+                HospitalID int NOT NULL,
+                Rank int NOT NULL,
+
+                CONSTRAINT UQ_Patient_preferred_hospitals_PatientID_HospitalID UNIQUE (PatientID, HospitalID),
+
+                CONSTRAINT FK_Patient_preferred_hospitals_Patients FOREIGN KEY (PatientID)
+                    REFERENCES rust.Patients (PatientID)
+                    ON DELETE CASCADE,
+                
+                CONSTRAINT FK_Patient_preferred_hospitals_Hospitals FOREIGN KEY (HospitalID)
+                    REFERENCES rust.Hospitals (HospitalID)
+            );
+
+            INSERT INTO rust.Patients (PatientID, Name, HospitalID, AdmittedAt)
             VALUES
-                (NEWID(), 'John Doe', 1),
-                (NEWID(), 'Jane Doe', 1),
-                (NEWID(), 'Bob Smith', 2)
+                (NEWID(), 'John Doe', 1, SYSDATETIMEOFFSET()),
+                (NEWID(), 'Jane Doe', 1, SYSDATETIMEOFFSET()),
+                (NEWID(), 'Bob Smith', 2, SYSDATETIMEOFFSET())
             ;
         ";
 
@@ -85,117 +116,113 @@ impl DatabasePatientRepository {
         Ok(result)
     }
 
-    async fn store_new_patient(&mut self, patient: &Patient) -> Result<Patient, PatientError> {
+    async fn store_new_patient(&self, patient: &Patient) -> Result<Patient, PatientError> {
         let store_me = patient
             .with_random_id()
-            .waitlisted();
-
-        let q = "
-            INSERT INTO rust.Patients (PatientID, Name)
-            VALUES (@P1, @P2);
-        ";
+            .waitlisted()
+            .with_waitlisted_at(patient.waitlisted_at().unwrap_or_else(Utc::now));
 
         let mut conn = self.pool.get()
             .await
             .map_err(PatientError::repository)?;
-        
-        conn.execute(q, &[&store_me.id().unwrap(), &store_me.name()])
+
+        insert_waitlisted_patients(&mut conn, std::slice::from_ref(&store_me))
             .await
             .map_err(PatientError::repository)?;
-
-        for disallowed_hospital in store_me.disallowed_hospitals() {
-            conn.execute("
-                INSERT INTO rust.Patient_disallowed_hospitals (PatientID, HospitalID)
-                VALUES (@P1, (
-                    SELECT HospitalID
-                      FROM rust.Hospitals
-                     WHERE Name = @P2
-                ));
-            ", &[&store_me.id().unwrap(), &disallowed_hospital])
-                .await
-                .map_err(PatientError::repository)?;
-        }
         
         Ok(store_me)
     }
 
-    async fn store_waitlisted_patient(&mut self, patient: &Patient) -> Result<Patient, PatientError> {
-        let store_me = patient.clone();
-
-        let q = "
-            INSERT INTO rust.Patients (PatientID, Name)
-            VALUES (@P1, @P2);
-        ";
+    async fn store_waitlisted_patient(&self, patient: &Patient) -> Result<Patient, PatientError> {
+        let store_me = patient.with_waitlisted_at(patient.waitlisted_at().unwrap_or_else(Utc::now));
 
         let mut conn = self.pool.get()
             .await
             .map_err(PatientError::repository)?;
-        
-        conn.execute(q, &[&store_me.id().unwrap(), &store_me.name()])
+
+        insert_waitlisted_patients(&mut conn, std::slice::from_ref(&store_me))
             .await
             .map_err(PatientError::repository)?;
-
-        for disallowed_hospital in store_me.disallowed_hospitals() {
-            conn.execute("
-                INSERT INTO rust.Patient_disallowed_hospitals (PatientID, HospitalID)
-                VALUES (@P1, (
-                    SELECT HospitalID
-                      FROM rust.Hospitals
-                     WHERE Name = @P2
-                ));
-            ", &[&store_me.id().unwrap(), &disallowed_hospital])
-                .await
-                .map_err(PatientError::repository)?;
-        }
         
         Ok(store_me)
     }
 
-    async fn store_admitted_patient(&mut self, patient: &Patient, hospital_name: &str) -> Result<Patient, PatientError> {
+    async fn store_admitted_patient(&self, patient: &Patient, hospital_name: &str) -> Result<Patient, PatientError> {
         let q = "
-            INSERT INTO rust.Patients (PatientID, Name, HospitalID)
+            INSERT INTO rust.Patients (PatientID, Name, HospitalID, Priority, WaitlistedAt, AdmittedAt)
             VALUES (@P1, @P2, (
                 SELECT HospitalID
                   FROM rust.Hospitals
-                 WHERE Name = @P3
-            ));
+                 WHERE UPPER(Name) = UPPER(@P3)
+            ), @P4, @P5, @P6);
         ";
 
+        let now = Utc::now();
+        let store_me = patient
+            .with_waitlisted_at(patient.waitlisted_at().unwrap_or(now))
+            .with_admitted_at(patient.admitted_at().unwrap_or(now));
+
         let mut conn = self.pool.get()
             .await
             .map_err(PatientError::repository)?;
         
-        conn.execute(q, &[&patient.id().unwrap(), &patient.name(), &hospital_name.to_string()])
+        conn.execute(q, &[&store_me.id().unwrap(), &store_me.name(), &hospital_name.to_string(), &store_me.priority().rank(), &store_me.waitlisted_at(), &store_me.admitted_at()])
             .await
             .map_err(PatientError::repository)?;
         
-        Ok(patient.clone())
+        Ok(store_me)
     }
-}
 
-struct PatientDisallowedHospitalMapping {
-    patient_id: uuid::Uuid,
-    patient_name: String,
-    hospital_name: Option<String>,
-    disallowed: Option<String>
-}
+    /// returns the preferred hospitals of the patients matching the given
+    /// filter, keyed by patient ID and ordered most preferred first
+    async fn get_preferred_hospitals(&self, filter: &PatientFilter) -> Result<HashMap<uuid::Uuid, Vec<String>>, PatientError> {
+        let q = format!("
+            SELECT pph.PatientID 'Patient ID', h.Name 'Preferred Hospital Name'
+              FROM rust.Patient_preferred_hospitals AS pph
+                   JOIN
+                   rust.Hospitals AS h
+                   ON pph.HospitalID = h.HospitalID
+                   JOIN
+                   rust.Patients AS p
+                   ON pph.PatientID = p.PatientID
+             WHERE {}
+             ORDER BY pph.PatientID, pph.Rank;
+        ", filter.condition());
 
-#[async_trait]
-impl PatientRepository for DatabasePatientRepository {
-    async fn store_patient(&mut self, patient: &Patient) -> Result<Patient, PatientError> {
-        if patient.id().is_none() {
-            // new patient
-            self.store_new_patient(patient).await
-        } else if let Some(ref hospital_name) = patient.admitted_to() {
-            self.store_admitted_patient(patient, hospital_name).await
-        } else {
-            self.store_waitlisted_patient(patient).await
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let result = conn.query(&q, &[&filter.ids_json()])
+            .await
+            .map_err(PatientError::repository)?;
+
+        let rows: Vec<(uuid::Uuid, String)> = helpers::map(
+            result,
+            |row| (
+                row.get("Patient ID").expect("Patient ID cannot be null"),
+                row.get::<&str, &str>("Preferred Hospital Name").map(String::from).expect("Hospital name cannot be null")
+            ))
+            .await;
+
+        let mut preferences: HashMap<uuid::Uuid, Vec<String>> = HashMap::new();
+        for (id, hospital_name) in rows {
+            preferences.entry(id)
+                .or_default()
+                .push(hospital_name);
         }
+        Ok(preferences)
     }
 
-    async fn get_all_patients(&mut self) -> Result<Vec<Patient>, PatientError> {
-        let q = "
-            SELECT p.PatientID 'Patient ID', p.Name 'Patient Name', h.Name 'Admitted To', d.Name 'Disallowed Hospital Name'
+    /// returns the patients matching the given filter, in the order they will
+    /// be admitted, along with their disallowed and preferred hospitals
+    async fn get_patients(&self, filter: &PatientFilter) -> Result<Vec<Patient>, PatientError> {
+        let q = format!("
+            SELECT p.PatientID 'Patient ID', p.Name 'Patient Name', p.Priority 'Priority', p.WaitlistedAt 'Waitlisted At', p.AdmittedAt 'Admitted At', h.Name 'Admitted To', d.Name 'Disallowed Hospital Name',
+                   p.DischargeReason 'Discharge Reason', p.DischargedAt 'Discharged At',
+                   (SELECT Name FROM rust.Hospitals WHERE HospitalID = p.DischargedFromHospitalID) 'Discharged From',
+                   p.WithdrawnAt 'Withdrawn At',
+                   CAST(p.RowVersion AS bigint) 'Row Version'
               FROM (
                        rust.Patients AS p
                        LEFT JOIN
@@ -210,14 +237,16 @@ impl PatientRepository for DatabasePatientRepository {
                        ON pdh.HospitalID = d.HospitalID
                    )
                    ON p.PatientID = pdh.PatientID
+             WHERE {}
+             ORDER BY p.Priority, p.WaitlistedAt, p.PatientID
             ;
-        ";
+        ", filter.condition());
 
         let mut conn = self.pool.get()
             .await
             .map_err(PatientError::repository)?;
 
-        let result = conn.query(q, &[])
+        let result = conn.query(&q, &[&filter.ids_json()])
             .await
             .map_err(PatientError::repository)?;
         let rows: Vec<PatientDisallowedHospitalMapping> = helpers::map(
@@ -225,21 +254,30 @@ impl PatientRepository for DatabasePatientRepository {
             |row| PatientDisallowedHospitalMapping {
                 patient_id: row.get("Patient ID").expect("Patient ID cannot be null"),
                 patient_name: row.get::<&str, &str>("Patient Name").map(String::from).expect("Patient name cannot be null"),
+                priority: priority_from_row(&row),
+                waitlisted_at: row.get("Waitlisted At"),
+                admitted_at: row.get("Admitted At"),
                 hospital_name: row.get::<&str, &str>("Admitted To").map(String::from),
-                disallowed: row.get::<&str, &str>("Disallowed Hospital Name").map(String::from)
+                disallowed: row.get::<&str, &str>("Disallowed Hospital Name").map(String::from),
+                discharge: discharge_from_row(&row),
+                withdrawn_at: row.get("Withdrawn At"),
+                version: helpers::row_version(&row)
             })
             .await;
         
+        // remember the order patients first appear in, as the query sorts them
+        let mut order: Vec<uuid::Uuid> = Vec::new();
         let mut hm: HashMap<uuid::Uuid, Patient> = HashMap::new();
         for row in rows {
+            if !hm.contains_key(&row.patient_id) {
+                order.push(row.patient_id);
+            }
             let e = hm.entry(row.patient_id)
                 .or_insert({
                     let np = Patient::new(&row.patient_name)
-                        .with_id(row.patient_id);
-                    match row.hospital_name {
-                        Some(ref hospital_name) => np.admit_to(hospital_name),
-                        None => np.waitlisted()
-                    }
+                        .with_id(row.patient_id)
+                        .with_priority(row.priority);
+                    row.to_patient_state(np)
                 });
 
             if let Some(hospital_name) = row.disallowed {
@@ -247,98 +285,568 @@ impl PatientRepository for DatabasePatientRepository {
             }
         }
 
-        Ok(hm.values().map(|p| p.to_owned()).collect())
+        for (id, preferred_hospitals) in self.get_preferred_hospitals(filter).await? {
+            if let Some(p) = hm.get_mut(&id) {
+                *p = p.with_preferred_hospitals(&preferred_hospitals);
+            }
+        }
+
+        Ok(order.iter().filter_map(|id| hm.remove(id)).collect())
     }
 
-    async fn get_waitlisted_patients(&mut self) -> Result<Vec<Patient>, PatientError> {
-        let all_patients = self.get_all_patients()
-            .await?;
-        let waitlisted = all_patients
+    /// loads the patients with the given IDs in one query, keyed by ID. IDs
+    /// with no patient are left out
+    pub(crate) async fn get_patients_by_ids(&self, ids: Vec<uuid::Uuid>) -> Result<HashMap<uuid::Uuid, Patient>, PatientError> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let patients = self.get_patients(&PatientFilter::Ids(ids))
+            .await?
             .into_iter()
-            .filter(Patient::is_waitlisted)
+            .filter_map(|p| p.id().map(|id| (id, p)))
             .collect();
-        Ok(waitlisted)
+        Ok(patients)
     }
+}
 
-    async fn get_patient_by_id(&mut self, id: uuid::Uuid) -> Result<Option<Patient>, PatientError> {
-        let q = "
-            SELECT p.PatientID 'Patient ID', p.Name 'Patient Name', h.Name 'Admitted To', d.Name 'Disallowed Hospital Name'
-              FROM (
-                        rust.Patients AS p
-                        LEFT JOIN
-                        rust.Hospitals AS h
-                        ON p.HospitalID = h.HospitalID
-                   )
-                   LEFT JOIN
-                   (
-                        rust.Patient_disallowed_hospitals AS pdh
-                        JOIN
-                        rust.Hospitals AS d
-                        ON pdh.HospitalID = d.HospitalID
-                   )
-                   ON p.PatientID = pdh.PatientID
-             WHERE p.PatientID = @P1;
-        ";
+/// Inserts the given patients, who must have IDs and waitlisted_at, onto the
+/// waitlist, along with their disallowed and preferred hospitals. Everything is
+/// sent as a single batch which commits or rolls back on the server, so a
+/// connection never goes back to the pool with a transaction still open, no
+/// matter how the request ends.
+async fn insert_waitlisted_patients(conn: &mut rt::Client, patients: &[Patient]) -> Result<(), tiberius::error::Error> {
+    // XACT_ABORT rolls the whole batch back on any error
+    let q = "
+        SET XACT_ABORT ON;
+        BEGIN TRANSACTION;
+
+        INSERT INTO rust.Patients (PatientID, Name, Priority, WaitlistedAt)
+        SELECT p.PatientID, p.Name, p.Priority, p.WaitlistedAt
+          FROM OPENJSON(@P1) WITH (
+                   PatientID uniqueidentifier,
+                   Name nvarchar(32),
+                   Priority tinyint,
+                   WaitlistedAt datetimeoffset
+               ) AS p;
+
+        INSERT INTO rust.Patient_disallowed_hospitals (PatientID, HospitalID)
+        SELECT p.PatientID, (
+                   SELECT HospitalID
+                     FROM rust.Hospitals
+                    WHERE UPPER(Name) = UPPER(d.value)
+               )
+          FROM OPENJSON(@P1) WITH (
+                   PatientID uniqueidentifier,
+                   Disallowed nvarchar(max) AS JSON
+               ) AS p
+               CROSS APPLY
+               OPENJSON(p.Disallowed) AS d;
+
+        INSERT INTO rust.Patient_preferred_hospitals (PatientID, HospitalID, Rank)
+        SELECT p.PatientID, (
+                   SELECT HospitalID
+                     FROM rust.Hospitals
+                    WHERE UPPER(Name) = UPPER(r.value)
+               ), CAST(r.[key] AS int)
+          FROM OPENJSON(@P1) WITH (
+                   PatientID uniqueidentifier,
+                   Preferred nvarchar(max) AS JSON
+               ) AS p
+               CROSS APPLY
+               OPENJSON(p.Preferred) AS r;
+
+        COMMIT TRANSACTION;
+    ";
+
+    // passed as one JSON parameter, as imports can be larger than the number
+    // of parameters a request may have
+    let rows: Vec<serde_json::Value> = patients.iter()
+        .map(|patient| serde_json::json!({
+            "PatientID": patient.id().expect("patient should have an ID to insert"),
+            "Name": patient.name(),
+            "Priority": patient.priority().rank(),
+            "WaitlistedAt": patient.waitlisted_at().map(|t| t.to_rfc3339_opts(SecondsFormat::Micros, true)),
+            "Disallowed": patient.disallowed_hospitals(),
+            "Preferred": patient.preferred_hospitals()
+        }))
+        .collect();
+    let json = serde_json::Value::Array(rows).to_string();
+
+    conn.execute(q, &[&json])
+        .await?;
+
+    Ok(())
+}
+
+/// which patients DatabasePatientRepository::get_patients loads
+enum PatientFilter {
+    All,
+    Waitlisted,
+    Discharged,
+
+    /// only the patients with the given IDs
+    Ids(Vec<uuid::Uuid>)
+}
+
+impl PatientFilter {
+    /// the SQL condition on rust.Patients AS p which selects these patients,
+    /// reading any IDs as a JSON array from @P1
+    fn condition(&self) -> &'static str {
+        match self {
+            Self::All => "1 = 1",
+            Self::Waitlisted => "p.HospitalID IS NULL AND p.DischargedAt IS NULL AND p.WithdrawnAt IS NULL",
+            Self::Discharged => "p.DischargedAt IS NOT NULL",
+            Self::Ids(_) => "p.PatientID IN (SELECT CAST(value AS uniqueidentifier) FROM OPENJSON(@P1))"
+        }
+    }
+
+    /// the value of @P1, which is only used to filter by ID
+    fn ids_json(&self) -> Option<String> {
+        match self {
+            Self::Ids(ids) => Some(serde_json::json!(ids).to_string()),
+            _ => None
+        }
+    }
+}
+
+struct PatientDisallowedHospitalMapping {
+    patient_id: uuid::Uuid,
+    patient_name: String,
+    priority: Priority,
+    waitlisted_at: Option<DateTime<Utc>>,
+    admitted_at: Option<DateTime<Utc>>,
+    hospital_name: Option<String>,
+    disallowed: Option<String>,
+    discharge: Option<Discharge>,
+    withdrawn_at: Option<DateTime<Utc>>,
+    version: Option<u64>
+}
+
+impl PatientDisallowedHospitalMapping {
+    /// returns a copy of the given patient, except admitted or waitlisted as
+    /// this row describes
+    fn to_patient_state(&self, patient: Patient) -> Patient {
+        let mut p = match self.hospital_name {
+            Some(ref hospital_name) => patient.admit_to(hospital_name),
+            None => patient.waitlisted()
+        };
+        if let Some(waitlisted_at) = self.waitlisted_at {
+            p = p.with_waitlisted_at(waitlisted_at);
+        }
+        if let Some(admitted_at) = self.admitted_at {
+            p = p.with_admitted_at(admitted_at);
+        }
+        if let Some(ref discharge) = self.discharge {
+            p = p.discharged(discharge.to_owned());
+        }
+        if let Some(withdrawn_at) = self.withdrawn_at {
+            p = p.withdrawn(withdrawn_at);
+        }
+        if let Some(version) = self.version {
+            p = p.with_version(version);
+        }
+        p
+    }
+}
+
+/// reads the discharge columns, or None if the patient is not discharged
+fn discharge_from_row(row: &tiberius::Row) -> Option<Discharge> {
+    let discharged_at = row.get::<DateTime<Utc>, &str>("Discharged At")?;
+    Some(Discharge::new(
+        row.get::<&str, &str>("Discharged From").unwrap_or_default(),
+        row.get::<&str, &str>("Discharge Reason").unwrap_or_default(),
+        discharged_at
+    ))
+}
+
+/// reads the priority column, treating unknown ranks as normal priority
+fn priority_from_row(row: &tiberius::Row) -> Priority {
+    row.get::<u8, &str>("Priority")
+        .and_then(Priority::from_rank)
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl PatientRepository for DatabasePatientRepository {
+    async fn store_patient(&self, patient: &Patient) -> Result<Patient, PatientError> {
+        if patient.id().is_none() {
+            // new patient
+            self.store_new_patient(patient).await
+        } else if let Some(ref hospital_name) = patient.admitted_to() {
+            self.store_admitted_patient(patient, hospital_name).await
+        } else {
+            self.store_waitlisted_patient(patient).await
+        }
+    }
+
+    async fn store_waitlisted_patients(&self, patients: &[Patient]) -> Result<Vec<Patient>, PatientError> {
+        let store_us: Vec<Patient> = patients.iter()
+            .map(|p| p.waitlisted().with_waitlisted_at(p.waitlisted_at().unwrap_or_else(Utc::now)))
+            .map(|p| if p.id().is_none() { p.with_random_id() } else { p })
+            .collect();
 
         let mut conn = self.pool.get()
             .await
             .map_err(PatientError::repository)?;
 
-        let result = conn.query(q, &[&id])
+        // all or nothing, so a failure part way through stores nobody
+        insert_waitlisted_patients(&mut conn, &store_us)
             .await
             .map_err(PatientError::repository)?;
 
-        let rows: Vec<PatientDisallowedHospitalMapping> = helpers::map(
+        Ok(store_us)
+    }
+
+    async fn get_all_patients(&self) -> Result<Vec<Patient>, PatientError> {
+        self.get_patients(&PatientFilter::All)
+            .await
+    }
+
+    async fn get_waitlisted_patients(&self) -> Result<Vec<Patient>, PatientError> {
+        self.get_patients(&PatientFilter::Waitlisted)
+            .await
+    }
+
+    async fn get_waitlist_page(&self, query: &WaitlistQuery) -> Result<Page<(usize, Patient)>, PatientError> {
+        let sort = query.sort();
+        let sort_keys: &[&str] = match sort {
+            WaitlistSort::Position => &["w.Priority", "w.WaitlistedAt", "w.PatientID"],
+            WaitlistSort::Name | WaitlistSort::NameDescending => &["w.Name", "w.PatientID"],
+            WaitlistSort::WaitlistedAt | WaitlistSort::WaitlistedAtDescending => &["w.WaitlistedAt", "w.PatientID"]
+        };
+        let direction = if sort.is_descending() { "DESC" } else { "ASC" };
+        let order_by = sort_keys.iter()
+            .map(|key| format!("{} {}", key, direction))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        // number the whole waitlist before filtering, so positions are where
+        // patients really are in line
+        let q = format!("
+            WITH waitlist AS (
+                SELECT p.PatientID, p.Name, p.Priority, p.WaitlistedAt,
+                       ROW_NUMBER() OVER (ORDER BY p.Priority, p.WaitlistedAt, p.PatientID) AS Position
+                  FROM rust.Patients AS p
+                 WHERE p.HospitalID IS NULL
+                   AND p.DischargedAt IS NULL
+                   AND p.WithdrawnAt IS NULL
+            )
+            SELECT TOP (@P1) w.PatientID 'Patient ID', w.Position 'Position'
+              FROM waitlist AS w
+             WHERE (@P2 IS NULL OR UPPER(w.Name) LIKE @P2 ESCAPE '\\')
+               AND {after}
+             ORDER BY {order_by}
+            ;
+        ", after = helpers::keyset_condition(sort_keys, 3, sort.is_descending()), order_by = order_by);
+
+        // cursors are the sort keys, ending with the patient ID
+        let invalid = || PatientError::Invalid(String::from("cursor does not match the sort order"));
+        let after: Vec<String> = query.cursor()
+            .map(|cursor| cursor.values().to_vec())
+            .unwrap_or_default();
+        if !after.is_empty() && after.len() != sort_keys.len() {
+            return Err(invalid());
+        }
+        let after_id: Option<uuid::Uuid> = match after.last() {
+            Some(id) => Some(id.parse().map_err(|_| invalid())?),
+            None => None
+        };
+        let parse_time = |value: &String| DateTime::parse_from_rfc3339(value)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|_| invalid());
+
+        let limit = query.limit();
+        let fetch = (limit + 1) as i32; // one extra to check for another page
+        let name_filter = query.name_filter().map(|name| helpers::contains_pattern(&name));
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let result = match sort {
+            WaitlistSort::Position => {
+                let after_priority: Option<u8> = match after.first() {
+                    Some(rank) => Some(rank.parse().map_err(|_| invalid())?),
+                    None => None
+                };
+                let after_time = after.get(1).map(parse_time).transpose()?;
+                conn.query(&q, &[&fetch, &name_filter, &after_priority, &after_time, &after_id]).await
+            },
+            WaitlistSort::Name | WaitlistSort::NameDescending => {
+                let after_name = after.first().map(String::as_str);
+                conn.query(&q, &[&fetch, &name_filter, &after_name, &after_id]).await
+            },
+            WaitlistSort::WaitlistedAt | WaitlistSort::WaitlistedAtDescending => {
+                let after_time = after.first().map(parse_time).transpose()?;
+                conn.query(&q, &[&fetch, &name_filter, &after_time, &after_id]).await
+            }
+        }.map_err(PatientError::repository)?;
+
+        let rows: Vec<(uuid::Uuid, i64)> = helpers::map(
             result,
-            |row| PatientDisallowedHospitalMapping {
-                patient_id: id,
-                patient_name: row.get::<&str, &str>("Patient Name").map(String::from).expect("Patient name cannot be null"),
-                hospital_name: row.get::<&str, &str>("Admitted To").map(String::from),
-                disallowed: row.get::<&str, &str>("Disallowed Hospital Name").map(String::from)
-            })
+            |row| (
+                row.get("Patient ID").expect("Patient ID cannot be null"),
+                row.get("Position").expect("Position cannot be null")
+            ))
             .await;
-        
-        if rows.is_empty() {
-            Ok(None)
-        } else {
-            let mut p = Patient::new(&rows[0].patient_name)
-                .with_id(id);
-            p = match rows[0].hospital_name {
-                Some(ref hospital_name) => p.admit_to(hospital_name),
-                None => p.waitlisted()
+        drop(conn);
+
+        // load the whole page at once, rather than a patient at a time
+        let ids = rows.iter().map(|(id, _)| *id).collect();
+        let mut patients = self.get_patients_by_ids(ids).await?;
+        let entries: Vec<(usize, Patient)> = rows.into_iter()
+            .filter_map(|(id, position)| patients.remove(&id).map(|p| (position as usize, p)))
+            .collect();
+
+        Ok(Page::from_rows(entries, limit, |(_, p)| {
+            let waitlisted_at = p.waitlisted_at()
+                .map(|t| t.to_rfc3339_opts(SecondsFormat::Nanos, true))
+                .unwrap_or_default();
+            let mut values = match sort {
+                WaitlistSort::Position => vec![p.priority().rank().to_string(), waitlisted_at],
+                WaitlistSort::Name | WaitlistSort::NameDescending => vec![p.name()],
+                WaitlistSort::WaitlistedAt | WaitlistSort::WaitlistedAtDescending => vec![waitlisted_at]
             };
+            values.push(p.id().map(|id| id.to_string()).unwrap_or_default());
+            Cursor::new(&values)
+        }))
+    }
 
-            let disallowed_hospitals: HashSet<String> = rows.iter()
-                .filter_map(|mapping| mapping.disallowed.as_ref().map(|h| h.to_owned()))
-                .collect();
-            p = p.with_disallowed_hospitals(&disallowed_hospitals);
+    fn stream_waitlist(&self, name_filter: Option<String>) -> LocalBoxStream<'static, Result<WaitlistLine, PatientError>> {
+        // number the whole waitlist before filtering, so positions are where
+        // patients really are in line
+        let q = "
+            WITH waitlist AS (
+                SELECT p.PatientID, p.Name, p.Priority, p.WaitlistedAt,
+                       ROW_NUMBER() OVER (ORDER BY p.Priority, p.WaitlistedAt, p.PatientID) AS Position
+                  FROM rust.Patients AS p
+                 WHERE p.HospitalID IS NULL
+                   AND p.DischargedAt IS NULL
+                   AND p.WithdrawnAt IS NULL
+            )
+            SELECT w.Position 'Position', w.PatientID 'Patient ID', w.Name 'Patient Name', w.Priority 'Priority', w.WaitlistedAt 'Waitlisted At',
+                   (
+                       SELECT STRING_AGG(h.Name, ';') WITHIN GROUP (ORDER BY h.Name)
+                         FROM rust.Patient_disallowed_hospitals AS pdh
+                              JOIN
+                              rust.Hospitals AS h
+                              ON pdh.HospitalID = h.HospitalID
+                        WHERE pdh.PatientID = w.PatientID
+                   ) 'Disallowed Hospitals'
+              FROM waitlist AS w
+             WHERE @P1 IS NULL OR UPPER(w.Name) LIKE @P1 ESCAPE '\\'
+             ORDER BY w.Position;
+        ";
 
-            Ok(Some(p))
-        }
+        // the stream outlives this repository's borrow, so it needs its own
+        // connection rather than one borrowed from the pool
+        let pool = self.pool.clone();
+        let name_filter = name_filter.map(|name| helpers::contains_pattern(&name));
+
+        Box::pin(try_stream! {
+            let mut conn = pool.get_owned()
+                .await
+                .map_err(PatientError::repository)?;
+
+            let mut rows = conn.query(q, &[&name_filter])
+                .await
+                .map_err(PatientError::repository)?
+                .into_row_stream();
+            while let Some(row) = rows.try_next().await.map_err(PatientError::repository)? {
+                let position: i64 = row.get("Position").expect("Position cannot be null");
+                yield WaitlistLine::new(
+                    position as usize,
+                    row.get("Patient ID").expect("Patient ID cannot be null"),
+                    row.get("Patient Name").expect("Patient name cannot be null"),
+                    priority_from_row(&row),
+                    row.get("Waitlisted At"),
+                    row.get("Disallowed Hospitals").unwrap_or_default()
+                );
+            }
+        })
+    }
+
+    async fn get_discharged_patients(&self) -> Result<Vec<Patient>, PatientError> {
+        self.get_patients(&PatientFilter::Discharged)
+            .await
     }
 
-    async fn update_patient_hospital(&mut self, patient: &Patient) -> Result<Patient, PatientError> {
+    async fn count_patients(&self) -> Result<(usize, usize), PatientError> {
+        let q = "
+            SELECT COUNT(CASE WHEN p.HospitalID IS NULL AND p.DischargedAt IS NULL AND p.WithdrawnAt IS NULL THEN 1 END) 'Waitlisted',
+                   COUNT(p.HospitalID) 'Admitted'
+              FROM rust.Patients AS p;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let stream = conn.query(q, &[])
+            .await
+            .map_err(PatientError::repository)?;
+        let row = stream.into_row()
+            .await
+            .map_err(PatientError::repository)?;
+        let (waitlisted, admitted) = row
+            .map(|row| (row.get::<i32, &str>("Waitlisted"), row.get::<i32, &str>("Admitted")))
+            .unwrap_or_default();
+        Ok((waitlisted.unwrap_or_default() as usize, admitted.unwrap_or_default() as usize))
+    }
+
+    async fn get_patient_by_id(&self, id: uuid::Uuid) -> Result<Option<Patient>, PatientError> {
+        let patient = self.get_patients(&PatientFilter::Ids(vec![id]))
+            .await?
+            .pop();
+        Ok(patient)
+    }
+
+    async fn update_patient_hospital(&self, patient: &Patient) -> Result<Patient, PatientError> {
         let hospital = patient.admitted_to() // must have a hospital to update
             .ok_or(PatientError::Unsupported)?;
+        let id = patient.id()
+            .ok_or(PatientError::Unsupported)?;
 
+        // Only admits patients still on the waitlist, into a hospital still open
+        // with a free bed, as transfers, closures, and withdrawals can happen
+        // while an admission run is going.
         let q = "
             UPDATE rust.Patients
                SET HospitalID = (
                    SELECT HospitalID
                      FROM rust.Hospitals
-                    WHERE Name = @P1
-               )
-             WHERE PatientID = @P2;
+                    WHERE UPPER(Name) = UPPER(@P1)
+               ),
+                   AdmittedAt = @P3
+             WHERE PatientID = @P2
+               AND HospitalID IS NULL
+               AND DischargedAt IS NULL
+               AND WithdrawnAt IS NULL
+               AND EXISTS ( -- locks the hospital, so concurrent writes can't both take its last bed
+                   SELECT 1
+                     FROM rust.Hospitals AS h WITH (UPDLOCK, HOLDLOCK)
+                    WHERE UPPER(h.Name) = UPPER(@P1)
+                      AND h.ClosedAt IS NULL
+                      AND (h.Capacity IS NULL OR h.Capacity > (
+                          SELECT COUNT(*)
+                            FROM rust.Patients AS p
+                           WHERE p.HospitalID = h.HospitalID
+                      ))
+               );
         ";
 
+        let updated = patient.with_admitted_at(patient.admitted_at().unwrap_or_else(Utc::now));
+
         let mut conn = self.pool.get()
             .await
             .map_err(PatientError::repository)?;
 
-        conn.execute(q, &[&hospital, &patient.id().unwrap()])
+        let result = conn.execute(q, &[&hospital, &id, &updated.admitted_at()])
             .await
             .map_err(PatientError::repository)?;
+        drop(conn);
+
+        if result.total() == 0 {
+            // either the patient left the waitlist, or the hospital can't take them
+            return Err(match self.get_patient_by_id(id).await? {
+                None => PatientError::NotFound(id),
+                Some(stored) if !stored.is_waitlisted() => PatientError::NotWaitlisted(id),
+                Some(_) => PatientError::HospitalUnavailable(hospital)
+            });
+        }
 
-        Ok(patient.to_owned())
+        Ok(updated)
     }
-}
\ No newline at end of file
+
+    async fn update_patient_details(&self, patient: &Patient, replace_disallowed: bool) -> Result<Patient, PatientError> {
+        let id = patient.id() // must already be stored to update
+            .ok_or(PatientError::Unsupported)?;
+
+        // One batch which commits or rolls back on the server, so a patient is
+        // never left with only some of their disallowed hospitals. Writing the
+        // name bumps the row version, even if it didn't change. Disallowed
+        // hospitals are only replaced if @P4 is given.
+        let q = "
+            SET XACT_ABORT ON;
+            BEGIN TRANSACTION;
+
+            UPDATE rust.Patients
+               SET Name = @P2
+             WHERE PatientID = @P1
+               AND (@P3 IS NULL OR CAST(RowVersion AS bigint) = @P3);
+
+            IF @@ROWCOUNT = 0
+            BEGIN
+                ROLLBACK TRANSACTION;
+                RETURN;
+            END;
+
+            IF @P4 IS NOT NULL
+            BEGIN
+                DELETE FROM rust.Patient_disallowed_hospitals
+                 WHERE PatientID = @P1;
+
+                INSERT INTO rust.Patient_disallowed_hospitals (PatientID, HospitalID)
+                SELECT @P1, h.HospitalID
+                  FROM rust.Hospitals AS h
+                 WHERE UPPER(h.Name) IN (
+                       SELECT UPPER(d.value)
+                         FROM OPENJSON(@P4) AS d
+                 );
+            END;
+
+            COMMIT TRANSACTION;
+        ";
+
+        let disallowed: Option<String> = replace_disallowed
+            .then(|| serde_json::json!(patient.disallowed_hospitals()).to_string());
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let result = conn.execute(q, &[&id, &patient.name(), &helpers::version_param(patient.version()), &disallowed])
+            .await
+            .map_err(PatientError::repository)?;
+        drop(conn);
+
+        let stored = self.get_patient_by_id(id)
+            .await?
+            .ok_or(PatientError::NotFound(id))?;
+        if result.total() == 0 {
+            // the patient exists, so only a newer version stopped the update
+            return Err(PatientError::Stale(id));
+        }
+        Ok(stored)
+    }
+
+    async fn withdraw_patient(&self, id: uuid::Uuid) -> Result<Patient, PatientError> {
+        // keeps the row, so the patient's record survives alongside their
+        // history, and only withdraws patients still on the waitlist
+        let q = "
+            UPDATE rust.Patients
+               SET WithdrawnAt = SYSDATETIMEOFFSET()
+             WHERE PatientID = @P1
+               AND HospitalID IS NULL
+               AND DischargedAt IS NULL
+               AND WithdrawnAt IS NULL;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(PatientError::repository)?;
+
+        let result = conn.execute(q, &[&id])
+            .await
+            .map_err(PatientError::repository)?;
+        drop(conn);
+
+        let stored = self.get_patient_by_id(id)
+            .await?
+            .ok_or(PatientError::NotFound(id))?;
+        if result.total() == 0 {
+            return Err(PatientError::NotWaitlisted(id));
+        }
+        Ok(stored)
+    }
+}
@@ -0,0 +1,277 @@
+// Implements WebhookRepository for an MSSQL database.
+
+use async_trait::async_trait;
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use chrono::{DateTime, Utc};
+use tiberius::ExecuteResult;
+use uuid::Uuid;
+
+use crate::{patient_services::PatientEventKind, webhook_services::{WebhookRepository, WebhookSubscription, WebhookDelivery, WebhookError}};
+
+use super::helpers;
+
+pub struct DatabaseWebhookRepository {
+    pool: Pool<ConnectionManager> // internally uses an Arc
+}
+
+impl DatabaseWebhookRepository {
+    pub fn new(pool: Pool<ConnectionManager>) -> Self {
+        Self {
+            pool
+        }
+    }
+
+    pub async fn setup(&self) -> Result<ExecuteResult, WebhookError> {
+        // event kinds are few and always read together, so they are stored as
+        // a comma-separated list rather than in their own table
+        let q = "
+            IF OBJECT_ID(N'rust.Webhook_deliveries', N'U') IS NOT NULL
+                DROP TABLE rust.Webhook_deliveries;
+
+            IF OBJECT_ID(N'rust.Webhook_subscriptions', N'U') IS NOT NULL
+                DROP TABLE rust.Webhook_subscriptions;
+
+            CREATE TABLE rust.Webhook_subscriptions (
+                SubscriptionID uniqueidentifier PRIMARY KEY NOT NULL,
+                Url nvarchar(2048) NOT NULL,
+                EventKinds varchar(128) NOT NULL,
+                Secret char(64) NOT NULL,
+                CreatedBy varchar(64) NOT NULL,
+                CreatedAt datetimeoffset NOT NULL
+            );
+
+            CREATE TABLE rust.Webhook_deliveries (
+                DeliveryID uniqueidentifier NOT NULL,
+                Attempt int NOT NULL,
+                SubscriptionID uniqueidentifier NOT NULL,
+                EventKind varchar(16) NOT NULL,
+                PatientID uniqueidentifier NOT NULL,
+                AttemptedAt datetimeoffset NOT NULL,
+                StatusCode int, -- null if the subscriber could not be reached
+                Error nvarchar(max), -- null if the attempt succeeded
+                CONSTRAINT PK_Webhook_deliveries PRIMARY KEY (DeliveryID, Attempt),
+                CONSTRAINT FK_Webhook_deliveries_Webhook_subscriptions
+                    FOREIGN KEY (SubscriptionID) REFERENCES rust.Webhook_subscriptions(SubscriptionID)
+                    ON DELETE CASCADE,
+                INDEX IX_Webhook_deliveries_SubscriptionID (SubscriptionID, AttemptedAt)
+            );
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(WebhookError::repository)?;
+
+        let result = conn.execute(q, &[])
+            .await
+            .map_err(WebhookError::repository)?;
+
+        Ok(result)
+    }
+
+    async fn query_subscriptions(&self, id: Option<Uuid>) -> Result<Vec<WebhookSubscription>, WebhookError> {
+        let q = "
+            SELECT SubscriptionID, Url, EventKinds, Secret, CreatedBy, CreatedAt
+              FROM rust.Webhook_subscriptions
+             WHERE @P1 IS NULL OR SubscriptionID = @P1
+             ORDER BY CreatedAt;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(WebhookError::repository)?;
+
+        let result = conn.query(q, &[&id])
+            .await
+            .map_err(WebhookError::repository)?;
+
+        let rows: Vec<SubscriptionMapping> = helpers::map(
+            result,
+            |row| SubscriptionMapping {
+                id: row.get("SubscriptionID").expect("SubscriptionID cannot be null"),
+                url: row.get::<&str, &str>("Url").map(String::from).expect("Url cannot be null"),
+                event_kinds: row.get::<&str, &str>("EventKinds").map(String::from).expect("EventKinds cannot be null"),
+                secret: row.get::<&str, &str>("Secret").map(String::from).expect("Secret cannot be null"),
+                created_by: row.get::<&str, &str>("CreatedBy").map(String::from).expect("CreatedBy cannot be null"),
+                created_at: row.get("CreatedAt").expect("CreatedAt cannot be null")
+            })
+            .await;
+
+        rows.iter()
+            .map(SubscriptionMapping::to_subscription)
+            .collect()
+    }
+}
+
+struct SubscriptionMapping {
+    id: Uuid,
+    url: String,
+    event_kinds: String,
+    secret: String,
+    created_by: String,
+    created_at: DateTime<Utc>
+}
+
+impl SubscriptionMapping {
+    fn to_subscription(&self) -> Result<WebhookSubscription, WebhookError> {
+        let event_kinds: Vec<PatientEventKind> = self.event_kinds
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_, String>>()
+            .map_err(|e| WebhookError::Repository(e.into()))?;
+
+        Ok(WebhookSubscription::restore(self.id, &self.url, &event_kinds, &self.secret, &self.created_by, self.created_at))
+    }
+}
+
+struct DeliveryMapping {
+    delivery_id: Uuid,
+    attempt: i32,
+    subscription_id: Uuid,
+    event_kind: String,
+    patient_id: Uuid,
+    attempted_at: DateTime<Utc>,
+    status_code: Option<i32>,
+    error: Option<String>
+}
+
+impl DeliveryMapping {
+    fn to_delivery(&self) -> Result<WebhookDelivery, WebhookError> {
+        let event_kind: PatientEventKind = self.event_kind.parse()
+            .map_err(|e: String| WebhookError::Repository(e.into()))?;
+
+        Ok(WebhookDelivery::restore(
+            self.delivery_id,
+            self.subscription_id,
+            event_kind,
+            self.patient_id,
+            self.attempt.try_into().expect("Attempt should be positive"),
+            self.attempted_at,
+            self.status_code.map(|status| status.try_into().expect("StatusCode should be a valid HTTP status")),
+            self.error.clone()
+        ))
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for DatabaseWebhookRepository {
+    async fn get_all_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookError> {
+        self.query_subscriptions(None).await
+    }
+
+    async fn get_subscription(&self, id: Uuid) -> Result<Option<WebhookSubscription>, WebhookError> {
+        let found = self.query_subscriptions(Some(id)).await?;
+        Ok(found.into_iter().next())
+    }
+
+    async fn store_subscription(&self, subscription: &WebhookSubscription) -> Result<(), WebhookError> {
+        let q = "
+            INSERT INTO rust.Webhook_subscriptions (SubscriptionID, Url, EventKinds, Secret, CreatedBy, CreatedAt)
+            VALUES (@P1, @P2, @P3, @P4, @P5, @P6);
+        ";
+
+        let event_kinds = subscription.event_kinds()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(WebhookError::repository)?;
+
+        conn.execute(q, &[
+                &subscription.id(),
+                &subscription.url(),
+                &event_kinds,
+                &subscription.secret(),
+                &subscription.created_by(),
+                &subscription.created_at()
+            ])
+            .await
+            .map_err(WebhookError::repository)?;
+
+        Ok(())
+    }
+
+    async fn delete_subscription(&self, id: Uuid) -> Result<(), WebhookError> {
+        let q = "
+            DELETE FROM rust.Webhook_subscriptions
+             WHERE SubscriptionID = @P1;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(WebhookError::repository)?;
+
+        conn.execute(q, &[&id])
+            .await
+            .map_err(WebhookError::repository)?;
+
+        Ok(())
+    }
+
+    async fn store_delivery(&self, delivery: &WebhookDelivery) -> Result<(), WebhookError> {
+        let q = "
+            INSERT INTO rust.Webhook_deliveries (DeliveryID, Attempt, SubscriptionID, EventKind, PatientID, AttemptedAt, StatusCode, Error)
+            VALUES (@P1, @P2, @P3, @P4, @P5, @P6, @P7, @P8);
+        ";
+
+        let attempt = i32::try_from(delivery.attempt())
+            .map_err(WebhookError::repository)?;
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(WebhookError::repository)?;
+
+        conn.execute(q, &[
+                &delivery.delivery_id(),
+                &attempt,
+                &delivery.subscription_id(),
+                &delivery.event_kind().to_string(),
+                &delivery.patient_id(),
+                &delivery.attempted_at(),
+                &delivery.status_code().map(i32::from),
+                &delivery.error()
+            ])
+            .await
+            .map_err(WebhookError::repository)?;
+
+        Ok(())
+    }
+
+    async fn get_deliveries(&self, subscription_id: Uuid) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        let q = "
+            SELECT DeliveryID, Attempt, SubscriptionID, EventKind, PatientID, AttemptedAt, StatusCode, Error
+              FROM rust.Webhook_deliveries
+             WHERE SubscriptionID = @P1
+             ORDER BY AttemptedAt DESC, Attempt DESC;
+        ";
+
+        let mut conn = self.pool.get()
+            .await
+            .map_err(WebhookError::repository)?;
+
+        let result = conn.query(q, &[&subscription_id])
+            .await
+            .map_err(WebhookError::repository)?;
+
+        let rows: Vec<DeliveryMapping> = helpers::map(
+            result,
+            |row| DeliveryMapping {
+                delivery_id: row.get("DeliveryID").expect("DeliveryID cannot be null"),
+                attempt: row.get("Attempt").expect("Attempt cannot be null"),
+                subscription_id: row.get("SubscriptionID").expect("SubscriptionID cannot be null"),
+                event_kind: row.get::<&str, &str>("EventKind").map(String::from).expect("EventKind cannot be null"),
+                patient_id: row.get("PatientID").expect("PatientID cannot be null"),
+                attempted_at: row.get("AttemptedAt").expect("AttemptedAt cannot be null"),
+                status_code: row.get("StatusCode"),
+                error: row.get::<&str, &str>("Error").map(String::from)
+            })
+            .await;
+
+        rows.iter()
+            .map(DeliveryMapping::to_delivery)
+            .collect()
+    }
+}
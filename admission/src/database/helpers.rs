@@ -1,7 +1,7 @@
 use std::future;
 
 use futures_util::{TryStreamExt, StreamExt};
-use tiberius::QueryStream;
+use tiberius::{QueryStream, error::Error};
 
 /// converts each row in the stream according to the given mapper, then returns
 /// the transformed rows
@@ -16,4 +16,58 @@ where
         .map(mapper)
         .collect()
         .await
-}
\ No newline at end of file
+}
+
+/// Reads the 'Row Version' column, which should be a rowversion cast to a
+/// bigint. rowversion is an 8-byte counter SQL Server bumps every time a row
+/// is written, so it tells whether a row changed since it was read.
+pub fn row_version(row: &tiberius::Row) -> Option<u64> {
+    row.get::<i64, &str>("Row Version").map(|version| version as u64)
+}
+
+/// converts a version into a parameter to compare against a rowversion cast to
+/// a bigint, where null matches any version
+pub fn version_param(version: Option<u64>) -> Option<i64> {
+    version.map(|version| version as i64)
+}
+
+/// returns a LIKE pattern, escaped with \, which matches any uppercase string
+/// containing the given text
+pub fn contains_pattern(text: &str) -> String {
+    let escaped: String = text.to_uppercase()
+        .chars()
+        .flat_map(|c| match c {
+            '\\' | '%' | '_' | '[' => vec!['\\', c],
+            _ => vec![c]
+        })
+        .collect();
+    format!("%{}%", escaped)
+}
+
+/// Returns a condition selecting rows which come after a cursor when sorted by
+/// the given columns, the last of which must be unique. Each column is compared
+/// to a parameter, numbered from first_param. Rows are never excluded if the
+/// last parameter is null, so the same query works for the first page.
+pub fn keyset_condition(columns: &[&str], first_param: usize, descending: bool) -> String {
+    let op = if descending { "<" } else { ">" };
+    let last_param = first_param + columns.len() - 1;
+    let condition = columns.iter()
+        .enumerate()
+        .rev()
+        .fold(String::new(), |inner, (i, column)| {
+            let param = first_param + i;
+            if inner.is_empty() {
+                format!("{} {} @P{}", column, op, param)
+            } else {
+                format!("{} {} @P{} OR ({} = @P{} AND ({}))", column, op, param, column, param, inner)
+            }
+        });
+    format!("(@P{} IS NULL OR {})", last_param, condition)
+}
+
+/// whether the given error is from inserting or updating a row which would
+/// duplicate a UNIQUE constraint or index, such as when two requests race to
+/// write the same name
+pub fn is_unique_violation(error: &Error) -> bool {
+    matches!(error, Error::Server(e) if e.code() == 2627 || e.code() == 2601)
+}
@@ -1,5 +1,9 @@
+pub mod database_admission_run_repository;
 pub mod database_group_repository;
 pub mod database_hospital_repository;
+pub mod database_idempotency_repository;
+pub mod database_patient_event_repository;
 pub mod database_patient_repository;
+pub mod database_webhook_repository;
 pub mod helpers;
 pub mod pool;
\ No newline at end of file
@@ -2,22 +2,31 @@
 // therefore, it is sometimes better to keep a pool of many open connections,
 // and have clients use a connection from the pool, then put it back in
 
-use std::env;
+use std::fmt::Display;
 
 use bb8::Pool;
 use bb8_tiberius::ConnectionManager;
 use tiberius::{Config, AuthMethod};
 
+use crate::config::DatabaseConfig;
+
 #[derive(Debug)]
 pub enum DatabaseError {
     BB8(bb8_tiberius::Error)
 }
 
-/// creates a DbPool of connections to the database
-/// this will return an error if any environment variables are not set or any
-/// errors arise while connecting
-pub async fn make_db_pool() -> Result<Pool<ConnectionManager>, DatabaseError> {
-    let config = create_config_from_env();
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BB8(inner) => write!(f, "BB8 error: {}", inner)
+        }
+    }
+}
+
+/// creates a DbPool of connections to the configured database
+/// this will return an error if any errors arise while connecting
+pub async fn make_db_pool(config: &DatabaseConfig) -> Result<Pool<ConnectionManager>, DatabaseError> {
+    let config = create_config(config);
     let manager = ConnectionManager::new(config);
     let pool = bb8::Pool::builder()
         .build(manager)
@@ -26,15 +35,14 @@ pub async fn make_db_pool() -> Result<Pool<ConnectionManager>, DatabaseError> {
     Ok(pool)
 }
 
-pub fn create_config_from_env() -> Config {
+pub fn create_config(database: &DatabaseConfig) -> Config {
     let mut config = Config::new();
-    config.database("RustDB");
+    config.host(&database.host);
+    config.port(database.port);
+    config.database(&database.name);
     config.trust_cert();
 
-    config.authentication(AuthMethod::sql_server(
-        env::var("TIBERIUS_USERNAME").expect("TIBERIUS_USERNAME environment variable should be set"),
-        env::var("TIBERIUS_PASSWORD").expect("TIBERIUS_PASSWORD environment variable should be set")
-    ));
+    config.authentication(AuthMethod::sql_server(&database.username, &database.password));
 
     config
 }
\ No newline at end of file
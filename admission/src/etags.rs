@@ -0,0 +1,80 @@
+// Optimistic concurrency: patients and hospitals are sent with an ETag of their
+// row version, and changes to them must send it back in If-Match, so a client
+// cannot overwrite changes it has not seen.
+
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, http::{StatusCode, header::{ETag, EntityTag, IfMatch}}};
+use serde::Serialize;
+
+use crate::api_error::ApiError;
+
+/// converts a stored version into an ETag
+pub fn etag(version: u64) -> ETag {
+    ETag(EntityTag::new_strong(format!("{:016x}", version)))
+}
+
+/// responds 200 with the given JSON, tagged with the given version if known
+pub fn json_with_etag(body: &impl Serialize, version: Option<u64>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    if let Some(version) = version {
+        response.insert_header(etag(version));
+    }
+    response.json(body)
+}
+
+/// Returns the version the client expects to change, as sent in If-Match, or
+/// None if they sent *, which matches any version. Fails if the client did not
+/// send If-Match, or sent an ETag this API could not have made.
+pub fn expected_version(req: &HttpRequest) -> Result<Option<u64>, ApiError> {
+    match req.get_header::<IfMatch>() {
+        None => Err(ApiError::new(
+            StatusCode::PRECONDITION_REQUIRED,
+            "if-match-required",
+            "If-Match required",
+            "Send the ETag from a GET request in the If-Match header"
+        )),
+        Some(IfMatch::Any) => Ok(None),
+        Some(IfMatch::Items(tags)) => match tags.as_slice() {
+            // If-Match always uses strong comparison, so weak ETags never match
+            [tag] if !tag.weak => u64::from_str_radix(tag.tag(), 16)
+                .map(Some)
+                .map_err(|_| ApiError::precondition_failed(format!("Unknown ETag: {}", tag))),
+            _ => Err(ApiError::precondition_failed("If-Match must contain a single strong ETag"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test::TestRequest, ResponseError};
+
+    use super::*;
+
+    #[test]
+    fn expected_version_reads_etag() {
+        let req = TestRequest::default()
+            .insert_header(("If-Match", etag(42).to_string()))
+            .to_http_request();
+
+        assert_eq!(Some(42), expected_version(&req).unwrap());
+    }
+
+    #[test]
+    fn expected_version_given_no_if_match_is_required() {
+        let req = TestRequest::default().to_http_request();
+
+        let result = expected_version(&req);
+
+        assert_eq!(StatusCode::PRECONDITION_REQUIRED, result.unwrap_err().status_code());
+    }
+
+    #[test]
+    fn expected_version_given_weak_etag_fails_precondition() {
+        let req = TestRequest::default()
+            .insert_header(("If-Match", "W/\"000000000000002a\""))
+            .to_http_request();
+
+        let result = expected_version(&req);
+
+        assert_eq!(StatusCode::PRECONDITION_FAILED, result.unwrap_err().status_code());
+    }
+}
@@ -0,0 +1,178 @@
+// Lists can be downloaded as CSV, which opens directly in spreadsheet programs
+// such as Excel. Exports are streamed a line at a time from the backing store,
+// so large ones are never held in memory all at once.
+
+use std::io;
+
+use actix_web::{HttpRequest, HttpResponse, HttpMessage, web::{Bytes, Query}, http::header::{Accept, ContentDisposition, DispositionParam, DispositionType}};
+use futures_util::{stream::LocalBoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::{ToSchema, IntoParams};
+
+use crate::api_error::ApiError;
+
+/// the media type of CSV exports
+pub const CSV: &str = "text/csv";
+
+/// starts every export, so Excel reads it as UTF-8 rather than guessing, and
+/// names with accents come through intact
+const BYTE_ORDER_MARK: &str = "\u{FEFF}";
+
+/// the formats lists can be returned in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv
+}
+
+/// lets clients choose a format without setting the Accept header, such as
+/// from a download link
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FormatQuery {
+    /// overrides the Accept header
+    format: Option<ExportFormat>
+}
+
+/// a line of a CSV export, whose fields are serialized in the same order as
+/// its headers
+pub trait CsvLine: Serialize {
+    const HEADERS: &'static [&'static str];
+}
+
+/// Returns the format the client asked for, either with ?format= or by
+/// preferring text/csv in their Accept header. Defaults to JSON.
+pub fn requested_format(req: &HttpRequest) -> Result<ExportFormat, ApiError> {
+    let query = Query::<FormatQuery>::from_query(req.query_string())
+        .map_err(|e| ApiError::bad_request("invalid-query", "Invalid query string", e))?;
+    if let Some(format) = query.format {
+        return Ok(format);
+    }
+
+    let prefers_csv = req.get_header::<Accept>()
+        .map(|accept| accept.preference().essence_str() == CSV)
+        .unwrap_or(false);
+    Ok(if prefers_csv { ExportFormat::Csv } else { ExportFormat::Json })
+}
+
+/// Streams the given lines to the client as a CSV file with the given name.
+/// Waits for the first line before responding, so errors which happen before
+/// then, such as asking for something which doesn't exist, are reported as
+/// usual. Later errors can only cut the download short.
+pub async fn csv_response<T, E>(file_name: &str, mut lines: LocalBoxStream<'static, Result<T, E>>) -> Result<HttpResponse, ApiError>
+where
+    T: CsvLine + 'static,
+    E: Into<ApiError> + 'static
+{
+    let first = lines.next()
+        .await
+        .transpose()
+        .map_err(Into::into)?;
+
+    let mut header = BYTE_ORDER_MARK.as_bytes().to_vec();
+    header.extend(to_csv(&T::HEADERS));
+    let header = Bytes::from(header);
+    let body = futures_util::stream::iter(first.map(Ok))
+        .chain(lines)
+        .map(|line| match line {
+            Ok(line) => Ok(Bytes::from(to_csv(&line))),
+            Err(e) => {
+                let e: ApiError = e.into();
+                error!(error = %e, "CSV export failed part way through");
+                Err(io::Error::other(e.to_string()))
+            }
+        });
+
+    Ok(HttpResponse::Ok()
+        .content_type(format!("{}; charset=utf-8", CSV))
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name.to_owned())]
+        })
+        .streaming(futures_util::stream::once(async { Ok::<_, io::Error>(header) }).chain(body)))
+}
+
+/// formats a single CSV record, ending with a newline
+fn to_csv(record: &impl Serialize) -> Vec<u8> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.serialize(record)
+        .expect("CSV lines should always serialize");
+    writer.into_inner()
+        .expect("writing to memory should not fail")
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn requested_format_prefers_query_over_accept() {
+        let req = TestRequest::with_uri("/waitlist?format=json")
+            .insert_header(("Accept", CSV))
+            .to_http_request();
+
+        assert_eq!(ExportFormat::Json, requested_format(&req).unwrap());
+    }
+
+    #[test]
+    fn requested_format_reads_accept() {
+        let req = TestRequest::default()
+            .insert_header(("Accept", "text/csv, application/json;q=0.5"))
+            .to_http_request();
+
+        assert_eq!(ExportFormat::Csv, requested_format(&req).unwrap());
+    }
+
+    #[derive(Serialize)]
+    struct NameLine {
+        name: &'static str
+    }
+
+    impl CsvLine for NameLine {
+        const HEADERS: &'static [&'static str] = &["name"];
+    }
+
+    #[tokio::test]
+    async fn csv_response_streams_headers_then_lines() {
+        let lines = futures_util::stream::iter(vec![Ok::<_, ApiError>(NameLine { name: "Foo" }), Ok(NameLine { name: "Bar" })]);
+
+        let response = csv_response("foo.csv", lines.boxed_local()).await.unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!("\u{FEFF}name\nFoo\nBar\n", body);
+    }
+
+    #[tokio::test]
+    async fn csv_response_starts_with_a_byte_order_mark() {
+        let lines = futures_util::stream::iter(vec![Ok::<_, ApiError>(NameLine { name: "José" })]);
+
+        let response = csv_response("foo.csv", lines.boxed_local()).await.unwrap();
+
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.starts_with(&[0xEF, 0xBB, 0xBF]));
+        assert_eq!("\u{FEFF}name\nJosé\n", body);
+    }
+
+    #[tokio::test]
+    async fn csv_response_given_an_error_first_reports_it() {
+        let lines = futures_util::stream::iter(vec![Err::<NameLine, _>(ApiError::not_found("foo", "Foo", "Foo"))]);
+
+        let result = csv_response("foo.csv", lines.boxed_local()).await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn to_csv_quotes_fields_containing_commas() {
+        let line = ("Doe, John", 1);
+
+        assert_eq!(b"\"Doe, John\",1\n".to_vec(), to_csv(&line));
+    }
+}
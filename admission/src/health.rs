@@ -0,0 +1,112 @@
+// Lets orchestrators tell whether admission is running with /healthz, and
+// whether it can reach everything it needs to serve requests with /readyz.
+// Neither requires a token, as orchestrators don't have one.
+
+use actix_web::{web::{self, ServiceConfig, resource, get}, HttpResponse, http::header::{CacheControl, CacheDirective}};
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use common::health::{HealthReport, Status, check, DEFAULT_CHECK_TIMEOUT};
+
+use crate::{authentication::openid::OpenIdService, remote_complement_provider::RemoteComplementProvider};
+
+/// what /readyz checks, besides OpenID
+pub struct Dependencies {
+    pool: Pool<ConnectionManager>,
+    complement: RemoteComplementProvider
+}
+
+impl Dependencies {
+    pub fn new(pool: Pool<ConnectionManager>, complement: RemoteComplementProvider) -> Self {
+        Self {
+            pool,
+            complement
+        }
+    }
+}
+
+/// must be registered before the /api/v1 scope, so it can be read without a
+/// token
+pub fn configure_health_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        resource("/healthz")
+            .name("liveness")
+            .route(get().to(healthz_handler))
+    );
+    cfg.service(
+        resource("/readyz")
+            .name("readiness")
+            .route(get().to(readyz_handler))
+    );
+}
+
+/// responds as long as the process can serve requests at all
+async fn healthz_handler() -> HttpResponse {
+    respond(HealthReport::live())
+}
+
+/// checks every dependency at once, responding 503 if any of them are down
+async fn readyz_handler(
+    dependencies: web::Data<Dependencies>,
+    openid: web::Data<OpenIdService>
+) -> HttpResponse {
+    let (database, complement, openid) = futures_util::join!(
+        check("database", DEFAULT_CHECK_TIMEOUT, check_database(&dependencies.pool)),
+        check("complement", DEFAULT_CHECK_TIMEOUT, dependencies.complement.check_reachable()),
+        check("openid", DEFAULT_CHECK_TIMEOUT, openid.check_discovery())
+    );
+
+    respond(HealthReport::new(vec![database, complement, openid]))
+}
+
+/// makes sure the pool can hand out a connection which can run a query
+async fn check_database(pool: &Pool<ConnectionManager>) -> Result<(), String> {
+    let mut conn = pool.get()
+        .await
+        .map_err(|e| e.to_string())?;
+    conn.execute("SELECT 1;", &[])
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn respond(report: HealthReport) -> HttpResponse {
+    let mut response = match report.status() {
+        Status::Up => HttpResponse::Ok(),
+        Status::Down => HttpResponse::ServiceUnavailable()
+    };
+    response.insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn respond_given_a_dependency_down_is_unavailable() {
+        let up = check("foo", DEFAULT_CHECK_TIMEOUT, async { Ok::<_, String>(()) }).await;
+        let down = check("bar", DEFAULT_CHECK_TIMEOUT, async { Err(String::from("connection refused")) }).await;
+
+        let response = respond(HealthReport::new(vec![up, down]));
+
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("down", json["status"]);
+        assert_eq!("connection refused", json["dependencies"][1]["error"]);
+    }
+
+    #[tokio::test]
+    async fn check_given_a_slow_dependency_times_it_out() {
+        let slow = async {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            Ok::<_, String>(())
+        };
+
+        let status = check("foo", std::time::Duration::from_millis(1), slow).await;
+
+        assert_eq!(Status::Down, status.status());
+    }
+}
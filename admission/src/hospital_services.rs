@@ -1,34 +1,350 @@
 use std::fmt::Display;
 use async_trait::async_trait;
-use common::hospital::{Hospital, GetHospitalNames, GetHospitalNamesResponse, GetHospitalNamesRequest, HospitalError};
+use chrono::{DateTime, Utc};
+use futures_util::stream::LocalBoxStream;
+use serde::{Deserialize, Serialize};
+use utoipa::{ToSchema, IntoParams};
+use common::{hospital::{Hospital, GetHospitalNames, GetHospitalNamesResponse, GetHospitalNamesRequest, HospitalError}, patient::{Patient, Discharge, Priority}, user::User};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{patient_services::{PatientEventRepository, PatientEvent, PatientEventKind}, pagination::{Page, Cursor, page_size}, export::CsvLine, live_events::EventBroadcaster};
+
+/// the longest name the hospital repository can store
+const MAX_NAME_LENGTH: usize = 16;
+
+/// why patients discharged by closing their hospital were discharged
+pub const CLOSED_DISCHARGE_REASON: &str = "hospital closed";
 
 pub struct HospitalService {
-    repository: Box<dyn HospitalRepository + 'static>
+    repository: Box<dyn HospitalRepository + 'static>,
+    event_repository: Box<dyn PatientEventRepository + 'static>,
+
+    /// tells listeners about events as they are recorded
+    broadcaster: EventBroadcaster
 }
 
 impl HospitalService {
-    pub fn new(repository: impl HospitalRepository + 'static) -> Self {
+    pub fn new(
+        repository: impl HospitalRepository + 'static,
+        event_repository: impl PatientEventRepository + 'static
+    ) -> Self {
         Self {
-            repository: Box::new(repository)
+            repository: Box::new(repository),
+            event_repository: Box::new(event_repository),
+            broadcaster: EventBroadcaster::default()
+        }
+    }
+
+    /// publishes events to the given broadcaster after recording them
+    pub fn with_broadcaster(self, broadcaster: EventBroadcaster) -> Self {
+        Self {
+            broadcaster,
+            ..self
+        }
+    }
+
+    /// Appends the given event to the patient's history, then tells listeners.
+    /// The change it describes has already been stored, so a failed append is
+    /// logged rather than failing the request.
+    async fn record(&self, event: PatientEvent) {
+        if let Err(e) = self.event_repository.append_event(&event).await {
+            error!(patient_id = %event.patient_id(), kind = %event.kind(), error = %e, "Failed to record patient event");
+        }
+        self.broadcaster.publish(&event);
+    }
+
+    /// like record, but for changes to a hospital itself
+    async fn record_hospital_event(&self, event: HospitalEvent) {
+        if let Err(e) = self.repository.append_event(&event).await {
+            error!(hospital = %event.hospital(), kind = %event.kind(), error = %e, "Failed to record hospital event");
         }
     }
 
-    pub async fn get_all_hospitals(&mut self) -> Result<Vec<Hospital>, RepositoryError> {
+    pub async fn get_all_hospitals(&self) -> Result<Vec<Hospital>, RepositoryError> {
         self.repository.get_all_hospitals().await
     }
 
-    pub async fn get_hospital_by_name(&mut self, name: &str) -> Result<Option<Hospital>, RepositoryError> {
+    /// returns a single page of the hospitals matching the given query
+    pub async fn get_hospitals(&self, query: &HospitalQuery) -> Result<Page<Hospital>, RepositoryError> {
+        self.repository.get_hospitals(query).await
+    }
+
+    pub async fn get_hospital_by_name(&self, name: &str) -> Result<Option<Hospital>, RepositoryError> {
         self.repository.get_hospital(name).await
     }
 
-    pub async fn unadmit_patient_from_hospital(&mut self, patient_id: uuid::Uuid, hospital_name: &str) -> Result<Hospital, RepositoryError> {
-        self.repository.remove_patient_from_hospital(patient_id, hospital_name).await
+    /// streams the roster of every hospital matching the given query's name
+    /// filter, ignoring the rest of the query, as exports are never paged
+    pub fn export_roster(&self, query: &HospitalQuery) -> LocalBoxStream<'static, Result<RosterLine, RepositoryError>> {
+        self.repository.stream_roster(query.name_filter(), None)
+    }
+
+    /// streams the roster of the given hospital, starting with an error if
+    /// there is no such hospital
+    pub fn export_hospital_roster(&self, name: &str) -> LocalBoxStream<'static, Result<RosterLine, RepositoryError>> {
+        self.repository.stream_roster(None, Some(name.to_owned()))
+    }
+
+    /// Opens a new hospital with the given name and, optionally, the given
+    /// number of beds. Returns an error if the name is invalid or already
+    /// belongs to another hospital, ignoring case.
+    pub async fn create_hospital(&self, name: &str, capacity: Option<u32>, actor: &User) -> Result<Hospital, HospitalManagementError> {
+        let name = validate_name(name)?;
+        let created = self.repository.create_hospital(name, capacity)
+            .await?;
+        self.record_hospital_event(HospitalEvent::new(HospitalEventKind::Opened, actor, &created))
+            .await;
+        Ok(created)
+    }
+
+    /// Renames the given hospital, keeping its patients. Returns an error if
+    /// there is no such hospital, or if the new name is invalid or already
+    /// belongs to another hospital, ignoring case. If given a version, also
+    /// returns an error if the hospital has changed since that version.
+    pub async fn rename_hospital(&self, name: &str, new_name: &str, expected_version: Option<u64>, actor: &User) -> Result<Hospital, HospitalManagementError> {
+        let new_name = validate_name(new_name)?;
+        let hospital = self.repository.get_hospital(name)
+            .await?
+            .ok_or_else(|| HospitalManagementError::NotFound(name.to_owned()))?;
+        check_version(&hospital.name(), hospital.version(), expected_version)?;
+
+        let renamed = self.repository.rename_hospital(name, new_name, expected_version)
+            .await?;
+        self.record_hospital_event(HospitalEvent::new(HospitalEventKind::Renamed, actor, &renamed).with_previous_name(&hospital.name()))
+            .await;
+        Ok(renamed)
+    }
+
+    /// Closes the given hospital, so patients can no longer be admitted to it.
+    /// If patients are still admitted, this refuses to close the hospital
+    /// unless given a policy for where they should go. The patients are moved
+    /// and the hospital closed all at once, so a failure changes nothing.
+    pub async fn close_hospital(&self, name: &str, relocation: Option<RelocationPolicy>, actor: &User) -> Result<(), HospitalManagementError> {
+        let hospital = self.repository.get_hospital(name)
+            .await?
+            .ok_or_else(|| HospitalManagementError::NotFound(name.to_owned()))?;
+        let patients = hospital.patients();
+        if relocation.is_none() && !patients.is_empty() {
+            return Err(HospitalManagementError::StillAdmitted(hospital.name(), patients.len()));
+        }
+
+        let patient_ids: Vec<Uuid> = patients.iter()
+            .map(|p| p.id().expect("admitted patient should have an ID"))
+            .collect();
+        let closed_at = Utc::now();
+        self.repository.close_hospital(&hospital.name(), relocation, &patient_ids, closed_at)
+            .await?;
+
+        // only record the moves once they are stored
+        if let Some(relocation) = relocation {
+            for patient in patients {
+                let (kind, after) = match relocation {
+                    RelocationPolicy::Waitlist => (PatientEventKind::Waitlisted, patient.waitlisted()),
+                    RelocationPolicy::Discharge => (PatientEventKind::Discharged, patient.discharged(Discharge::new(&hospital.name(), CLOSED_DISCHARGE_REASON, closed_at)))
+                };
+                self.record(PatientEvent::new(kind, actor, Some(&patient), &after))
+                    .await;
+            }
+        }
+        self.record_hospital_event(HospitalEvent::new(HospitalEventKind::Closed, actor, &hospital))
+            .await;
+        Ok(())
+    }
+
+    /// Discharges the given patient from the given hospital for the given
+    /// reason. The patient is kept, along with their history, but no longer
+    /// appears on the hospital's roster. Returns an error if the patient is not
+    /// admitted to that hospital. If given a version, the patient is only
+    /// discharged if they have not changed since that version.
+    pub async fn unadmit_patient_from_hospital(&self, patient_id: uuid::Uuid, hospital_name: &str, reason: &str, expected_version: Option<u64>, actor: &User) -> Result<Hospital, RepositoryError> {
+        // look the patient up first, so we can record what they looked like
+        let hospital = self.repository.get_hospital(hospital_name)
+            .await?
+            .ok_or_else(|| RepositoryError::invalid_hospital_name(hospital_name))?;
+        let before = hospital.patients()
+            .into_iter()
+            .find(|p| p.id() == Some(patient_id))
+            .ok_or_else(|| RepositoryError::PatientNotAdmitted(patient_id, hospital.name()))?;
+        check_version(&patient_id.to_string(), before.version(), expected_version)?;
+
+        let now = Utc::now();
+        let hospital = self.repository.discharge_patient(patient_id, hospital_name, reason, now, expected_version)
+            .await?;
+
+        let after = before.discharged(Discharge::new(&hospital.name(), reason, now));
+        self.record(PatientEvent::new(PatientEventKind::Discharged, actor, Some(&before), &after))
+            .await;
+
+        Ok(hospital)
+    }
+
+    /// Moves the given patient from the hospital they are admitted to into
+    /// another hospital, then returns the transferred patient. Fails without
+    /// moving the patient if they may not be admitted to the target hospital,
+    /// or if it has no free beds. If given a version, the patient is only moved
+    /// if they have not changed since that version.
+    pub async fn transfer_patient(&self, patient_id: Uuid, from: &str, to: &str, expected_version: Option<u64>, actor: &User) -> Result<Patient, TransferError> {
+        let source = self.repository.get_hospital(from)
+            .await?
+            .ok_or_else(|| TransferError::HospitalNotFound(from.to_owned()))?;
+
+        let patient = source.patients()
+            .into_iter()
+            .find(|p| p.id() == Some(patient_id))
+            .ok_or_else(|| TransferError::PatientNotAdmitted(patient_id, source.name()))?;
+        check_version(&patient_id.to_string(), patient.version(), expected_version)?;
+
+        let target = self.repository.get_hospital(to)
+            .await?
+            .ok_or_else(|| TransferError::HospitalNotFound(to.to_owned()))?;
+
+        if source == target {
+            return Err(TransferError::SameHospital(target.name()));
+        }
+        if patient.disallowed_hospitals().iter().any(|h| h.eq_ignore_ascii_case(&target.name())) {
+            return Err(TransferError::Disallowed(patient_id, target.name()));
+        }
+        if !target.has_room() {
+            return Err(TransferError::HospitalFull(target.name()));
+        }
+
+        let transferred = self.repository.transfer_patient(patient_id, &source.name(), &target.name(), expected_version)
+            .await?
+            .patients()
+            .into_iter()
+            .find(|p| p.id() == Some(patient_id))
+            .ok_or_else(|| TransferError::Repository(RepositoryError::other("transferred patient is missing from target hospital")))?;
+
+        self.record(PatientEvent::new(PatientEventKind::Transferred, actor, Some(&patient), &transferred))
+            .await;
+
+        Ok(transferred)
+    }
+}
+
+/// Returns an error if the client expects a different version of a hospital or
+/// patient than the one stored. This catches most stale writes before doing
+/// any work, while the repository catches any which happen in between.
+fn check_version(name: &str, stored: Option<u64>, expected: Option<u64>) -> Result<(), RepositoryError> {
+    match expected {
+        Some(expected) if stored != Some(expected) => Err(RepositoryError::StaleVersion(name.to_owned())),
+        _ => Ok(())
+    }
+}
+
+/// trims the given hospital name, or returns an error if it cannot be stored
+fn validate_name(name: &str) -> Result<&str, HospitalManagementError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(HospitalManagementError::InvalidName(name.to_owned()));
+    }
+    Ok(name)
+}
+
+/// which page of hospitals to list, as given by clients
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct HospitalQuery {
+    /// only list hospitals whose names contain this, ignoring case
+    name: Option<String>,
+    sort: Option<HospitalSort>,
+
+    /// where the previous page ended, or None for the first page
+    #[param(value_type = Option<String>)]
+    cursor: Option<Cursor>,
+    limit: Option<usize>,
+
+    /// whether to list each hospital's patients, which defaults to true
+    include_patients: Option<bool>
+}
+
+impl HospitalQuery {
+    pub fn name_filter(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    pub fn sort(&self) -> HospitalSort {
+        self.sort.unwrap_or_default()
+    }
+
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.cursor.clone()
+    }
+
+    /// the page size, kept within the allowed range
+    pub fn limit(&self) -> usize {
+        page_size(self.limit)
+    }
+
+    pub fn include_patients(&self) -> bool {
+        self.include_patients.unwrap_or(true)
+    }
+}
+
+/// the orders hospitals can be listed in, where a leading - means descending
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum HospitalSort {
+    #[default]
+    #[serde(rename = "name")]
+    Name,
+
+    #[serde(rename = "-name")]
+    NameDescending,
+
+    /// hospitals without a bed limit are treated as the largest
+    #[serde(rename = "capacity")]
+    Capacity,
+
+    #[serde(rename = "-capacity")]
+    CapacityDescending
+}
+
+impl HospitalSort {
+    pub fn is_descending(&self) -> bool {
+        matches!(self, Self::NameDescending | Self::CapacityDescending)
+    }
+}
+
+/// a patient admitted to a hospital, as a line of a roster export
+#[derive(Debug, Clone, Serialize)]
+pub struct RosterLine {
+    hospital: String,
+    patient_id: Uuid,
+    patient_name: String,
+    priority: Priority,
+    admitted_at: Option<DateTime<Utc>>
+}
+
+impl RosterLine {
+    pub fn new(hospital: &str, patient_id: Uuid, patient_name: &str, priority: Priority, admitted_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            hospital: hospital.to_owned(),
+            patient_id,
+            patient_name: patient_name.to_owned(),
+            priority,
+            admitted_at
+        }
     }
 }
 
+impl CsvLine for RosterLine {
+    const HEADERS: &'static [&'static str] = &["hospital", "patientId", "patientName", "priority", "admittedAt"];
+}
+
+/// what to do with patients still admitted to a hospital when it closes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RelocationPolicy {
+    /// returns them to the waitlist, keeping their original place in line
+    Waitlist,
+    Discharge
+}
+
 #[async_trait]
 impl GetHospitalNames for HospitalService {
-    async fn get_hospital_names(&mut self, _request: GetHospitalNamesRequest) -> Result<GetHospitalNamesResponse, HospitalError> {
+    async fn get_hospital_names(&self, _request: GetHospitalNamesRequest) -> Result<GetHospitalNamesResponse, HospitalError> {
         self.get_all_hospitals()
             .await
             .map(|hospitals| hospitals.iter().map(Hospital::name).collect::<Vec<String>>())
@@ -37,10 +353,113 @@ impl GetHospitalNames for HospitalService {
     }
 }
 
+/// the kinds of changes recorded in a hospital's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HospitalEventKind {
+    Opened,
+    Renamed,
+    Closed
+}
+
+impl Display for HospitalEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Opened => write!(f, "opened"),
+            Self::Renamed => write!(f, "renamed"),
+            Self::Closed => write!(f, "closed")
+        }
+    }
+}
+
+/// a single change to a hospital itself, rather than to its patients,
+/// recording who made it and when
+#[derive(Debug, Clone)]
+pub struct HospitalEvent {
+    hospital_id: Option<u32>,
+    hospital: String,
+
+    /// the hospital's name before it was renamed, or None for other events
+    previous_name: Option<String>,
+    kind: HospitalEventKind,
+
+    /// the email of the user who made this change
+    actor: String,
+    occurred_at: DateTime<Utc>
+}
+
+impl HospitalEvent {
+    /// creates an event about the given hospital which happened just now
+    pub fn new(kind: HospitalEventKind, actor: &User, hospital: &Hospital) -> Self {
+        Self {
+            hospital_id: hospital.id(),
+            hospital: hospital.name(),
+            previous_name: None,
+            kind,
+            actor: actor.email(),
+            occurred_at: Utc::now()
+        }
+    }
+
+    /// returns a copy of this event, except recording the hospital's name
+    /// before this change
+    pub fn with_previous_name(&self, previous_name: &str) -> Self {
+        Self {
+            previous_name: Some(previous_name.to_owned()),
+            ..self.clone()
+        }
+    }
+
+    pub fn hospital_id(&self) -> Option<u32> {
+        self.hospital_id
+    }
+
+    pub fn hospital(&self) -> String {
+        self.hospital.to_owned()
+    }
+
+    pub fn previous_name(&self) -> Option<String> {
+        self.previous_name.to_owned()
+    }
+
+    pub fn kind(&self) -> HospitalEventKind {
+        self.kind
+    }
+
+    pub fn actor(&self) -> String {
+        self.actor.to_owned()
+    }
+
+    pub fn occurred_at(&self) -> DateTime<Utc> {
+        self.occurred_at
+    }
+}
+
 #[derive(Debug)]
 pub enum RepositoryError {
     Other(String),
     InvalidHospitalName(String),
+
+    /// another hospital already has this name, ignoring case
+    DuplicateHospitalName(String),
+
+    /// the client gave a page cursor which does not fit their sort order
+    InvalidCursor(String),
+
+    /// the hospital or patient with this name or ID was changed since the
+    /// version being written was read
+    StaleVersion(String),
+
+    /// a patient could not be moved into this hospital, as another request
+    /// took its last free bed first
+    HospitalFull(String),
+
+    /// the patient with this ID is not admitted to the hospital with this
+    /// name, such as when another request discharged or moved them first
+    PatientNotAdmitted(Uuid, String),
+
+    /// the hospital's name and how many patients are still admitted to it,
+    /// which stopped it from being closed
+    HospitalNotEmpty(String, usize),
     Tiberius(tiberius::error::Error)
 }
 
@@ -63,26 +482,139 @@ impl Display for RepositoryError {
         match self {
             Self::Other(message) => write!(f, "Other error: {}", message),
             Self::InvalidHospitalName(name) => write!(f, "Invalid hospital name: {}", name),
+            Self::DuplicateHospitalName(name) => write!(f, "Duplicate hospital name: {}", name),
+            Self::InvalidCursor(message) => write!(f, "Invalid cursor: {}", message),
+            Self::StaleVersion(name) => write!(f, "{} was changed by someone else; fetch it again and retry", name),
+            Self::HospitalFull(name) => write!(f, "{} has no free beds", name),
+            Self::PatientNotAdmitted(id, hospital) => write!(f, "Patient {} is not admitted to {}", id, hospital),
+            Self::HospitalNotEmpty(name, count) => write!(f, "{} still has {} patient(s) admitted", name, count),
             Self::Tiberius(inner) => write!(f, "Tiberius Error: {}", inner)
         }
     }
 }
 
+impl std::error::Error for RepositoryError {}
+
+/// reasons a patient cannot be transferred between hospitals
+#[derive(Debug)]
+pub enum TransferError {
+    HospitalNotFound(String),
+    PatientNotAdmitted(Uuid, String),
+    SameHospital(String),
+    Disallowed(Uuid, String),
+    HospitalFull(String),
+    Repository(RepositoryError)
+}
+
+impl From<RepositoryError> for TransferError {
+    fn from(inner: RepositoryError) -> Self {
+        match inner {
+            RepositoryError::HospitalFull(name) => Self::HospitalFull(name),
+            RepositoryError::PatientNotAdmitted(id, hospital) => Self::PatientNotAdmitted(id, hospital),
+            inner => Self::Repository(inner)
+        }
+    }
+}
+
+impl Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HospitalNotFound(name) => write!(f, "No hospital named {}", name),
+            Self::PatientNotAdmitted(id, hospital) => write!(f, "Patient {} is not admitted to {}", id, hospital),
+            Self::SameHospital(name) => write!(f, "Patient is already admitted to {}", name),
+            Self::Disallowed(id, hospital) => write!(f, "Patient {} may not be admitted to {}", id, hospital),
+            Self::HospitalFull(name) => write!(f, "{} has no free beds", name),
+            Self::Repository(inner) => write!(f, "Repository error: {}", inner)
+        }
+    }
+}
+
+/// reasons a hospital cannot be opened, renamed, or closed
+#[derive(Debug)]
+pub enum HospitalManagementError {
+    NotFound(String),
+    InvalidName(String),
+    NameTaken(String),
+
+    /// the hospital's name and how many patients are still admitted to it
+    StillAdmitted(String, usize),
+    Repository(RepositoryError)
+}
+
+impl From<RepositoryError> for HospitalManagementError {
+    fn from(inner: RepositoryError) -> Self {
+        match inner {
+            RepositoryError::InvalidHospitalName(name) => Self::NotFound(name),
+            RepositoryError::DuplicateHospitalName(name) => Self::NameTaken(name),
+            RepositoryError::HospitalNotEmpty(name, count) => Self::StillAdmitted(name, count),
+            other => Self::Repository(other)
+        }
+    }
+}
+
+impl Display for HospitalManagementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "No hospital named {}", name),
+            Self::InvalidName(name) => write!(f, "Hospital names must be between 1 and {} characters long: {}", MAX_NAME_LENGTH, name),
+            Self::NameTaken(name) => write!(f, "A hospital named {} already exists", name),
+            Self::StillAdmitted(name, count) => write!(f, "{} still has {} patient(s) admitted; choose a relocation policy to close it anyway", name, count),
+            Self::Repository(inner) => write!(f, "Repository error: {}", inner)
+        }
+    }
+}
+
 /// designates something as an interface into a backing store of hospitals
 #[async_trait] // stable Rust does not yet allow async function in traits, which this fixes
 pub trait HospitalRepository: Send + Sync { // must be safe to have multiple threads accessing at the same time
 
     /// retrieves all hospitals from the backing store, then returns them, or
     /// an error if applicable
-    async fn get_all_hospitals(&mut self) -> Result<Vec<Hospital>, RepositoryError>;
+    async fn get_all_hospitals(&self) -> Result<Vec<Hospital>, RepositoryError>;
+
+    /// filters, sorts, and pages hospitals in the backing store, rather than
+    /// retrieving all of them
+    async fn get_hospitals(&self, query: &HospitalQuery) -> Result<Page<Hospital>, RepositoryError>;
 
     /// returns a single hospital with the given name, or returns an error when 
     /// applicable. Note that this returns None if no such hospital exists
-    async fn get_hospital(&mut self, name: &str) -> Result<Option<Hospital>, RepositoryError>;
+    async fn get_hospital(&self, name: &str) -> Result<Option<Hospital>, RepositoryError>;
+
+    /// Streams a line for each patient admitted to an open hospital, sorted by
+    /// hospital then patient name. Only includes hospitals whose names contain
+    /// the given filter, ignoring case, or the hospital with the given name, if
+    /// given. Starts with an error if there is no hospital with that name.
+    fn stream_roster(&self, name_filter: Option<String>, hospital_name: Option<String>) -> LocalBoxStream<'static, Result<RosterLine, RepositoryError>>;
+
+    /// discharges the given patient from the given hospital, recording when
+    /// and why. Returns an error if the hospital is not stored, or if the
+    /// patient is not admitted to it. If given a version, the patient is only
+    /// discharged if they have not changed since that version.
+    async fn discharge_patient(&self, patient_id: uuid::Uuid, hospital_name: &str, reason: &str, discharged_at: DateTime<Utc>, version: Option<u64>) -> Result<Hospital, RepositoryError>;
+
+    /// moves the given patient from one hospital to another, then returns the
+    /// hospital they were moved to. Does not check whether the patient is
+    /// allowed into the target hospital. If given a version, the patient is
+    /// only moved if they have not changed since that version.
+    async fn transfer_patient(&self, patient_id: uuid::Uuid, from: &str, to: &str, version: Option<u64>) -> Result<Hospital, RepositoryError>;
+
+    /// stores a new hospital, returning an error if another hospital already
+    /// has the same name, ignoring case
+    async fn create_hospital(&self, name: &str, capacity: Option<u32>) -> Result<Hospital, RepositoryError>;
+
+    /// renames the given hospital, returning an error if it does not exist or
+    /// if another hospital already has the new name, ignoring case. If given
+    /// a version, the hospital is only renamed if it has not changed since.
+    async fn rename_hospital(&self, name: &str, new_name: &str, version: Option<u64>) -> Result<Hospital, RepositoryError>;
 
-    /// removes the given patient from the given hospital. Returns an error if
-    /// the hospital is not stored. Note this method should be idempotent.
-    async fn remove_patient_from_hospital(&mut self, patient_id: uuid::Uuid, hospital_name: &str) -> Result<Hospital, RepositoryError>;
+    /// closes the given hospital, so it is no longer returned by this
+    /// repository. Its name stays reserved, so old records remain unambiguous.
+    /// First relocates the given patients by the given policy, all in one
+    /// transaction, so nothing changes if any other patient is still admitted.
+    async fn close_hospital(&self, name: &str, relocation: Option<RelocationPolicy>, patient_ids: &[uuid::Uuid], closed_at: DateTime<Utc>) -> Result<(), RepositoryError>;
+
+    /// appends the given event to its hospital's history
+    async fn append_event(&self, event: &HospitalEvent) -> Result<(), RepositoryError>;
 }
 
 #[cfg(test)]
@@ -90,20 +622,43 @@ pub mod tests {
     use super::*;
     use async_trait::async_trait;
     use mockall::mock;
+    use crate::patient_services::{PatientError, tests::{events_accepting, MockEvents}};
 
     mock! {
-        Dummy {
+        pub Dummy {
 
         }
 
         #[async_trait]
         impl HospitalRepository for Dummy {
-            async fn get_all_hospitals(&mut self) -> Result<Vec<Hospital>, RepositoryError>;
-            async fn get_hospital(&mut self, name: &str) -> Result<Option<Hospital>, RepositoryError>;
-            async fn remove_patient_from_hospital(&mut self, patient_id: uuid::Uuid, hospital_name: &str) -> Result<Hospital, RepositoryError>;
+            async fn get_all_hospitals(&self) -> Result<Vec<Hospital>, RepositoryError>;
+            async fn get_hospitals(&self, query: &HospitalQuery) -> Result<Page<Hospital>, RepositoryError>;
+            async fn get_hospital(&self, name: &str) -> Result<Option<Hospital>, RepositoryError>;
+            fn stream_roster(&self, name_filter: Option<String>, hospital_name: Option<String>) -> LocalBoxStream<'static, Result<RosterLine, RepositoryError>>;
+            async fn discharge_patient(&self, patient_id: uuid::Uuid, hospital_name: &str, reason: &str, discharged_at: DateTime<Utc>, version: Option<u64>) -> Result<Hospital, RepositoryError>;
+            async fn transfer_patient(&self, patient_id: uuid::Uuid, from: &str, to: &str, version: Option<u64>) -> Result<Hospital, RepositoryError>;
+            async fn create_hospital(&self, name: &str, capacity: Option<u32>) -> Result<Hospital, RepositoryError>;
+            async fn rename_hospital(&self, name: &str, new_name: &str, version: Option<u64>) -> Result<Hospital, RepositoryError>;
+            async fn close_hospital(&self, name: &str, relocation: Option<RelocationPolicy>, patient_ids: &[uuid::Uuid], closed_at: DateTime<Utc>) -> Result<(), RepositoryError>;
+            async fn append_event(&self, event: &HospitalEvent) -> Result<(), RepositoryError>;
         }
     }
 
+    /// mocks a repository containing the given hospitals
+    fn repository_with(hospitals: Vec<Hospital>) -> MockDummy {
+        let mut mock = MockDummy::new();
+        mock
+            .expect_get_hospital()
+            .returning(move |name| Ok(hospitals.iter().find(|h| h.name().eq_ignore_ascii_case(name)).cloned()));
+        mock
+    }
+
+    fn hospital_with(id: u32, name: &str, patient: &Patient) -> Hospital {
+        let mut h = Hospital::new(name).with_id(id);
+        h.add_patient(patient.admit_to(name));
+        h
+    }
+
     #[tokio::test]
     async fn get_all_hospitals_forwards_to_repository() {
         let mut mock = MockDummy::new();
@@ -111,7 +666,7 @@ pub mod tests {
             .expect_get_all_hospitals()
             .once()
             .returning(|| Ok(Vec::new()));
-        let mut sut = HospitalService::new(mock);
+        let sut = HospitalService::new(mock, events_accepting());
 
         let result = sut.get_all_hospitals().await;
 
@@ -125,7 +680,7 @@ pub mod tests {
             .expect_get_hospital()
             .once()
             .returning(|_by| Ok(None));
-        let mut sut = HospitalService::new(mock);
+        let sut = HospitalService::new(mock, events_accepting());
 
         let result = sut.get_hospital_by_name("Foo").await;
 
@@ -134,15 +689,279 @@ pub mod tests {
 
     #[tokio::test]
     async fn unadmit_patient_forwards_to_repository() {
-        let mut mock = MockDummy::new();
+        let patient = Patient::new("Foo").with_random_id();
+        let mut mock = repository_with(vec![hospital_with(1, "Foo", &patient)]);
         mock
-            .expect_remove_patient_from_hospital()
+            .expect_discharge_patient()
             .once()
-            .returning(|_, _| Err(RepositoryError::other("")));
-        let mut sut = HospitalService::new(mock);
+            .returning(|_, _, _, _, _| Err(RepositoryError::other("")));
+        let sut = HospitalService::new(mock, events_accepting());
 
-        let result = sut.unadmit_patient_from_hospital(uuid::Uuid::new_v4(), "Foo").await;
+        let result = sut.unadmit_patient_from_hospital(patient.id().unwrap(), "Foo", "Bar", None, &User::new("Baz")).await;
 
         assert!(result.is_err());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn unadmit_patient_given_patient_not_admitted_does_not_discharge() {
+        let mut mock = repository_with(vec![Hospital::new("Foo").with_id(1)]);
+        mock
+            .expect_discharge_patient()
+            .never();
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.unadmit_patient_from_hospital(uuid::Uuid::new_v4(), "Foo", "Bar", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(RepositoryError::PatientNotAdmitted(_, _))));
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_a_disallowed_target_does_not_transfer() {
+        let patient = Patient::new("Foo")
+            .with_random_id()
+            .with_disallowed_hospitals(&[String::from("Bar")].into_iter().collect());
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            Hospital::new("Bar").with_id(2)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .never();
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "bar", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(TransferError::Disallowed(_, _))));
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_a_full_target_does_not_transfer() {
+        let patient = Patient::new("Foo").with_random_id();
+        let other = Patient::new("Bar").with_random_id();
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            hospital_with(2, "Bar", &other).with_capacity(1)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .never();
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "Bar", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(TransferError::HospitalFull(_))));
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_an_allowed_target_forwards_to_repository() {
+        let patient = Patient::new("Foo").with_random_id();
+        let moved = hospital_with(2, "Bar", &patient);
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            Hospital::new("Bar").with_id(2)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .once()
+            .return_once(|_, _, _, _| Ok(moved));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "Bar", None, &User::new("Baz")).await;
+
+        assert_eq!(Some(String::from("Bar")), result.expect("transfer should succeed").admitted_to());
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_target_fills_up_first_reports_it_full() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            Hospital::new("Bar").with_id(2).with_capacity(1)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .once()
+            .returning(|_, _, to, _| Err(RepositoryError::HospitalFull(to.to_owned())));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "Bar", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(TransferError::HospitalFull(_))));
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_patient_leaves_first_reports_them_not_admitted() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            Hospital::new("Bar").with_id(2)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .once()
+            .returning(|id, from, _, _| Err(RepositoryError::PatientNotAdmitted(id, from.to_owned())));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "Bar", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(TransferError::PatientNotAdmitted(_, _))));
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_a_stale_version_does_not_transfer() {
+        let patient = Patient::new("Foo").with_random_id().with_version(2);
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            Hospital::new("Bar").with_id(2)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .never();
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "Bar", Some(1), &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(TransferError::Repository(RepositoryError::StaleVersion(_)))));
+    }
+
+    #[tokio::test]
+    async fn create_hospital_given_a_blank_name_does_not_create() {
+        let mut mock = MockDummy::new();
+        mock
+            .expect_create_hospital()
+            .never();
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.create_hospital("  ", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(HospitalManagementError::InvalidName(_))));
+    }
+
+    #[tokio::test]
+    async fn create_hospital_given_a_taken_name_returns_name_taken() {
+        let mut mock = MockDummy::new();
+        mock
+            .expect_create_hospital()
+            .once()
+            .returning(|name, _| Err(RepositoryError::DuplicateHospitalName(name.to_owned())));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.create_hospital("napa", Some(3), &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(HospitalManagementError::NameTaken(_))));
+    }
+
+    #[tokio::test]
+    async fn close_hospital_given_admitted_patients_and_no_policy_does_not_close() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut mock = repository_with(vec![hospital_with(1, "Foo", &patient)]);
+        mock
+            .expect_close_hospital()
+            .never();
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.close_hospital("Foo", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(HospitalManagementError::StillAdmitted(_, 1))));
+    }
+
+    #[tokio::test]
+    async fn close_hospital_given_a_patient_admitted_meanwhile_does_not_close() {
+        let mut mock = repository_with(vec![Hospital::new("Foo").with_id(1)]);
+        mock
+            .expect_close_hospital()
+            .once()
+            .returning(|name, _, _, _| Err(RepositoryError::HospitalNotEmpty(name.to_owned(), 1)));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.close_hospital("Foo", None, &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(HospitalManagementError::StillAdmitted(_, 1))));
+    }
+
+    #[tokio::test]
+    async fn close_hospital_given_the_close_fails_records_no_relocations() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut mock = repository_with(vec![hospital_with(1, "Foo", &patient)]);
+        mock
+            .expect_close_hospital()
+            .once()
+            .returning(|name, _, _, _| Err(RepositoryError::HospitalNotEmpty(name.to_owned(), 1)));
+        mock
+            .expect_append_event()
+            .never();
+        let mut events = MockEvents::new();
+        events
+            .expect_append_event()
+            .never();
+        let sut = HospitalService::new(mock, events);
+
+        let result = sut.close_hospital("Foo", Some(RelocationPolicy::Discharge), &User::new("Baz")).await;
+
+        assert!(matches!(result, Err(HospitalManagementError::StillAdmitted(_, 1))));
+    }
+
+    #[tokio::test]
+    async fn close_hospital_given_waitlist_policy_waitlists_patients_as_it_closes() {
+        let patient = Patient::new("Foo").with_random_id();
+        let id = patient.id().unwrap();
+        let mut mock = repository_with(vec![hospital_with(1, "Foo", &patient)]);
+        mock
+            .expect_close_hospital()
+            .once()
+            .withf(move |name, relocation, ids, _| name == "Foo" && *relocation == Some(RelocationPolicy::Waitlist) && ids == [id])
+            .returning(|_, _, _, _| Ok(()));
+        mock
+            .expect_append_event()
+            .once()
+            .withf(|e| e.kind() == HospitalEventKind::Closed && e.hospital() == "Foo")
+            .returning(|_| Ok(()));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.close_hospital("foo", Some(RelocationPolicy::Waitlist), &User::new("Baz")).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rename_hospital_records_the_previous_name() {
+        let mut mock = repository_with(vec![Hospital::new("Foo").with_id(1)]);
+        mock
+            .expect_rename_hospital()
+            .once()
+            .returning(|_, new_name, _| Ok(Hospital::new(new_name).with_id(1)));
+        mock
+            .expect_append_event()
+            .once()
+            .withf(|e| e.kind() == HospitalEventKind::Renamed && e.hospital() == "Bar" && e.previous_name().as_deref() == Some("Foo"))
+            .returning(|_| Ok(()));
+        let sut = HospitalService::new(mock, events_accepting());
+
+        let result = sut.rename_hospital("foo", "Bar", None, &User::new("Baz")).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn transfer_patient_given_the_event_fails_to_append_still_transfers() {
+        let patient = Patient::new("Foo").with_random_id();
+        let moved = hospital_with(2, "Bar", &patient);
+        let mut mock = repository_with(vec![
+            hospital_with(1, "Foo", &patient),
+            Hospital::new("Bar").with_id(2)
+        ]);
+        mock
+            .expect_transfer_patient()
+            .once()
+            .return_once(|_, _, _, _| Ok(moved));
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .once()
+            .returning(|_| Err(PatientError::Repository("connection reset".into())));
+        let sut = HospitalService::new(mock, events);
+
+        let result = sut.transfer_patient(patient.id().unwrap(), "Foo", "Bar", None, &User::new("Baz")).await;
+
+        assert!(result.is_ok());
+    }
+}
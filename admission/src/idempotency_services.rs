@@ -0,0 +1,348 @@
+// Idempotency keys let clients safely retry requests which create things. The
+// first response to each key is stored, and any retry with the same key gets
+// that response back instead of acting again.
+// https://datatracker.ietf.org/doc/draft-ietf-httpapi-idempotency-key-header/
+
+use std::{error::Error, fmt::Display, collections::HashMap, sync::{Arc, Mutex as SyncMutex}};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc, Duration};
+use common::user::User;
+use sha2::{Sha256, Digest};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// the header clients send their idempotency key in
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// added to responses which were replayed rather than freshly made
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// the longest key a client may send
+pub const MAX_KEY_LENGTH: usize = 64;
+
+/// how long keys are remembered if not otherwise configured
+pub const DEFAULT_WINDOW_HOURS: i64 = 24;
+
+pub struct IdempotencyService {
+    repository: Box<dyn IdempotencyRepository>,
+
+    /// how long a stored response can be replayed for
+    window: Duration,
+
+    /// one lock per actor and key with a request in progress
+    in_progress: SyncMutex<HashMap<(String, String), KeyLock>>
+}
+
+/// held while a request with an idempotency key runs
+type KeyLock = Arc<Mutex<()>>;
+
+impl IdempotencyService {
+    pub fn new<T>(repository: T) -> Self
+    where
+        T: IdempotencyRepository + 'static
+    {
+        Self {
+            repository: Box::new(repository),
+            window: Duration::hours(DEFAULT_WINDOW_HOURS),
+            in_progress: SyncMutex::default()
+        }
+    }
+
+    /// returns a copy of this service, except keys are remembered for the
+    /// given amount of time
+    pub fn with_window(self, window: Duration) -> Self {
+        Self {
+            window,
+            ..self
+        }
+    }
+
+    /// Waits for any other request with the same key to finish, then holds
+    /// the key until the returned guard is dropped, so a retry sent while the
+    /// original is still running waits for it rather than running alongside
+    /// it. Requests with different keys don't wait for each other.
+    pub async fn lock_key(&self, request: &IdempotentRequest) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut in_progress = self.in_progress.lock()
+                .expect("idempotency key locks should never be poisoned");
+            // forget keys nobody is holding, so the map doesn't grow forever
+            in_progress.retain(|_, lock| Arc::strong_count(lock) > 1);
+            in_progress.entry((request.actor.to_owned(), request.key.to_owned()))
+                .or_default()
+                .clone()
+        };
+        lock.lock_owned().await
+    }
+
+    /// Returns the response previously stored for the given request's key, or
+    /// None if the key is new or has expired. It is an error to reuse a key
+    /// for a different request.
+    pub async fn find_response(&self, request: &IdempotentRequest) -> Result<Option<StoredResponse>, IdempotencyError> {
+        let stored = self.repository.get_response(&request.key, &request.actor)
+            .await?
+            .filter(|stored| stored.created_at + self.window > Utc::now());
+
+        match stored {
+            Some(stored) if stored.endpoint != request.endpoint || stored.fingerprint != request.fingerprint => {
+                Err(IdempotencyError::KeyReused(request.key.to_owned()))
+            },
+            other => Ok(other)
+        }
+    }
+
+    /// remembers the response to the given request, so retries can replay it
+    pub async fn store_response(&self, request: &IdempotentRequest, status: u16, body: &str) -> Result<(), IdempotencyError> {
+        // expired keys may be reused, so clear them out first
+        self.repository.delete_responses_before(Utc::now() - self.window)
+            .await?;
+
+        let response = StoredResponse {
+            key: request.key.to_owned(),
+            actor: request.actor.to_owned(),
+            endpoint: request.endpoint.to_owned(),
+            fingerprint: request.fingerprint.to_owned(),
+            status,
+            body: body.to_owned(),
+            created_at: Utc::now()
+        };
+        self.repository.store_response(&response).await
+    }
+}
+
+/// a request made with an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotentRequest {
+    key: String,
+
+    /// the email of the user who made the request, as keys are per user
+    actor: String,
+
+    /// such as "POST /waitlist"
+    endpoint: String,
+
+    /// a hash of the request's contents, so reusing a key for a different
+    /// request can be detected
+    fingerprint: String
+}
+
+impl IdempotentRequest {
+    pub fn new(key: &str, actor: &User, endpoint: &str, contents: &[u8]) -> Result<Self, IdempotencyError> {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH || !key.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(IdempotencyError::InvalidKey(key.to_owned()));
+        }
+
+        let fingerprint = Sha256::digest(contents)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Ok(Self {
+            key: key.to_owned(),
+            actor: actor.email(),
+            endpoint: endpoint.to_owned(),
+            fingerprint
+        })
+    }
+}
+
+/// the first response made to an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+    key: String,
+    actor: String,
+    endpoint: String,
+    fingerprint: String,
+    status: u16,
+
+    /// the JSON response body
+    body: String,
+    created_at: DateTime<Utc>
+}
+
+impl StoredResponse {
+    /// recreates a response which was previously stored
+    pub fn restore(
+        key: &str,
+        actor: &str,
+        endpoint: &str,
+        fingerprint: &str,
+        status: u16,
+        body: &str,
+        created_at: DateTime<Utc>
+    ) -> Self {
+        Self {
+            key: key.to_owned(),
+            actor: actor.to_owned(),
+            endpoint: endpoint.to_owned(),
+            fingerprint: fingerprint.to_owned(),
+            status,
+            body: body.to_owned(),
+            created_at
+        }
+    }
+
+    pub fn key(&self) -> String {
+        self.key.to_owned()
+    }
+
+    pub fn actor(&self) -> String {
+        self.actor.to_owned()
+    }
+
+    pub fn endpoint(&self) -> String {
+        self.endpoint.to_owned()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        self.fingerprint.to_owned()
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn body(&self) -> String {
+        self.body.to_owned()
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+/// backing store for responses to idempotent requests
+#[async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    async fn get_response(&self, key: &str, actor: &str) -> Result<Option<StoredResponse>, IdempotencyError>;
+    async fn store_response(&self, response: &StoredResponse) -> Result<(), IdempotencyError>;
+
+    /// forgets every response stored before the given time
+    async fn delete_responses_before(&self, cutoff: DateTime<Utc>) -> Result<(), IdempotencyError>;
+}
+
+#[derive(Debug)]
+pub enum IdempotencyError {
+    InvalidKey(String),
+
+    /// the key was already used for a different request
+    KeyReused(String),
+    Repository(Box<dyn Error + 'static>)
+}
+
+impl IdempotencyError {
+    pub fn repository(inner: impl Error + 'static) -> Self {
+        Self::Repository(Box::new(inner))
+    }
+}
+
+impl Display for IdempotencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidKey(key) => write!(f, "Invalid idempotency key: {:?}. Keys must be 1 to {} visible ASCII characters", key, MAX_KEY_LENGTH),
+            Self::KeyReused(key) => write!(f, "Idempotency key {} was already used for a different request", key),
+            Self::Repository(inner) => write!(f, "Repository error: {}", inner)
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use mockall::mock;
+
+    use super::*;
+
+    mock! {
+        pub Responses {
+
+        }
+
+        #[async_trait]
+        impl IdempotencyRepository for Responses {
+            async fn get_response(&self, key: &str, actor: &str) -> Result<Option<StoredResponse>, IdempotencyError>;
+            async fn store_response(&self, response: &StoredResponse) -> Result<(), IdempotencyError>;
+            async fn delete_responses_before(&self, cutoff: DateTime<Utc>) -> Result<(), IdempotencyError>;
+        }
+    }
+
+    fn request(contents: &str) -> IdempotentRequest {
+        IdempotentRequest::new("foo", &User::new("foo.bar@baz.qux"), "POST /waitlist", contents.as_bytes()).unwrap()
+    }
+
+    fn stored(request: &IdempotentRequest, created_at: DateTime<Utc>) -> StoredResponse {
+        StoredResponse::restore(&request.key, &request.actor, &request.endpoint, &request.fingerprint, 201, "{}", created_at)
+    }
+
+    #[test]
+    fn new_given_invalid_key_fails() {
+        let user = User::new("foo.bar@baz.qux");
+
+        assert!(IdempotentRequest::new("", &user, "POST /waitlist", &[]).is_err());
+        assert!(IdempotentRequest::new("has space", &user, "POST /waitlist", &[]).is_err());
+        assert!(IdempotentRequest::new(&"a".repeat(MAX_KEY_LENGTH + 1), &user, "POST /waitlist", &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn find_response_given_same_request_replays_it() {
+        let original = request("{\"name\":\"Foo\"}");
+        let response = stored(&original, Utc::now());
+        let mut repo = MockResponses::new();
+        repo.expect_get_response()
+            .returning(move |_, _| Ok(Some(response.clone())));
+        let sut = IdempotencyService::new(repo);
+
+        let result = sut.find_response(&original).await.unwrap();
+
+        assert_eq!(201, result.unwrap().status());
+    }
+
+    #[tokio::test]
+    async fn find_response_given_different_request_fails() {
+        let response = stored(&request("{\"name\":\"Foo\"}"), Utc::now());
+        let mut repo = MockResponses::new();
+        repo.expect_get_response()
+            .returning(move |_, _| Ok(Some(response.clone())));
+        let sut = IdempotencyService::new(repo);
+
+        let result = sut.find_response(&request("{\"name\":\"Bar\"}")).await;
+
+        assert!(matches!(result, Err(IdempotencyError::KeyReused(_))));
+    }
+
+    #[tokio::test]
+    async fn find_response_given_expired_key_ignores_it() {
+        let original = request("{\"name\":\"Foo\"}");
+        let response = stored(&original, Utc::now() - Duration::hours(2));
+        let mut repo = MockResponses::new();
+        repo.expect_get_response()
+            .returning(move |_, _| Ok(Some(response.clone())));
+        let sut = IdempotencyService::new(repo).with_window(Duration::hours(1));
+
+        let result = sut.find_response(&original).await.unwrap();
+
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn lock_key_given_same_key_waits_for_the_first() {
+        let sut = IdempotencyService::new(MockResponses::new());
+        let first = sut.lock_key(&request("{}")).await;
+
+        let waited = tokio::time::timeout(std::time::Duration::from_millis(50), sut.lock_key(&request("{}"))).await;
+        drop(first);
+        let after = tokio::time::timeout(std::time::Duration::from_millis(50), sut.lock_key(&request("{}"))).await;
+
+        assert!(waited.is_err());
+        assert!(after.is_ok());
+    }
+
+    #[tokio::test]
+    async fn lock_key_given_different_keys_does_not_wait() {
+        let sut = IdempotencyService::new(MockResponses::new());
+        let other = IdempotentRequest::new("bar", &User::new("foo.bar@baz.qux"), "POST /waitlist", &[]).unwrap();
+        let _first = sut.lock_key(&request("{}")).await;
+
+        let result = tokio::time::timeout(std::time::Duration::from_millis(50), sut.lock_key(&other)).await;
+
+        assert!(result.is_ok());
+    }
+}
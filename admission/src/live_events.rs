@@ -0,0 +1,122 @@
+// Broadcasts patient events as they happen to whoever is listening, such as
+// clients of GET /api/v1/events, so they needn't poll for changes. Unlike the
+// history kept by PatientEventRepository, events are only held until every
+// listener has received them.
+// https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use std::{convert::Infallible, time::Duration};
+
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use tokio::sync::broadcast::{self, Receiver, Sender, error::RecvError};
+
+use crate::patient_services::{PatientEvent, PatientEventKind};
+
+/// how many events a slow listener can fall behind by before missing some
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// how often to send a comment while no events happen, so proxies don't close
+/// the connection for being idle
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Sends events to every subscriber. Clones share the same channel, so each
+/// service can have its own.
+#[derive(Debug, Clone)]
+pub struct EventBroadcaster {
+    sender: Sender<PatientEvent>
+}
+
+impl EventBroadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender
+        }
+    }
+
+    /// sends the given event to every current subscriber, if any
+    pub fn publish(&self, event: &PatientEvent) {
+        // only fails if nobody is listening, which is fine
+        let _ = self.sender.send(event.clone());
+    }
+
+    /// receives every event published from now on
+    pub fn subscribe(&self) -> Receiver<PatientEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBroadcaster {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// the SSE event type clients can listen for
+pub fn event_name(kind: PatientEventKind) -> &'static str {
+    match kind {
+        PatientEventKind::Waitlisted => "patient-waitlisted",
+        PatientEventKind::Admitted => "patient-admitted",
+        PatientEventKind::Discharged => "patient-unadmitted",
+        PatientEventKind::Transferred => "patient-transferred",
+        PatientEventKind::Updated => "patient-updated",
+        PatientEventKind::Withdrawn => "patient-withdrawn"
+    }
+}
+
+/// formats the given event as a Server-Sent Event, with the event as JSON data
+pub fn to_server_sent_event(event: &PatientEvent) -> String {
+    // JSON never contains raw newlines, so the data fits on one line
+    let data = serde_json::to_string(event).expect("events should always serialize");
+    format!("event: {}\ndata: {}\n\n", event_name(event.kind()), data)
+}
+
+/// Turns the events the given receiver gets into a never-ending stream of
+/// Server-Sent Events. Listeners which fall too far behind are sent a "lagged"
+/// event saying how many events they missed, so they can fetch what changed.
+pub fn server_sent_events(mut receiver: Receiver<PatientEvent>) -> impl Stream<Item = Result<Bytes, Infallible>> {
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+
+    async_stream::stream! {
+        loop {
+            let message = tokio::select! {
+                received = receiver.recv() => match received {
+                    Ok(event) => to_server_sent_event(&event),
+                    Err(RecvError::Lagged(missed)) => format!("event: lagged\ndata: {}\n\n", missed),
+                    Err(RecvError::Closed) => break
+                },
+                _ = keep_alive.tick() => String::from(": keep-alive\n\n")
+            };
+            yield Ok(Bytes::from(message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{patient::Patient, user::User};
+
+    use super::*;
+
+    fn event(kind: PatientEventKind) -> PatientEvent {
+        PatientEvent::new(kind, &User::new("foo@bar.baz"), None, &Patient::new("Foo").with_random_id())
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_published_events() {
+        let sut = EventBroadcaster::default();
+        let mut receiver = sut.subscribe();
+
+        sut.clone().publish(&event(PatientEventKind::Admitted));
+
+        assert_eq!(PatientEventKind::Admitted, receiver.recv().await.unwrap().kind());
+    }
+
+    #[test]
+    fn to_server_sent_event_names_the_event() {
+        let sse = to_server_sent_event(&event(PatientEventKind::Discharged));
+
+        assert!(sse.starts_with("event: patient-unadmitted\ndata: {"));
+        assert!(sse.ends_with("}\n\n"));
+    }
+}
@@ -1,30 +1,59 @@
 // Declare which modules (folders) should be compiled / loaded.
 // These are searched recursively to load any of their declared modules as well.
+mod admission_scheduler;
+mod api_error;
 mod authentication;
+mod config;
 mod database;
+mod etags;
+mod health;
+mod export;
 mod hospital_services;
+mod idempotency_services;
+mod live_events;
+mod metrics;
+mod pagination;
 mod remote_complement_provider;
 mod routes;
 mod patient_services;
 mod user_services;
+mod webhook_services;
 
-use std::env;
+use std::{env, sync::Arc, time::{Duration as StdDuration, Instant}};
 
 use actix_session::{SessionMiddleware, storage::CookieSessionStore};
-use actix_web::{HttpServer, App, web, cookie::Key};
+use actix_web::{HttpServer, App, web, cookie::Key, dev::Service, http::header::{HeaderName, HeaderValue}};
+use chrono::Duration;
 use actix_web_httpauth::middleware::HttpAuthentication;
-use common::complement_service::ComplementService;
-use tokio::sync::Mutex;
+use common::{complement_service::ComplementService, metrics::{HttpMetrics, UNMATCHED_ROUTE}, config::load as load_config, logging::{init_logging, request_id, request_span, REQUEST_ID_HEADER}};
+use futures_util::FutureExt;
+use tracing::{info, Instrument};
 use crate::{
+    admission_scheduler::{AdmissionScheduler, DEFAULT_SCHEDULE},
+    database::database_admission_run_repository::DatabaseAdmissionRunRepository,
+    api_error::{configure_extractor_errors, not_found},
+    health::{Dependencies, configure_health_routes},
     hospital_services::HospitalService,
-    {routes::configure_hospital_routes, authentication::{jwt::{jwt_auth_middleware, configure_jwt_routes}, openid::{OpenIdService, configure_openid_routes}}, database::{database_hospital_repository::DatabaseHospitalRepository, pool::make_db_pool, database_group_repository::DatabaseGroupRepository, database_patient_repository::DatabasePatientRepository}}, patient_services::PatientService, remote_complement_provider::RemoteComplementProvider,
-    user_services::UserService
+    config::Config,
+    idempotency_services::IdempotencyService,
+    live_events::EventBroadcaster,
+    metrics::{AdmissionMetrics, Metrics, configure_metrics_routes},
+    {routes::{configure_hospital_routes, configure_openapi_routes}, authentication::{jwt::{jwt_auth_middleware, configure_jwt_routes, JwtSecret}, openid::{OpenIdService, configure_openid_routes}}, database::{database_hospital_repository::DatabaseHospitalRepository, pool::make_db_pool, database_group_repository::DatabaseGroupRepository, database_patient_repository::DatabasePatientRepository, database_patient_event_repository::DatabasePatientEventRepository, database_idempotency_repository::DatabaseIdempotencyRepository}}, patient_services::PatientService, remote_complement_provider::RemoteComplementProvider,
+    user_services::UserService,
+    webhook_services::{WebhookService, WebhookDispatcher},
+    database::database_webhook_repository::DatabaseWebhookRepository
 };
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // nothing can be logged until the configuration says how, so report any
+    // problems with it by crashing
+    let config: Config = load_config()
+        .unwrap_or_else(|e| panic!("{}", e));
+    init_logging(&config.logging);
+    let config = config.admission;
 
-    let openid_service = OpenIdService::from_env()
+    let openid_service = OpenIdService::new(&config.openid, &config.public_url)
         .await
         .expect("Should be able to start OpenID service");
     // expect causes the program to crash if the result is not OK.
@@ -33,13 +62,17 @@ async fn main() -> std::io::Result<()> {
     // so you don't have to propogate Results up the callers' return types,
     // that will make things a lot harder in the long run.
 
-    let pool = make_db_pool()
+    let pool = make_db_pool(&config.database)
         .await
         .expect("Database pool should initialize successfully");
     
-    let mut hospital_repo = DatabaseHospitalRepository::new(pool.clone());
-    let mut group_repo = DatabaseGroupRepository::new(pool.clone());
-    let mut patient_repo = DatabasePatientRepository::new(pool.clone());
+    let hospital_repo = DatabaseHospitalRepository::new(pool.clone());
+    let group_repo = DatabaseGroupRepository::new(pool.clone());
+    let patient_repo = DatabasePatientRepository::new(pool.clone());
+    let event_repo = DatabasePatientEventRepository::new(pool.clone());
+    let idempotency_repo = DatabaseIdempotencyRepository::new(pool.clone());
+    let webhook_repo = DatabaseWebhookRepository::new(pool.clone());
+    let admission_run_repo = DatabaseAdmissionRunRepository::new(pool.clone());
 
     let args: Vec<String> = env::args().collect();
     if args.iter().any(|arg| arg == "--setup") {
@@ -56,26 +89,92 @@ async fn main() -> std::io::Result<()> {
             })
             .await
             .expect("Should be able to setup patient repository");
+        event_repo.setup()
+            .await
+            .expect("Should be able to setup patient event repository");
+        idempotency_repo.setup()
+            .await
+            .expect("Should be able to setup idempotency repository");
+        webhook_repo.setup()
+            .await
+            .expect("Should be able to setup webhook repository");
+        admission_run_repo.setup()
+            .await
+            .expect("Should be able to setup admission run repository");
     }
 
-    // Actix web uses web::Data to share resources across requests, though they
-    // must be wrapped in a Mutex for synchronization
-    let hosp_service = web::Data::new(Mutex::new(HospitalService::new(hospital_repo)));
-    let user_service = web::Data::new(Mutex::new(UserService::new(group_repo)));
-    let patient_service = web::Data::new(Mutex::new(PatientService::new(
+    // services record to these as they go, then /metrics renders them all
+    let http_metrics = HttpMetrics::new();
+    let admission_metrics = AdmissionMetrics::new();
+
+    // both services publish to the same broadcaster, which GET /events reads
+    let broadcaster = EventBroadcaster::default();
+
+    // delivers events to webhooks in the background for as long as the app runs
+    actix_web::rt::spawn(WebhookDispatcher::new(DatabaseWebhookRepository::new(pool.clone()))
+        .with_retries(config.webhooks.max_attempts, StdDuration::from_secs(config.webhooks.initial_delay_seconds))
+        .run(broadcaster.subscribe()));
+
+    // Actix web uses web::Data to share resources across requests. Services
+    // only need shared access, as each query borrows its own connection from
+    // the pool, so requests are handled concurrently rather than one at a time
+    let hosp_service = web::Data::new(HospitalService::new(
+        hospital_repo,
+        DatabasePatientEventRepository::new(pool.clone())
+    ).with_broadcaster(broadcaster.clone()));
+    let user_service = web::Data::new(UserService::new(group_repo));
+    let patient_service = Arc::new(PatientService::new(
         patient_repo,
-        ComplementService::new(RemoteComplementProvider::new("http://localhost:8081"))
-    )));
-    let oid = web::Data::new(openid_service); // non-writing service, so no mutex needed
+        DatabaseHospitalRepository::new(pool.clone()),
+        event_repo,
+        ComplementService::new(RemoteComplementProvider::new(&config.complement_url)
+            .with_metrics(admission_metrics.clone()))
+    ).with_default_strategy(config.default_strategy)
+        .with_broadcaster(broadcaster.clone())
+        .with_metrics(admission_metrics.clone()));
+
+    // shares the patient service with the routes, which makes scheduled runs
+    // take turns with those started by hand
+    // scheduled admission runs only start enabled if a schedule is configured
+    let scheduler = Arc::new(AdmissionScheduler::new(
+        config.schedule.as_deref().unwrap_or(DEFAULT_SCHEDULE),
+        patient_service.clone(),
+        admission_run_repo
+    ).expect("schedule should have been validated with the rest of the configuration")
+        .with_enabled(config.schedule.is_some()));
+    actix_web::rt::spawn(scheduler.clone().run());
+
+    let patient_service = web::Data::from(patient_service);
+    let idempotency_service = web::Data::new(IdempotencyService::new(idempotency_repo)
+        .with_window(Duration::hours(config.idempotency_window_hours)));
+    let webhook_service = web::Data::new(WebhookService::new(webhook_repo));
+    let dependencies = web::Data::new(Dependencies::new(pool.clone(), RemoteComplementProvider::new(&config.complement_url)));
+    let metrics = web::Data::new(Metrics::new(
+        http_metrics.clone(),
+        admission_metrics,
+        pool.clone(),
+        DatabasePatientRepository::new(pool.clone())
+    ));
+    let jwt_secret = web::Data::new(JwtSecret::new(&config.jwt_secret));
+    let oid = web::Data::new(openid_service);
+    let broadcaster = web::Data::new(broadcaster);
+    let scheduler = web::Data::from(scheduler);
 
-    println!("Starting web server...");
+    info!(host = %config.host, port = config.port, "Starting web server...");
     
     HttpServer::new(move || {
         App::new()
             .app_data(hosp_service.clone()) // app data is thread-safe
             .app_data(patient_service.clone())
             .app_data(oid.clone())
+            .app_data(jwt_secret.clone())
             .app_data(user_service.clone())
+            .app_data(idempotency_service.clone())
+            .app_data(webhook_service.clone())
+            .app_data(scheduler.clone())
+            .app_data(dependencies.clone())
+            .app_data(broadcaster.clone())
+            .app_data(metrics.clone())
             // the session allows us to persist data across requests and associate
             // it with a single user. This demo uses a cookie to store all the
             // session data, as the Actix Session package does not support storing
@@ -85,14 +184,50 @@ async fn main() -> std::io::Result<()> {
                 CookieSessionStore::default(),
                 Key::from("super-secret-key-that-must-be-at-least-64-bytes-long-so-I-guess-I-will-just-have-to-make-something-up".as_bytes())
             ))
+            // Times and logs every request within a span carrying its ID.
+            // Requests are labelled by the route they matched rather than
+            // their path, so IDs don't make a new series each.
+            .wrap_fn({
+                let http_metrics = http_metrics.clone();
+                move |req, srv| {
+                    let method = req.method().to_string();
+                    let route = req.match_pattern().unwrap_or_else(|| String::from(UNMATCHED_ROUTE));
+                    let request_id = request_id(req.headers().get(REQUEST_ID_HEADER).and_then(|id| id.to_str().ok()));
+                    let span = request_span(&request_id, &method, &route);
+                    let started = Instant::now();
+                    let http_metrics = http_metrics.clone();
+                    srv.call(req).map(move |res| {
+                        // middleware such as authentication fails with an error
+                        // instead of a response
+                        let status = match &res {
+                            Ok(res) => res.status(),
+                            Err(e) => e.as_response_error().status_code()
+                        };
+                        let elapsed = started.elapsed();
+                        http_metrics.observe_request(&method, &route, status.as_u16(), elapsed);
+                        info!(status = status.as_u16(), elapsed_ms = elapsed.as_millis() as u64, "Finished request");
+
+                        res.map(|mut res| {
+                            let id = HeaderValue::from_str(&request_id).expect("request IDs should be valid header values");
+                            res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), id);
+                            res
+                        })
+                    }).instrument(span)
+                }
+            })
+            .configure(configure_extractor_errors)
             .configure(configure_jwt_routes)
             .configure(configure_openid_routes)
+            .configure(configure_openapi_routes) // public, so must come before the API scope
+            .configure(configure_health_routes) // likewise
+            .configure(configure_metrics_routes) // likewise
             .service(web::scope("/api/v1") // register API routes
                 .wrap(HttpAuthentication::bearer(jwt_auth_middleware)) // apply JWT auth middleware
                 .configure(configure_hospital_routes)
+                .default_service(web::to(not_found))
             )
         })
-        .bind(("127.0.0.1", 8080))?
+        .bind((config.host.as_str(), config.port))?
         .run()
         .await
 }
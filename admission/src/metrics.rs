@@ -0,0 +1,198 @@
+// Serves Prometheus metrics from /metrics, so we can see request rates,
+// latencies, pool usage, and how admission runs are going. Like /healthz, it
+// does not require a token, as Prometheus doesn't have one.
+
+use std::time::Duration;
+
+use actix_web::{web::{self, ServiceConfig, resource, get}, HttpResponse, http::header::{ContentType, CacheControl, CacheDirective}};
+use bb8::Pool;
+use bb8_tiberius::ConnectionManager;
+use common::metrics::{HttpMetrics, TEXT_FORMAT};
+use prometheus::{Registry, IntCounterVec, IntCounter, IntGauge, Histogram, HistogramOpts, Opts, exponential_buckets};
+use tracing::warn;
+
+use crate::patient_services::{PatientRepository, AdmissionResult, PatientError};
+
+/// metrics about admitting patients, which services record as they go
+#[derive(Clone)]
+pub struct AdmissionMetrics {
+    admission_runs: IntCounterVec,
+    admitted_per_run: Histogram,
+    complement_duration: Histogram,
+    complement_failures: IntCounter,
+    waitlisted_patients: IntGauge,
+    admitted_patients: IntGauge,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge
+}
+
+impl AdmissionMetrics {
+    /// creates metrics which are not registered anywhere, so recording them
+    /// does nothing visible until they are
+    pub fn new() -> Self {
+        Self {
+            admission_runs: IntCounterVec::new(
+                    Opts::new("admission_runs_total", "Admission runs, by whether they succeeded or failed"),
+                    &["outcome"]
+                )
+                .expect("admission run metric should be valid"),
+            admitted_per_run: Histogram::with_opts(
+                    HistogramOpts::new("admission_run_admitted_patients", "How many patients each successful admission run admitted")
+                        .buckets(exponential_buckets(1.0, 2.0, 10).expect("buckets should be valid"))
+                )
+                .expect("admitted patients metric should be valid"),
+            complement_duration: Histogram::with_opts(
+                    HistogramOpts::new("complement_request_duration_seconds", "How long requests to the complement service take")
+                )
+                .expect("complement duration metric should be valid"),
+            complement_failures: IntCounter::new("complement_request_failures_total", "Requests to the complement service which failed")
+                .expect("complement failure metric should be valid"),
+            waitlisted_patients: IntGauge::new("patients_waitlisted", "Patients currently on the waitlist")
+                .expect("waitlisted patients metric should be valid"),
+            admitted_patients: IntGauge::new("patients_admitted", "Patients currently admitted to a hospital")
+                .expect("admitted patients metric should be valid"),
+            pool_connections: IntGauge::new("db_pool_connections", "Connections the database pool has open")
+                .expect("pool connections metric should be valid"),
+            pool_idle_connections: IntGauge::new("db_pool_idle_connections", "Open database connections not in use")
+                .expect("idle pool connections metric should be valid")
+        }
+    }
+
+    /// adds every metric to the given registry, so they are rendered with it
+    pub fn register(&self, registry: &Registry) -> Result<(), prometheus::Error> {
+        registry.register(Box::new(self.admission_runs.clone()))?;
+        registry.register(Box::new(self.admitted_per_run.clone()))?;
+        registry.register(Box::new(self.complement_duration.clone()))?;
+        registry.register(Box::new(self.complement_failures.clone()))?;
+        registry.register(Box::new(self.waitlisted_patients.clone()))?;
+        registry.register(Box::new(self.admitted_patients.clone()))?;
+        registry.register(Box::new(self.pool_connections.clone()))?;
+        registry.register(Box::new(self.pool_idle_connections.clone()))?;
+        Ok(())
+    }
+
+    /// records how an admission run, scheduled or not, turned out
+    pub fn observe_admission_run(&self, result: &Result<AdmissionResult, PatientError>) {
+        match result {
+            Ok(result) => {
+                self.admission_runs.with_label_values(&["success"]).inc();
+                self.admitted_per_run.observe(result.admitted().len() as f64);
+            },
+            Err(_) => self.admission_runs.with_label_values(&["failure"]).inc()
+        }
+    }
+
+    pub fn observe_complement_request(&self, elapsed: Duration, succeeded: bool) {
+        self.complement_duration.observe(elapsed.as_secs_f64());
+        if !succeeded {
+            self.complement_failures.inc();
+        }
+    }
+
+    fn observe_pool(&self, pool: &Pool<ConnectionManager>) {
+        let state = pool.state();
+        self.pool_connections.set(state.connections.into());
+        self.pool_idle_connections.set(state.idle_connections.into());
+    }
+
+    fn observe_patients(&self, waitlisted: usize, admitted: usize) {
+        self.waitlisted_patients.set(waitlisted as i64);
+        self.admitted_patients.set(admitted as i64);
+    }
+}
+
+impl Default for AdmissionMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// everything /metrics reports on
+pub struct Metrics {
+    http: HttpMetrics,
+    admission: AdmissionMetrics,
+    pool: Pool<ConnectionManager>,
+
+    /// separate from the patient service, as scraping needs nothing else from it
+    patients: Box<dyn PatientRepository>
+}
+
+impl Metrics {
+    /// registers the admission metrics alongside the HTTP ones
+    pub fn new(
+        http: HttpMetrics,
+        admission: AdmissionMetrics,
+        pool: Pool<ConnectionManager>,
+        patients: impl PatientRepository + 'static
+    ) -> Self {
+        admission.register(http.registry())
+            .expect("admission metrics should only be registered once");
+        Self {
+            http,
+            admission,
+            pool,
+            patients: Box::new(patients)
+        }
+    }
+
+    /// updates gauges which are read rather than recorded, then renders every
+    /// metric
+    async fn scrape(&self) -> String {
+        self.admission.observe_pool(&self.pool);
+        match self.patients.count_patients().await {
+            Ok((waitlisted, admitted)) => self.admission.observe_patients(waitlisted, admitted),
+            Err(e) => warn!(error = %e, "Failed to count patients for metrics") // keep the last counts
+        }
+        self.http.render()
+    }
+}
+
+/// must be registered before the /api/v1 scope, so it can be read without a
+/// token
+pub fn configure_metrics_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        resource("/metrics")
+            .name("metrics")
+            .route(get().to(metrics_handler))
+    );
+}
+
+async fn metrics_handler(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(ContentType(TEXT_FORMAT.parse().expect("text format should be a valid media type")))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .body(metrics.scrape().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe_admission_run_counts_failures() {
+        let http = HttpMetrics::new();
+        let sut = AdmissionMetrics::new();
+        sut.register(http.registry()).unwrap();
+
+        sut.observe_admission_run(&Err(PatientError::Unsupported));
+        sut.observe_admission_run(&Err(PatientError::Unsupported));
+        let rendered = http.render();
+
+        assert!(rendered.contains("admission_runs_total{outcome=\"failure\"} 2"));
+        assert!(!rendered.contains("outcome=\"success\""));
+    }
+
+    #[test]
+    fn observe_complement_request_counts_failures() {
+        let http = HttpMetrics::new();
+        let sut = AdmissionMetrics::new();
+        sut.register(http.registry()).unwrap();
+
+        sut.observe_complement_request(Duration::from_millis(5), true);
+        sut.observe_complement_request(Duration::from_millis(5), false);
+        let rendered = http.render();
+
+        assert!(rendered.contains("complement_request_duration_seconds_count 2"));
+        assert!(rendered.contains("complement_request_failures_total 1"));
+    }
+}
@@ -0,0 +1,158 @@
+// cursor-based pagination, shared by list endpoints
+
+use std::fmt::Write;
+
+use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
+
+/// how many items a page contains if the client does not ask for a size
+pub const DEFAULT_LIMIT: usize = 50;
+
+/// the most items a client may ask for in a single page
+pub const MAX_LIMIT: usize = 200;
+
+/// returns the requested page size, kept between 1 and MAX_LIMIT
+pub fn page_size(requested: Option<usize>) -> usize {
+    requested.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+/// one page of results, along with where the next page starts
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    items: Vec<T>,
+
+    /// None if this is the last page
+    #[schema(value_type = Option<String>)]
+    next_cursor: Option<Cursor>
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, next_cursor: Option<Cursor>) -> Self {
+        Self {
+            items,
+            next_cursor
+        }
+    }
+
+    /// Creates a page from up to limit + 1 rows, as fetched by a repository.
+    /// The extra row only signals there is another page, so it is dropped,
+    /// and the cursor is made from the last row kept.
+    pub fn from_rows(mut rows: Vec<T>, limit: usize, cursor_for: impl Fn(&T) -> Cursor) -> Self {
+        let has_more = rows.len() > limit;
+        rows.truncate(limit);
+        let next_cursor = match rows.last() {
+            Some(last) if has_more => Some(cursor_for(last)),
+            _ => None
+        };
+        Self::new(rows, next_cursor)
+    }
+
+    /// returns a copy of this page, except with each item converted
+    pub fn map<U>(self, mapper: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(mapper).collect(),
+            next_cursor: self.next_cursor
+        }
+    }
+}
+
+/// Marks where a page ended, as the sort key values of its last item followed
+/// by that item's ID. Clients receive it hex-encoded, so they treat it as
+/// opaque.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    values: Vec<String>
+}
+
+impl Cursor {
+    pub fn new(values: &[String]) -> Self {
+        Self {
+            values: values.to_vec()
+        }
+    }
+
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_string(&self.values)
+            .expect("strings should always serialize");
+        json.bytes().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cursor: {}", encoded);
+        if !encoded.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|i| encoded.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        let values: Vec<String> = serde_json::from_slice(&bytes)
+            .map_err(|_| invalid())?;
+        Ok(Self::new(&values))
+    }
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(encoded: String) -> Result<Self, Self::Error> {
+        Self::decode(&encoded)
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        cursor.encode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_survives_encoding() {
+        let cursor = Cursor::new(&[String::from("Napa"), String::from("4")]);
+
+        let decoded = Cursor::decode(&cursor.encode());
+
+        assert_eq!(Ok(cursor), decoded);
+    }
+
+    #[test]
+    fn cursor_given_garbage_does_not_decode() {
+        assert!(Cursor::decode("not a cursor").is_err());
+    }
+
+    #[test]
+    fn from_rows_given_an_extra_row_drops_it_and_sets_cursor() {
+        let page = Page::from_rows(vec![1, 2, 3], 2, |n| Cursor::new(&[n.to_string()]));
+
+        assert_eq!(vec![1, 2], page.items);
+        assert_eq!(Some(Cursor::new(&[String::from("2")])), page.next_cursor);
+    }
+
+    #[test]
+    fn from_rows_given_the_last_page_has_no_cursor() {
+        let page = Page::from_rows(vec![1, 2], 2, |n| Cursor::new(&[n.to_string()]));
+
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[test]
+    fn page_size_is_clamped() {
+        assert_eq!(DEFAULT_LIMIT, page_size(None));
+        assert_eq!(MAX_LIMIT, page_size(Some(MAX_LIMIT + 1)));
+        assert_eq!(1, page_size(Some(0)));
+    }
+}
@@ -0,0 +1,199 @@
+// admission strategies decide which hospital a waitlisted patient is placed in
+// when more than one hospital could accept them
+
+use std::{fmt::Display, str::FromStr};
+
+use common::{hospital::Hospital, patient::Patient};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// chooses a hospital for each patient during an admission run
+pub trait AdmissionStrategy: Send + Sync {
+
+    /// Returns the name of the hospital the given patient should be admitted
+    /// to, or None if none of the candidates are suitable. Candidates are
+    /// sorted by name, and each of them has room for the patient and does not
+    /// appear in the patient's disallowed hospitals.
+    fn choose(&mut self, patient: &Patient, candidates: &[&Hospital]) -> Option<String>;
+}
+
+/// admits each patient to whichever candidate has the fewest patients
+pub struct LeastOccupied;
+
+impl AdmissionStrategy for LeastOccupied {
+    fn choose(&mut self, _patient: &Patient, candidates: &[&Hospital]) -> Option<String> {
+        // min_by_key returns the first minimum, so ties go to the earliest name
+        candidates.iter()
+            .min_by_key(|h| h.patients().len())
+            .map(|h| h.name())
+    }
+}
+
+/// cycles through hospitals in alphabetical order, so consecutive patients are
+/// spread across as many hospitals as possible
+pub struct RoundRobin {
+    last_chosen: Option<String>
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        Self {
+            last_chosen: None
+        }
+    }
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdmissionStrategy for RoundRobin {
+    fn choose(&mut self, _patient: &Patient, candidates: &[&Hospital]) -> Option<String> {
+        // pick the first hospital after the last one chosen, wrapping around
+        let next = match self.last_chosen {
+            Some(ref last) => candidates.iter()
+                .find(|h| h.name() > *last)
+                .or_else(|| candidates.first()),
+            None => candidates.first()
+        };
+        self.last_chosen = next.map(|h| h.name());
+        self.last_chosen.clone()
+    }
+}
+
+/// admits each patient to the hospital they most prefer, falling back to the
+/// first candidate alphabetically if none of their preferences are available
+pub struct PatientPreferred;
+
+impl AdmissionStrategy for PatientPreferred {
+    fn choose(&mut self, patient: &Patient, candidates: &[&Hospital]) -> Option<String> {
+        patient.preferred_hospitals()
+            .iter()
+            .find_map(|preferred| candidates.iter().find(|h| h.name().eq_ignore_ascii_case(preferred)))
+            .or_else(|| candidates.first())
+            .map(|h| h.name())
+    }
+}
+
+/// always admits each patient to the first candidate alphabetically
+pub struct Alphabetical;
+
+impl AdmissionStrategy for Alphabetical {
+    fn choose(&mut self, _patient: &Patient, candidates: &[&Hospital]) -> Option<String> {
+        candidates.first().map(|h| h.name())
+    }
+}
+
+/// names the built-in admission strategies, so they can be chosen by clients
+/// and configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdmissionStrategyKind {
+    #[default]
+    LeastOccupied,
+    RoundRobin,
+    PatientPreferred,
+    Alphabetical
+}
+
+impl AdmissionStrategyKind {
+    /// creates a new instance of the strategy this names
+    pub fn create(&self) -> Box<dyn AdmissionStrategy> {
+        match self {
+            Self::LeastOccupied => Box::new(LeastOccupied),
+            Self::RoundRobin => Box::new(RoundRobin::new()),
+            Self::PatientPreferred => Box::new(PatientPreferred),
+            Self::Alphabetical => Box::new(Alphabetical)
+        }
+    }
+}
+
+impl Display for AdmissionStrategyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LeastOccupied => write!(f, "least-occupied"),
+            Self::RoundRobin => write!(f, "round-robin"),
+            Self::PatientPreferred => write!(f, "patient-preferred"),
+            Self::Alphabetical => write!(f, "alphabetical")
+        }
+    }
+}
+
+impl FromStr for AdmissionStrategyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "least-occupied" => Ok(Self::LeastOccupied),
+            "round-robin" => Ok(Self::RoundRobin),
+            "patient-preferred" => Ok(Self::PatientPreferred),
+            "alphabetical" => Ok(Self::Alphabetical),
+            _ => Err(format!("Unknown admission strategy: {}", s))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hospital_with_patients(name: &str, patients: usize) -> Hospital {
+        let mut h = Hospital::new(name);
+        for _ in 0..patients {
+            h.add_patient(Patient::new("Foo").with_random_id().admit_to(name));
+        }
+        h
+    }
+
+    #[test]
+    fn least_occupied_chooses_hospital_with_fewest_patients() {
+        let a = hospital_with_patients("A", 2);
+        let b = hospital_with_patients("B", 1);
+        let c = hospital_with_patients("C", 3);
+
+        let chosen = LeastOccupied.choose(&Patient::new("Foo"), &[&a, &b, &c]);
+
+        assert_eq!(Some(String::from("B")), chosen);
+    }
+
+    #[test]
+    fn round_robin_cycles_through_candidates() {
+        let a = Hospital::new("A");
+        let b = Hospital::new("B");
+        let mut sut = RoundRobin::new();
+        let patient = Patient::new("Foo");
+
+        let chosen: Vec<Option<String>> = (0..3)
+            .map(|_| sut.choose(&patient, &[&a, &b]))
+            .collect();
+
+        assert_eq!(vec![Some(String::from("A")), Some(String::from("B")), Some(String::from("A"))], chosen);
+    }
+
+    #[test]
+    fn patient_preferred_chooses_most_preferred_available_hospital() {
+        let a = Hospital::new("A");
+        let b = Hospital::new("B");
+        let patient = Patient::new("Foo")
+            .with_preferred_hospitals(&[String::from("C"), String::from("B")]);
+
+        let chosen = PatientPreferred.choose(&patient, &[&a, &b]);
+
+        assert_eq!(Some(String::from("B")), chosen);
+    }
+
+    #[test]
+    fn alphabetical_given_no_candidates_chooses_nothing() {
+        let chosen = Alphabetical.choose(&Patient::new("Foo"), &[]);
+
+        assert!(chosen.is_none());
+    }
+
+    #[test]
+    fn strategy_kind_parses_from_kebab_case() {
+        assert_eq!(Ok(AdmissionStrategyKind::RoundRobin), "round-robin".parse());
+        assert!("random".parse::<AdmissionStrategyKind>().is_err());
+    }
+}
@@ -0,0 +1,1479 @@
+pub mod admission_strategy;
+pub mod waitlist_import;
+
+use std::{error::Error, fmt::Display, collections::{HashMap, HashSet}, str::FromStr};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc, Duration};
+use futures_util::stream::LocalBoxStream;
+use common::{patient::{Patient, Priority}, complement_service::ComplementService, hospital::Hospital, user::User};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+use utoipa::{ToSchema, IntoParams};
+use uuid::Uuid;
+
+use crate::{hospital_services::HospitalRepository, pagination::{Page, Cursor, page_size}, export::CsvLine, live_events::EventBroadcaster, metrics::AdmissionMetrics};
+
+use self::{admission_strategy::AdmissionStrategyKind, waitlist_import::{ImportReport, AcceptedLine, RejectedLine}};
+
+/// the longest name the patient repository can store
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// provides services related to patients
+pub struct PatientService {
+    patient_repository: Box<dyn PatientRepository + 'static>,
+    hospital_repository: Box<dyn HospitalRepository + 'static>,
+    event_repository: Box<dyn PatientEventRepository + 'static>,
+    complement_service: ComplementService,
+
+    /// used when an admission run does not ask for a specific strategy
+    default_strategy: AdmissionStrategyKind,
+
+    /// tells listeners about events as they are recorded
+    broadcaster: EventBroadcaster,
+
+    /// records how each admission run turns out
+    metrics: AdmissionMetrics,
+
+    /// held for the whole of each admission run, so runs started by hand and
+    /// on schedule never plan around the same free beds
+    admission_lock: Mutex<()>
+}
+
+impl PatientService {
+    pub fn new(
+        patient_repository: impl PatientRepository + 'static,
+        hospital_repository: impl HospitalRepository + 'static,
+        event_repository: impl PatientEventRepository + 'static,
+        complement_service: ComplementService
+    ) -> Self {
+        Self {
+            patient_repository: Box::new(patient_repository),
+            hospital_repository: Box::new(hospital_repository),
+            event_repository: Box::new(event_repository),
+            complement_service,
+            default_strategy: AdmissionStrategyKind::default(),
+            broadcaster: EventBroadcaster::default(),
+            metrics: AdmissionMetrics::default(),
+            admission_lock: Mutex::default()
+        }
+    }
+
+    /// sets the admission strategy to use when none is requested
+    pub fn with_default_strategy(self, default_strategy: AdmissionStrategyKind) -> Self {
+        Self {
+            default_strategy,
+            ..self
+        }
+    }
+
+    /// publishes events to the given broadcaster after recording them
+    pub fn with_broadcaster(self, broadcaster: EventBroadcaster) -> Self {
+        Self {
+            broadcaster,
+            ..self
+        }
+    }
+
+    /// records the outcome of admission runs to the given metrics
+    pub fn with_metrics(self, metrics: AdmissionMetrics) -> Self {
+        Self {
+            metrics,
+            ..self
+        }
+    }
+
+    /// Appends the given event to the patient's history, then tells listeners.
+    /// The change it describes has already been stored, so a failed append is
+    /// logged rather than failing the request.
+    async fn record(&self, event: PatientEvent) {
+        if let Err(e) = self.event_repository.append_event(&event).await {
+            error!(patient_id = %event.patient_id(), kind = %event.kind(), error = %e, "Failed to record patient event");
+        }
+        self.broadcaster.publish(&event);
+    }
+
+    /// returns every patient, whether waitlisted, admitted, or discharged
+    pub async fn get_all_patients(&self) -> Result<Vec<Patient>, PatientError> {
+        self.patient_repository.get_all_patients()
+            .await
+    }
+
+    /// returns the patient with the given ID, or None if there is no such
+    /// patient
+    pub async fn get_patient_by_id(&self, patient_id: Uuid) -> Result<Option<Patient>, PatientError> {
+        self.patient_repository.get_patient_by_id(patient_id)
+            .await
+    }
+
+    /// returns the patients on the waitlist in the order they will be
+    /// admitted: most urgent first, then whoever has waited longest
+    pub async fn get_waitlisted_patients(&self) -> Result<Vec<Patient>, PatientError> {
+        let mut waitlisted_patients = self.patient_repository.get_waitlisted_patients()
+            .await?;
+        waitlisted_patients.sort_by_key(|p| (p.priority(), p.waitlisted_at(), p.id()));
+        Ok(waitlisted_patients)
+    }
+
+    /// returns patients who have been discharged, most recently discharged
+    /// first
+    pub async fn get_discharged_patients(&self) -> Result<Vec<Patient>, PatientError> {
+        let mut discharged_patients = self.patient_repository.get_discharged_patients()
+            .await?;
+        discharged_patients.sort_by_key(|p| std::cmp::Reverse(p.discharge().map(|d| d.discharged_at())));
+        Ok(discharged_patients)
+    }
+
+    /// returns a single page of the waitlist, along with each patient's
+    /// position on it and how long they have been waiting
+    pub async fn get_waitlist(&self, query: &WaitlistQuery) -> Result<Page<WaitlistEntry>, PatientError> {
+        let now = Utc::now();
+        let page = self.patient_repository.get_waitlist_page(query)
+            .await?
+            .map(|(position, patient)| WaitlistEntry {
+                position,
+                wait_time_seconds: patient.wait_time(now).map(|d| d.num_seconds()),
+                patient
+            });
+        Ok(page)
+    }
+
+    /// streams the waitlist in the order patients will be admitted, keeping
+    /// only those matching the given query's name filter, as exports are never
+    /// sorted differently or paged
+    pub fn export_waitlist(&self, query: &WaitlistQuery) -> LocalBoxStream<'static, Result<WaitlistLine, PatientError>> {
+        self.patient_repository.stream_waitlist(query.name_filter())
+    }
+
+    /// Returns every event recorded for the given patient, oldest first, or
+    /// None if there is no such patient.
+    pub async fn get_patient_history(&self, patient_id: Uuid) -> Result<Option<Vec<PatientEvent>>, PatientError> {
+        let events = self.event_repository.get_events_for_patient(patient_id)
+            .await?;
+        if events.is_empty() && self.patient_repository.get_patient_by_id(patient_id).await?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(events))
+    }
+
+    /// Adds the given patient to the hospital admission waitlist, if they have
+    /// not yet been added to the waitlist and have not yet been admitted to a
+    /// hospital. Returns an error if the patient is not added to the waitlist.
+    pub async fn add_patient_to_waitlist(&self, patient: &Patient, actor: &User) -> Result<Patient, PatientError> {
+        match patient.id() {
+            Some(id) => Err(PatientError::AlreadyExists(id)),
+            None => {
+                let waitlisted = patient.waitlisted()
+                    .with_random_id()
+                    .with_waitlisted_at(Utc::now());
+                let stored = self.patient_repository.store_patient(&waitlisted).await?;
+                self.record(PatientEvent::new(PatientEventKind::Waitlisted, actor, None, &stored))
+                    .await;
+                Ok(stored)
+            }
+        }
+    }
+
+    /// Adds a patient to the waitlist for each valid line of the given CSV, in
+    /// a single transaction, and reports which lines were accepted or rejected.
+    /// Patients are waitlisted in the order they appear, as if each were
+    /// waitlisted just after the one before.
+    pub async fn import_patients_to_waitlist(&self, csv: &[u8], actor: &User) -> Result<ImportReport, PatientError> {
+        let known: Vec<String> = self.hospital_repository.get_all_hospitals()
+            .await
+            .map_err(PatientError::repository)?
+            .iter()
+            .map(|h| h.name())
+            .collect();
+
+        let now = Utc::now();
+        let mut lines: Vec<u64> = Vec::new();
+        let mut patients: Vec<Patient> = Vec::new();
+        let mut rejected: Vec<RejectedLine> = Vec::new();
+        for (line, result) in waitlist_import::read_patients(csv, &known) {
+            match result {
+                Ok(patient) => {
+                    let waitlisted_at = now + Duration::microseconds(patients.len() as i64);
+                    patients.push(patient.with_random_id().with_waitlisted_at(waitlisted_at));
+                    lines.push(line);
+                },
+                Err(reason) => rejected.push(RejectedLine::new(line, &reason))
+            }
+        }
+
+        let stored = if patients.is_empty() {
+            Vec::new()
+        } else {
+            self.patient_repository.store_waitlisted_patients(&patients).await?
+        };
+        for patient in &stored {
+            self.record(PatientEvent::new(PatientEventKind::Waitlisted, actor, None, patient))
+                .await;
+        }
+
+        let accepted = lines.into_iter()
+            .zip(stored)
+            .map(|(line, patient)| AcceptedLine::new(line, patient))
+            .collect();
+        Ok(ImportReport::new(accepted, rejected))
+    }
+
+    /// Changes the name and / or disallowed hospitals of the given patient,
+    /// leaving any which are None as they are. Returns an error if there is no
+    /// such patient, or if the changes are invalid. If given a version, also
+    /// returns an error if the patient has changed since that version.
+    pub async fn update_patient(
+        &self,
+        patient_id: Uuid,
+        name: Option<&str>,
+        disallowed_hospitals: Option<&HashSet<String>>,
+        expected_version: Option<u64>,
+        actor: &User
+    ) -> Result<Patient, PatientError> {
+        let before = self.patient_repository.get_patient_by_id(patient_id)
+            .await?
+            .ok_or(PatientError::NotFound(patient_id))?;
+        if expected_version.is_some() && expected_version != before.version() {
+            return Err(PatientError::Stale(patient_id));
+        }
+        let mut after = before.clone();
+
+        if let Some(name) = name {
+            let name = name.trim();
+            if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+                return Err(PatientError::Invalid(format!("name must be between 1 and {} characters long", MAX_NAME_LENGTH)));
+            }
+            after = after.with_name(name);
+        }
+
+        if let Some(disallowed_hospitals) = disallowed_hospitals {
+            let known: Vec<String> = self.hospital_repository.get_all_hospitals()
+                .await
+                .map_err(PatientError::repository)?
+                .iter()
+                .map(|h| h.name())
+                .collect();
+
+            // spell each as the hospital does, so names differing only in case
+            // become one
+            let mut canonical: HashSet<String> = HashSet::new();
+            for name in disallowed_hospitals {
+                let hospital = known.iter()
+                    .find(|k| k.eq_ignore_ascii_case(name))
+                    .ok_or_else(|| PatientError::Invalid(format!("no hospital named {}", name)))?;
+                canonical.insert(hospital.to_owned());
+            }
+            after = after.with_disallowed_hospitals(&canonical);
+        }
+
+        let updated = self.patient_repository.update_patient_details(&after, disallowed_hospitals.is_some())
+            .await?;
+        self.record(PatientEvent::new(PatientEventKind::Updated, actor, Some(&before), &updated))
+            .await;
+        Ok(updated)
+    }
+
+    /// Removes the given patient from the waitlist, returning them as they
+    /// were just before removal. Returns an error if there is no such patient,
+    /// or if they are not on the waitlist. Their record and history are kept.
+    pub async fn withdraw_patient_from_waitlist(&self, patient_id: Uuid, actor: &User) -> Result<Patient, PatientError> {
+        let patient = self.patient_repository.get_patient_by_id(patient_id)
+            .await?
+            .ok_or(PatientError::NotFound(patient_id))?;
+        if !patient.is_waitlisted() {
+            return Err(PatientError::NotWaitlisted(patient_id));
+        }
+
+        let withdrawn = self.patient_repository.withdraw_patient(patient_id)
+            .await?;
+        self.record(PatientEvent::new(PatientEventKind::Withdrawn, actor, Some(&patient), &withdrawn))
+            .await;
+        Ok(patient)
+    }
+
+    /// Moves as many patients as possible from the waitlist to a hospital that
+    /// can accept them and has a free bed, then returns the admitted patients
+    /// along with those who could not be placed anywhere. The given strategy
+    /// decides between hospitals, or the default strategy if none is given.
+    /// More urgent patients are placed first, so they get any scarce beds, and
+    /// patients of the same priority are placed first-come-first-served.
+    pub async fn admit_patients_from_waitlist(
+        &self,
+        strategy: Option<AdmissionStrategyKind>,
+        actor: &User
+    ) -> Result<AdmissionResult, PatientError> {
+        let _running = self.admission_lock.lock()
+            .await;
+        let result = self.admit_patients(strategy, actor)
+            .await;
+        self.metrics.observe_admission_run(&result);
+        result
+    }
+
+    /// Returns who admit_patients_from_waitlist would admit to which hospitals
+    /// if run now, and why everyone else could not be placed, without actually
+    /// admitting anyone.
+    pub async fn preview_admissions(&self, strategy: Option<AdmissionStrategyKind>) -> Result<AdmissionResult, PatientError> {
+        let mut result = self.plan_admissions(strategy)
+            .await?;
+        result.dry_run = true;
+        Ok(result)
+    }
+
+    /// plans admissions, then stores them
+    async fn admit_patients(&self, strategy: Option<AdmissionStrategyKind>, actor: &User) -> Result<AdmissionResult, PatientError> {
+        let mut result = self.plan_admissions(strategy)
+            .await?;
+        let planned = std::mem::take(&mut result.admitted);
+
+        // update DB, which has the final say, as other requests may have
+        // changed patients or hospitals since the plan was made
+        for patient in planned {
+            let before = patient.waitlisted();
+            match self.patient_repository.update_patient_hospital(&patient).await {
+                Ok(_) => {},
+                Err(PatientError::NotFound(_) | PatientError::NotWaitlisted(_)) => {
+                    warn!(patient_id = ?patient.id(), "Patient left the waitlist during an admission run");
+                    continue;
+                },
+                Err(PatientError::HospitalUnavailable(_)) => {
+                    result.leave_waitlisted(&before, UnplacedReason::HospitalUnavailable);
+                    continue;
+                },
+                Err(e) => return Err(e)
+            }
+
+            self.record(PatientEvent::new(PatientEventKind::Admitted, actor, Some(&before), &patient))
+                .await;
+            result.admitted.push(patient);
+        }
+
+        Ok(result)
+    }
+
+    /// works out where each waitlisted patient should be admitted, without
+    /// changing anything
+    async fn plan_admissions(&self, strategy: Option<AdmissionStrategyKind>) -> Result<AdmissionResult, PatientError> {
+        let mut strategy = strategy
+            .unwrap_or(self.default_strategy)
+            .create();
+
+        let waitlisted_patients = self.get_waitlisted_patients()
+            .await?;
+
+        // track occupancy as we go, so we never overfill a hospital. Keyed by
+        // upper case name, as the complement service may spell names
+        // differently.
+        let mut hospitals: HashMap<String, Hospital> = self.hospital_repository.get_all_hospitals()
+            .await
+            .map_err(PatientError::repository)?
+            .into_iter()
+            .map(|h| (h.name().to_uppercase(), h))
+            .collect();
+
+        let mut result = AdmissionResult::new();
+        let now = Utc::now();
+
+        for patient in &waitlisted_patients {
+            // get the list of hospitals this patient can be admitted to
+            let allowed = match self.complement_service.compute_complement(patient.disallowed_hospitals()).await {
+                Ok(allowed) => allowed,
+                Err(e) => {
+                    warn!(patient_id = ?patient.id(), error = %e, "Cannot place patient, as the complement service is unavailable");
+                    result.leave_waitlisted(patient, UnplacedReason::ComplementUnavailable);
+                    continue;
+                }
+            };
+
+            let allowed_names = allowed.len();
+            let allowed: Vec<&Hospital> = allowed.iter()
+                .filter_map(|name| hospitals.get(&name.to_uppercase()))
+                .collect();
+            if allowed.is_empty() && !hospitals.is_empty() {
+                if allowed_names == 0 {
+                    result.leave_waitlisted(patient, UnplacedReason::AllHospitalsDisallowed);
+                } else {
+                    warn!(patient_id = ?patient.id(), "Cannot place patient, as the complement service named no known hospital");
+                    result.leave_waitlisted(patient, UnplacedReason::NoKnownHospitals);
+                }
+                continue;
+            }
+
+            // sort so strategies behave the same from one run to the next
+            let mut candidates: Vec<&Hospital> = allowed.into_iter()
+                .filter(|h| h.has_room())
+                .collect();
+            if candidates.is_empty() {
+                result.leave_waitlisted(patient, UnplacedReason::NoCapacity);
+                continue;
+            }
+            candidates.sort_by_key(|h| h.name());
+
+            let chosen = strategy.choose(patient, &candidates)
+                .and_then(|name| hospitals.get_mut(&name.to_uppercase()));
+
+            match chosen {
+                Some(hospital) => {
+                    let admitted = patient.admit_to(&hospital.name())
+                        .with_admitted_at(now);
+                    hospital.add_patient(admitted.clone());
+                    result.admitted.push(admitted);
+                },
+                None => result.leave_waitlisted(patient, UnplacedReason::StrategyDeclined)
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// a patient on the waitlist, along with where they are in line
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WaitlistEntry {
+    #[serde(flatten)]
+    patient: Patient,
+
+    /// where this patient is on the waitlist, starting from 1 for whoever is
+    /// next in line
+    position: usize,
+
+    /// how long this patient has been on the waitlist, if known
+    wait_time_seconds: Option<i64>
+}
+
+/// a patient on the waitlist, as a line of a waitlist export
+#[derive(Debug, Clone, Serialize)]
+pub struct WaitlistLine {
+    position: usize,
+    patient_id: Uuid,
+    name: String,
+    priority: Priority,
+    waitlisted_at: Option<DateTime<Utc>>,
+
+    /// separated by semicolons, as in waitlist imports
+    disallowed_hospitals: String
+}
+
+impl WaitlistLine {
+    pub fn new(position: usize, patient_id: Uuid, name: &str, priority: Priority, waitlisted_at: Option<DateTime<Utc>>, disallowed_hospitals: &str) -> Self {
+        Self {
+            position,
+            patient_id,
+            name: name.to_owned(),
+            priority,
+            waitlisted_at,
+            disallowed_hospitals: disallowed_hospitals.to_owned()
+        }
+    }
+}
+
+impl CsvLine for WaitlistLine {
+    const HEADERS: &'static [&'static str] = &["position", "patientId", "name", "priority", "waitlistedAt", "disallowedHospitals"];
+}
+
+/// which page of the waitlist to list, as given by clients
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct WaitlistQuery {
+    /// only list patients whose names contain this, ignoring case
+    name: Option<String>,
+    sort: Option<WaitlistSort>,
+
+    /// where the previous page ended, or None for the first page
+    #[param(value_type = Option<String>)]
+    cursor: Option<Cursor>,
+    limit: Option<usize>
+}
+
+impl WaitlistQuery {
+    pub fn name_filter(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    pub fn sort(&self) -> WaitlistSort {
+        self.sort.unwrap_or_default()
+    }
+
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.cursor.clone()
+    }
+
+    /// the page size, kept within the allowed range
+    pub fn limit(&self) -> usize {
+        page_size(self.limit)
+    }
+}
+
+/// the orders the waitlist can be listed in, where a leading - means
+/// descending
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+pub enum WaitlistSort {
+    /// the order patients will be admitted in
+    #[default]
+    #[serde(rename = "position")]
+    Position,
+
+    #[serde(rename = "name")]
+    Name,
+
+    #[serde(rename = "-name")]
+    NameDescending,
+
+    #[serde(rename = "waitlistedAt")]
+    WaitlistedAt,
+
+    #[serde(rename = "-waitlistedAt")]
+    WaitlistedAtDescending
+}
+
+impl WaitlistSort {
+    pub fn is_descending(&self) -> bool {
+        matches!(self, Self::NameDescending | Self::WaitlistedAtDescending)
+    }
+}
+
+/// the outcome of admitting patients from the waitlist
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionResult {
+    /// patients who were admitted to a hospital
+    admitted: Vec<Patient>,
+
+    /// patients who could not be placed in any hospital, and so remain on the
+    /// waitlist
+    waitlisted: Vec<Patient>,
+
+    /// why each patient in waitlisted could not be placed
+    unplaced: Vec<UnplacedPatient>,
+
+    /// if true, nobody was actually admitted, and this shows what would happen
+    dry_run: bool
+}
+
+impl AdmissionResult {
+    fn new() -> Self {
+        Self {
+            admitted: Vec::new(),
+            waitlisted: Vec::new(),
+            unplaced: Vec::new(),
+            dry_run: false
+        }
+    }
+
+    pub fn admitted(&self) -> Vec<Patient> {
+        self.admitted.clone()
+    }
+
+    pub fn waitlisted(&self) -> Vec<Patient> {
+        self.waitlisted.clone()
+    }
+
+    fn leave_waitlisted(&mut self, patient: &Patient, reason: UnplacedReason) {
+        self.waitlisted.push(patient.clone());
+        self.unplaced.push(UnplacedPatient {
+            patient_id: patient.id().expect("waitlisted patients should be stored"),
+            reason
+        });
+    }
+}
+
+/// a patient left on the waitlist by an admission run, and why
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UnplacedPatient {
+    patient_id: Uuid,
+    reason: UnplacedReason
+}
+
+/// why an admission run could not place a patient in any hospital
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnplacedReason {
+    /// the patient is disallowed from every hospital
+    AllHospitalsDisallowed,
+
+    /// the complement service only allowed hospitals this service does not
+    /// know of, so the two disagree on which hospitals exist
+    NoKnownHospitals,
+
+    /// every hospital the patient is allowed in is full
+    NoCapacity,
+
+    /// the complement service could not say which hospitals the patient is
+    /// allowed in
+    ComplementUnavailable,
+
+    /// the admission strategy chose none of the hospitals with room
+    StrategyDeclined,
+
+    /// the chosen hospital closed or filled up while the run was going, so
+    /// the next run will try again
+    HospitalUnavailable
+}
+
+/// backing store for patients
+#[async_trait]
+pub trait PatientRepository: Send + Sync {
+    async fn store_patient(&self, patient: &Patient) -> Result<Patient, PatientError>;
+
+    /// Stores every given patient on the waitlist in a single transaction, so
+    /// either all of them are stored, or none are. Returns them as stored.
+    async fn store_waitlisted_patients(&self, patients: &[Patient]) -> Result<Vec<Patient>, PatientError>;
+    async fn get_all_patients(&self) -> Result<Vec<Patient>, PatientError>;
+
+    /// returns every patient not yet admitted to a hospital, most urgent first
+    async fn get_waitlisted_patients(&self) -> Result<Vec<Patient>, PatientError>;
+
+    /// filters, sorts, and pages the waitlist in the backing store, returning
+    /// each patient along with their position on the whole waitlist
+    async fn get_waitlist_page(&self, query: &WaitlistQuery) -> Result<Page<(usize, Patient)>, PatientError>;
+
+    /// streams a line for each patient on the waitlist whose name contains the
+    /// given filter, ignoring case, in the order they will be admitted
+    fn stream_waitlist(&self, name_filter: Option<String>) -> LocalBoxStream<'static, Result<WaitlistLine, PatientError>>;
+
+    /// returns every patient who has been discharged from a hospital
+    async fn get_discharged_patients(&self) -> Result<Vec<Patient>, PatientError>;
+
+    /// returns how many patients are waitlisted, then how many are admitted,
+    /// without loading any of them
+    async fn count_patients(&self) -> Result<(usize, usize), PatientError>;
+    async fn get_patient_by_id(&self, id: Uuid) -> Result<Option<Patient>, PatientError>;
+    async fn update_patient_hospital(&self, patient: &Patient) -> Result<Patient, PatientError>;
+
+    /// Updates the given patient's name, and their disallowed hospitals too if
+    /// told to replace them, all at once, then returns them as stored. If the
+    /// patient has a version, returns an error instead if the stored patient
+    /// has changed since that version.
+    async fn update_patient_details(&self, patient: &Patient, replace_disallowed: bool) -> Result<Patient, PatientError>;
+
+    /// Withdraws the given patient from the waitlist, keeping their record,
+    /// then returns them as stored. Returns an error instead if they are no
+    /// longer on the waitlist.
+    async fn withdraw_patient(&self, id: Uuid) -> Result<Patient, PatientError>;
+}
+
+/// the kinds of changes recorded in a patient's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PatientEventKind {
+    Waitlisted,
+    Admitted,
+    Transferred,
+    Discharged,
+    Updated,
+
+    /// removed from the waitlist without being admitted
+    Withdrawn
+}
+
+impl Display for PatientEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Waitlisted => write!(f, "waitlisted"),
+            Self::Admitted => write!(f, "admitted"),
+            Self::Transferred => write!(f, "transferred"),
+            Self::Discharged => write!(f, "discharged"),
+            Self::Updated => write!(f, "updated"),
+            Self::Withdrawn => write!(f, "withdrawn")
+        }
+    }
+}
+
+impl FromStr for PatientEventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waitlisted" => Ok(Self::Waitlisted),
+            "admitted" => Ok(Self::Admitted),
+            "transferred" => Ok(Self::Transferred),
+            "discharged" => Ok(Self::Discharged),
+            "updated" => Ok(Self::Updated),
+            "withdrawn" => Ok(Self::Withdrawn),
+            _ => Err(format!("Unknown patient event kind: {}", s))
+        }
+    }
+}
+
+/// a single change to a patient, recording who made it, when, and what the
+/// patient looked like before and after
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PatientEvent {
+    patient_id: Uuid,
+    kind: PatientEventKind,
+
+    /// the email of the user who made this change
+    actor: String,
+    occurred_at: DateTime<Utc>,
+
+    /// None if the patient did not exist before this event
+    before: Option<Patient>,
+    after: Patient
+}
+
+impl PatientEvent {
+    /// creates an event which happened just now
+    pub fn new(kind: PatientEventKind, actor: &User, before: Option<&Patient>, after: &Patient) -> Self {
+        Self {
+            patient_id: after.id().expect("event should be about a stored patient"),
+            kind,
+            actor: actor.email(),
+            occurred_at: Utc::now(),
+            before: before.cloned(),
+            after: after.to_owned()
+        }
+    }
+
+    /// recreates an event which was previously stored
+    pub fn restore(
+        patient_id: Uuid,
+        kind: PatientEventKind,
+        actor: &str,
+        occurred_at: DateTime<Utc>,
+        before: Option<Patient>,
+        after: Patient
+    ) -> Self {
+        Self {
+            patient_id,
+            kind,
+            actor: actor.to_owned(),
+            occurred_at,
+            before,
+            after
+        }
+    }
+
+    pub fn patient_id(&self) -> Uuid {
+        self.patient_id
+    }
+
+    pub fn kind(&self) -> PatientEventKind {
+        self.kind
+    }
+
+    pub fn actor(&self) -> String {
+        self.actor.to_owned()
+    }
+
+    pub fn occurred_at(&self) -> DateTime<Utc> {
+        self.occurred_at
+    }
+
+    pub fn before(&self) -> Option<Patient> {
+        self.before.clone()
+    }
+
+    pub fn after(&self) -> Patient {
+        self.after.clone()
+    }
+}
+
+/// Append-only backing store for patient events. Events are never updated or
+/// removed once appended.
+#[async_trait]
+pub trait PatientEventRepository: Send + Sync {
+    async fn append_event(&self, event: &PatientEvent) -> Result<(), PatientError>;
+
+    /// returns every event about the given patient, oldest first
+    async fn get_events_for_patient(&self, patient_id: Uuid) -> Result<Vec<PatientEvent>, PatientError>;
+}
+
+#[derive(Debug)]
+pub enum PatientError {
+    AlreadyExists(Uuid),
+    NotFound(Uuid),
+    NotWaitlisted(Uuid),
+
+    /// the patient was changed since the version being written was read
+    Stale(Uuid),
+
+    /// the named hospital closed or filled up before the patient could be
+    /// admitted to it
+    HospitalUnavailable(String),
+    Invalid(String),
+    Repository(Box<dyn Error + 'static>),
+    Unsupported
+}
+
+impl PatientError {
+    pub fn repository(inner: impl Error + 'static) -> Self {
+        Self::Repository(Box::new(inner))
+    }
+}
+
+impl Display for PatientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyExists(id) => write!(f, "Duplicate patient ID: {}", id),
+            Self::NotFound(id) => write!(f, "No patient with ID {}", id),
+            Self::NotWaitlisted(id) => write!(f, "Patient {} is not on the waitlist", id),
+            Self::Stale(id) => write!(f, "Patient {} was changed by someone else; fetch them again and retry", id),
+            Self::HospitalUnavailable(name) => write!(f, "{} closed or has no free beds", name),
+            Self::Invalid(message) => write!(f, "Invalid patient: {}", message),
+            Self::Repository(inner) => write!(f, "Repository error: {}", inner),
+            Self::Unsupported => write!(f, "Unsupported operation")
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use common::{complement_service::{ComplementProvider, ComplementError}, patient::{Priority, Discharge}};
+    use mockall::mock;
+
+    use super::*;
+    use crate::hospital_services::tests::MockDummy as MockHospitals;
+
+    mock! {
+        pub Patients {
+
+        }
+
+        #[async_trait]
+        impl PatientRepository for Patients {
+            async fn store_patient(&self, patient: &Patient) -> Result<Patient, PatientError>;
+            async fn store_waitlisted_patients(&self, patients: &[Patient]) -> Result<Vec<Patient>, PatientError>;
+            async fn get_all_patients(&self) -> Result<Vec<Patient>, PatientError>;
+            async fn get_waitlisted_patients(&self) -> Result<Vec<Patient>, PatientError>;
+            async fn get_waitlist_page(&self, query: &WaitlistQuery) -> Result<Page<(usize, Patient)>, PatientError>;
+            fn stream_waitlist(&self, name_filter: Option<String>) -> LocalBoxStream<'static, Result<WaitlistLine, PatientError>>;
+            async fn get_discharged_patients(&self) -> Result<Vec<Patient>, PatientError>;
+            async fn count_patients(&self) -> Result<(usize, usize), PatientError>;
+            async fn get_patient_by_id(&self, id: Uuid) -> Result<Option<Patient>, PatientError>;
+            async fn update_patient_hospital(&self, patient: &Patient) -> Result<Patient, PatientError>;
+            async fn update_patient_details(&self, patient: &Patient, replace_disallowed: bool) -> Result<Patient, PatientError>;
+            async fn withdraw_patient(&self, id: Uuid) -> Result<Patient, PatientError>;
+        }
+    }
+
+    mock! {
+        pub Events {
+
+        }
+
+        #[async_trait]
+        impl PatientEventRepository for Events {
+            async fn append_event(&self, event: &PatientEvent) -> Result<(), PatientError>;
+            async fn get_events_for_patient(&self, patient_id: Uuid) -> Result<Vec<PatientEvent>, PatientError>;
+        }
+    }
+
+    /// mocks an event repository which accepts any number of events
+    pub fn events_accepting() -> MockEvents {
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .returning(|_| Ok(()));
+        events
+    }
+
+    fn actor() -> User {
+        User::new("foo@bar.baz")
+    }
+
+    mock! {
+        pub Complements {
+
+        }
+
+        #[async_trait]
+        impl ComplementProvider for Complements {
+            async fn compute_complement(&self, set: HashSet<String>) -> Result<HashSet<String>, ComplementError>;
+        }
+    }
+
+    #[tokio::test]
+    async fn add_patient_to_waitlist_given_an_existing_patient_returns_error() {
+        let patient = Patient::new("Foo").with_random_id();
+        let repo = MockPatients::new();
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.add_patient_to_waitlist(&patient, &actor()).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn add_patient_to_waitlist_given_a_new_patient_stores_in_repository() {
+        let patient = Patient::new("Foo");
+        let mut repo = MockPatients::new();
+        repo.expect_store_patient()
+            .returning(|p| Ok(p.with_random_id()));
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.add_patient_to_waitlist(&patient, &actor()).await;
+
+        assert!(result.is_ok());
+    }
+
+    fn hospitals_returning(hospitals: Vec<Hospital>) -> MockHospitals {
+        let mut mock = MockHospitals::new();
+        mock.expect_get_all_hospitals()
+            .return_once(|| Ok(hospitals));
+        mock
+    }
+
+    fn complements_returning(names: &[&str]) -> MockComplements {
+        let names: HashSet<String> = names.iter().map(|n| n.to_string()).collect();
+        let mut complements = MockComplements::new();
+        complements.expect_compute_complement()
+            .returning(move |_| Ok(names.clone()));
+        complements
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_given_no_waitlisted_patients_does_not_update() {
+        let mut repo = MockPatients::new();
+        repo.expect_store_patient()
+            .never();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(Vec::new()));
+        let sut = PatientService::new(repo, hospitals_returning(Vec::new()), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await;
+
+        assert!(result.is_ok());
+        assert!(result.unwrap().admitted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_given_a_waitlisted_patient_adds_them_to_a_hospital() {
+        let a_waitlisted_patient = Patient::new("Foo")
+            .with_random_id()
+            .waitlisted();
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(vec![a_waitlisted_patient]));
+        repo.expect_update_patient_hospital()
+            .once()
+            .return_once(|p| Ok(p.to_owned()));
+
+        let mut complements = MockComplements::new();
+        complements.expect_compute_complement()
+            .once()
+            .return_once(|_| {
+                let mut h = HashSet::new();
+                h.insert(String::from("Foo"));
+                Ok(h)
+            });
+
+        let hospitals = hospitals_returning(vec![Hospital::new("Foo")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await;
+
+        assert!(result.is_ok());
+        let updated_patients = result.unwrap().admitted;
+        assert!(updated_patients.len() == 1);
+        assert!(updated_patients.iter().all(Patient::is_admitted));
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_given_a_full_hospital_leaves_patient_waitlisted() {
+        let a_waitlisted_patient = Patient::new("Foo")
+            .with_random_id()
+            .waitlisted();
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(vec![a_waitlisted_patient]));
+        repo.expect_update_patient_hospital()
+            .never();
+
+        let mut full = Hospital::new("Foo").with_capacity(1);
+        full.add_patient(Patient::new("Bar").with_random_id().admit_to("Foo"));
+
+        let sut = PatientService::new(repo, hospitals_returning(vec![full]), events_accepting(), ComplementService::new(complements_returning(&["Foo"])));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await
+            .expect("admission should succeed");
+
+        assert!(result.admitted.is_empty());
+        assert_eq!(1, result.waitlisted.len());
+        assert_eq!(UnplacedReason::NoCapacity, result.unplaced[0].reason);
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_given_hospital_fills_up_meanwhile_leaves_patient_waitlisted() {
+        let a_waitlisted_patient = Patient::new("Foo")
+            .with_random_id()
+            .waitlisted();
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(vec![a_waitlisted_patient]));
+        repo.expect_update_patient_hospital()
+            .once()
+            .return_once(|_| Err(PatientError::HospitalUnavailable(String::from("Foo"))));
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .never();
+
+        let sut = PatientService::new(repo, hospitals_returning(vec![Hospital::new("Foo")]), events, ComplementService::new(complements_returning(&["Foo"])));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await
+            .expect("admission should succeed");
+
+        assert!(result.admitted.is_empty());
+        assert_eq!(1, result.waitlisted.len());
+        assert_eq!(UnplacedReason::HospitalUnavailable, result.unplaced[0].reason);
+    }
+
+    #[tokio::test]
+    async fn preview_admissions_explains_without_admitting() {
+        let waitlisted = vec![
+            Patient::new("Foo").with_random_id(),
+            Patient::new("Bar").with_random_id().with_disallowed_hospitals(&HashSet::from([String::from("A")]))
+        ];
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(waitlisted));
+        repo.expect_update_patient_hospital()
+            .never();
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .never();
+        let mut complements = MockComplements::new();
+        complements.expect_compute_complement()
+            .returning(|disallowed| Ok(["A"].into_iter().map(String::from).filter(|h| !disallowed.contains(h)).collect()));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("A")]);
+        let sut = PatientService::new(repo, hospitals, events, ComplementService::new(complements));
+
+        let result = sut.preview_admissions(None).await
+            .expect("preview should succeed");
+
+        assert!(result.dry_run);
+        assert_eq!(1, result.admitted.len());
+        assert_eq!(UnplacedReason::AllHospitalsDisallowed, result.unplaced[0].reason);
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_given_complement_unavailable_leaves_patient_waitlisted() {
+        let waitlisted = vec![Patient::new("Foo").with_random_id()];
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(waitlisted));
+        repo.expect_update_patient_hospital()
+            .never();
+        let mut complements = MockComplements::new();
+        complements.expect_compute_complement()
+            .returning(|_| Err(ComplementError::new("connection refused")));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("A")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await
+            .expect("admission should succeed");
+
+        assert!(result.admitted.is_empty());
+        assert_eq!(UnplacedReason::ComplementUnavailable, result.unplaced[0].reason);
+    }
+
+    #[tokio::test]
+    async fn preview_admissions_matches_complement_names_ignoring_case() {
+        let waitlisted = vec![Patient::new("Foo").with_random_id()];
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(waitlisted));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("Napa")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements_returning(&["NAPA"])));
+
+        let result = sut.preview_admissions(None).await
+            .expect("preview should succeed");
+
+        assert_eq!(Some(String::from("Napa")), result.admitted[0].admitted_to());
+    }
+
+    #[tokio::test]
+    async fn preview_admissions_given_complement_names_no_known_hospital_says_so() {
+        let waitlisted = vec![Patient::new("Foo").with_random_id()];
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(waitlisted));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("Napa")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements_returning(&["Fresno"])));
+
+        let result = sut.preview_admissions(None).await
+            .expect("preview should succeed");
+
+        assert!(result.admitted.is_empty());
+        assert_eq!(UnplacedReason::NoKnownHospitals, result.unplaced[0].reason);
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_does_not_exceed_capacity() {
+        let waitlisted = vec![
+            Patient::new("Foo").with_random_id(),
+            Patient::new("Bar").with_random_id()
+        ];
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(waitlisted));
+        repo.expect_update_patient_hospital()
+            .once()
+            .return_once(|p| Ok(p.to_owned()));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("Baz").with_capacity(1)]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements_returning(&["Baz"])));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await
+            .expect("admission should succeed");
+
+        assert_eq!(1, result.admitted.len());
+        assert_eq!(1, result.waitlisted.len());
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_uses_requested_strategy() {
+        let waitlisted = vec![
+            Patient::new("Foo").with_random_id(),
+            Patient::new("Bar").with_random_id()
+        ];
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(waitlisted));
+        repo.expect_update_patient_hospital()
+            .times(2)
+            .returning(|p| Ok(p.to_owned()));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("A"), Hospital::new("B")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements_returning(&["A", "B"])));
+
+        let result = sut.admit_patients_from_waitlist(Some(AdmissionStrategyKind::Alphabetical), &actor()).await
+            .expect("admission should succeed");
+
+        assert!(result.admitted.iter().all(|p| p.admitted_to() == Some(String::from("A"))));
+    }
+
+    #[tokio::test]
+    async fn admit_patients_from_waitlist_given_scarce_beds_admits_most_urgent_first() {
+        let normal = Patient::new("Foo").with_random_id();
+        let urgent = Patient::new("Bar").with_random_id().with_priority(Priority::Urgent);
+        let urgent_id = urgent.id();
+
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(vec![normal, urgent]));
+        repo.expect_update_patient_hospital()
+            .once()
+            .returning(|p| Ok(p.to_owned()));
+
+        let hospitals = hospitals_returning(vec![Hospital::new("A").with_capacity(1)]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(complements_returning(&["A"])));
+
+        let result = sut.admit_patients_from_waitlist(None, &actor()).await
+            .expect("admission should succeed");
+
+        assert_eq!(1, result.admitted.len());
+        assert_eq!(urgent_id, result.admitted[0].id());
+    }
+
+    #[tokio::test]
+    async fn get_waitlisted_patients_returns_most_urgent_first() {
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(|| Ok(vec![
+                Patient::new("Foo").with_priority(Priority::Normal),
+                Patient::new("Bar").with_priority(Priority::Urgent),
+                Patient::new("Baz").with_priority(Priority::High)
+            ]));
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.get_waitlisted_patients().await
+            .expect("should get waitlist");
+
+        let priorities: Vec<Priority> = result.iter().map(Patient::priority).collect();
+        assert_eq!(vec![Priority::Urgent, Priority::High, Priority::Normal], priorities);
+    }
+
+    #[tokio::test]
+    async fn get_waitlisted_patients_orders_same_priority_first_come_first_served() {
+        let now = Utc::now();
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlisted_patients()
+            .once()
+            .return_once(move || Ok(vec![
+                Patient::new("Foo").with_waitlisted_at(now - chrono::Duration::minutes(5)),
+                Patient::new("Bar").with_waitlisted_at(now - chrono::Duration::minutes(10)),
+                Patient::new("Baz").with_waitlisted_at(now).with_priority(Priority::High)
+            ]));
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.get_waitlisted_patients().await
+            .expect("should get waitlist");
+
+        let names: Vec<String> = result.iter().map(Patient::name).collect();
+        assert_eq!(vec!["Baz", "Bar", "Foo"], names);
+    }
+
+    #[tokio::test]
+    async fn get_waitlist_keeps_positions_and_reports_wait_times() {
+        let now = Utc::now();
+        let mut repo = MockPatients::new();
+        repo.expect_get_waitlist_page()
+            .once()
+            .return_once(move |_| Ok(Page::new(vec![
+                (3, Patient::new("Foo").with_waitlisted_at(now - chrono::Duration::minutes(10))),
+                (4, Patient::new("Bar").with_waitlisted_at(now))
+            ], None)));
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.get_waitlist(&WaitlistQuery::default()).await
+            .expect("should get waitlist");
+
+        let json = serde_json::to_value(&result).expect("page should serialize");
+        assert_eq!(3, json["items"][0]["position"]);
+        assert_eq!(4, json["items"][1]["position"]);
+        assert!(json["items"][0]["waitTimeSeconds"].as_i64().is_some_and(|s| s >= 600));
+        assert!(json["nextCursor"].is_null());
+    }
+
+    #[tokio::test]
+    async fn get_discharged_patients_returns_most_recently_discharged_first() {
+        let now = Utc::now();
+        let mut repo = MockPatients::new();
+        repo.expect_get_discharged_patients()
+            .once()
+            .return_once(move || Ok(vec![
+                Patient::new("Foo").discharged(Discharge::new("A", "recovered", now - chrono::Duration::days(1))),
+                Patient::new("Bar").discharged(Discharge::new("A", "recovered", now))
+            ]));
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.get_discharged_patients().await
+            .expect("should get discharged patients");
+
+        assert_eq!("Bar", result[0].name());
+        assert!(result.iter().all(|p| p.is_discharged() && !p.is_waitlisted()));
+    }
+
+    #[tokio::test]
+    async fn add_patient_to_waitlist_records_who_waitlisted_them() {
+        let mut repo = MockPatients::new();
+        repo.expect_store_patient()
+            .returning(|p| Ok(p.to_owned()));
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .once()
+            .withf(|e| e.kind() == PatientEventKind::Waitlisted && e.actor() == "foo@bar.baz" && e.before().is_none())
+            .returning(|_| Ok(()));
+        let sut = PatientService::new(repo, MockHospitals::new(), events, ComplementService::new(MockComplements::new()));
+
+        let result = sut.add_patient_to_waitlist(&Patient::new("Foo"), &actor()).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn get_patient_history_given_an_unknown_patient_returns_none() {
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .returning(|_| Ok(None));
+        let mut events = MockEvents::new();
+        events.expect_get_events_for_patient()
+            .returning(|_| Ok(Vec::new()));
+        let sut = PatientService::new(repo, MockHospitals::new(), events, ComplementService::new(MockComplements::new()));
+
+        let result = sut.get_patient_history(Uuid::new_v4()).await;
+
+        assert!(result.expect("should get history").is_none());
+    }
+
+    #[tokio::test]
+    async fn import_patients_to_waitlist_stores_valid_lines_together() {
+        let mut repo = MockPatients::new();
+        repo.expect_store_waitlisted_patients()
+            .once()
+            .returning(|patients| {
+                assert!(patients[0].waitlisted_at() < patients[1].waitlisted_at());
+                Ok(patients.to_vec())
+            });
+        let hospitals = hospitals_returning(vec![Hospital::new("Napa")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(MockComplements::new()));
+        let csv = "name,disallowed hospitals,priority\nFoo,Napa,\nBar,Fresno,\nBaz,,urgent\n";
+
+        let result = sut.import_patients_to_waitlist(csv.as_bytes(), &actor()).await.unwrap();
+
+        let report = serde_json::to_value(result).unwrap();
+        assert_eq!(2, report["accepted"][0]["line"]);
+        assert_eq!(4, report["accepted"][1]["line"]);
+        assert_eq!(3, report["rejected"][0]["line"]);
+        assert_eq!("no hospital named Fresno", report["rejected"][0]["reason"]);
+    }
+
+    #[tokio::test]
+    async fn import_patients_to_waitlist_given_no_valid_lines_stores_nothing() {
+        let mut repo = MockPatients::new();
+        repo.expect_store_waitlisted_patients()
+            .never();
+        let hospitals = hospitals_returning(vec![Hospital::new("Napa")]);
+        let sut = PatientService::new(repo, hospitals, events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.import_patients_to_waitlist("name\n\"\"\n".as_bytes(), &actor()).await.unwrap();
+
+        let report = serde_json::to_value(result).unwrap();
+        assert_eq!(0, report["accepted"].as_array().unwrap().len());
+        assert_eq!(1, report["rejected"].as_array().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn update_patient_given_an_unknown_patient_returns_not_found() {
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .returning(|_| Ok(None));
+        repo.expect_update_patient_details()
+            .never();
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.update_patient(Uuid::new_v4(), Some("Bar"), None, None, &actor()).await;
+
+        assert!(matches!(result, Err(PatientError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn update_patient_given_an_unknown_hospital_returns_invalid() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_update_patient_details()
+            .never();
+        let disallowed = HashSet::from([String::from("Nowhere")]);
+        let sut = PatientService::new(repo, hospitals_returning(vec![Hospital::new("A")]), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.update_patient(Uuid::new_v4(), None, Some(&disallowed), None, &actor()).await;
+
+        assert!(matches!(result, Err(PatientError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn update_patient_changes_name() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_update_patient_details()
+            .once()
+            .withf(|_, replace_disallowed| !replace_disallowed)
+            .returning(|p, _| Ok(p.to_owned()));
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.update_patient(Uuid::new_v4(), Some(" Bar "), None, None, &actor()).await;
+
+        assert_eq!("Bar", result.expect("should update patient").name());
+    }
+
+    #[tokio::test]
+    async fn update_patient_given_hospitals_differing_in_case_disallows_each_once() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_update_patient_details()
+            .once()
+            .withf(|p, replace_disallowed| *replace_disallowed && p.disallowed_hospitals() == HashSet::from([String::from("Napa")]))
+            .returning(|p, _| Ok(p.to_owned()));
+        let disallowed = HashSet::from([String::from("napa"), String::from("NAPA")]);
+        let sut = PatientService::new(repo, hospitals_returning(vec![Hospital::new("Napa")]), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.update_patient(Uuid::new_v4(), None, Some(&disallowed), None, &actor()).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn update_patient_given_a_stale_version_returns_stale() {
+        let patient = Patient::new("Foo").with_random_id().with_version(2);
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_update_patient_details()
+            .never();
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.update_patient(Uuid::new_v4(), Some("Bar"), None, Some(1), &actor()).await;
+
+        assert!(matches!(result, Err(PatientError::Stale(_))));
+    }
+
+    #[tokio::test]
+    async fn withdraw_patient_from_waitlist_given_an_admitted_patient_returns_error() {
+        let patient = Patient::new("Foo").with_random_id().admit_to("A");
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_withdraw_patient()
+            .never();
+        let sut = PatientService::new(repo, MockHospitals::new(), events_accepting(), ComplementService::new(MockComplements::new()));
+
+        let result = sut.withdraw_patient_from_waitlist(Uuid::new_v4(), &actor()).await;
+
+        assert!(matches!(result, Err(PatientError::NotWaitlisted(_))));
+    }
+
+    #[tokio::test]
+    async fn withdraw_patient_from_waitlist_given_a_waitlisted_patient_withdraws_them() {
+        let patient = Patient::new("Foo").with_random_id();
+        let withdrawn = patient.withdrawn(Utc::now());
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_withdraw_patient()
+            .once()
+            .return_once(|_| Ok(withdrawn));
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .once()
+            .withf(|e| e.kind() == PatientEventKind::Withdrawn)
+            .returning(|_| Ok(()));
+        let sut = PatientService::new(repo, MockHospitals::new(), events, ComplementService::new(MockComplements::new()));
+
+        let result = sut.withdraw_patient_from_waitlist(Uuid::new_v4(), &actor()).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn withdraw_patient_from_waitlist_given_patient_admitted_meanwhile_returns_error() {
+        let patient = Patient::new("Foo").with_random_id();
+        let mut repo = MockPatients::new();
+        repo.expect_get_patient_by_id()
+            .return_once(|_| Ok(Some(patient)));
+        repo.expect_withdraw_patient()
+            .return_once(|id| Err(PatientError::NotWaitlisted(id)));
+        let mut events = MockEvents::new();
+        events.expect_append_event()
+            .never();
+        let sut = PatientService::new(repo, MockHospitals::new(), events, ComplementService::new(MockComplements::new()));
+
+        let result = sut.withdraw_patient_from_waitlist(Uuid::new_v4(), &actor()).await;
+
+        assert!(matches!(result, Err(PatientError::NotWaitlisted(_))));
+    }
+}
@@ -0,0 +1,166 @@
+// Reads patients to add to the waitlist from CSV, such as spreadsheets sent in
+// by counties. After a header row, each line has a patient's name, the
+// hospitals they may not be admitted to separated by semicolons, and their
+// priority. Only the name is required.
+
+use common::patient::{Patient, Priority};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::MAX_NAME_LENGTH;
+
+/// the outcome of importing each line of a CSV file
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    /// lines whose patients were added to the waitlist
+    accepted: Vec<AcceptedLine>,
+
+    /// lines which were skipped, and why
+    rejected: Vec<RejectedLine>
+}
+
+impl ImportReport {
+    pub fn new(accepted: Vec<AcceptedLine>, rejected: Vec<RejectedLine>) -> Self {
+        Self {
+            accepted,
+            rejected
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptedLine {
+    /// the line number in the CSV file, starting from 1 for the header
+    line: u64,
+    patient: Patient
+}
+
+impl AcceptedLine {
+    pub fn new(line: u64, patient: Patient) -> Self {
+        Self {
+            line,
+            patient
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RejectedLine {
+    /// the line number in the CSV file, starting from 1 for the header
+    line: u64,
+    reason: String
+}
+
+impl RejectedLine {
+    pub fn new(line: u64, reason: &str) -> Self {
+        Self {
+            line,
+            reason: reason.to_owned()
+        }
+    }
+}
+
+/// Reads a new patient from each line of the given CSV after the header, along
+/// with its line number. Disallowed hospitals must be among the given known
+/// hospitals, ignoring case. Lines which cannot be read say why instead.
+pub fn read_patients(csv: &[u8], known_hospitals: &[String]) -> Vec<(u64, Result<Patient, String>)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true) // trailing optional columns may be left off
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+
+    reader.records()
+        .map(|record| match record {
+            Ok(record) => (
+                record.position().map(|p| p.line()).unwrap_or_default(),
+                read_patient(&record, known_hospitals)
+            ),
+            Err(e) => (
+                e.position().map(|p| p.line()).unwrap_or_default(),
+                Err(e.to_string())
+            )
+        })
+        .collect()
+}
+
+fn read_patient(record: &csv::StringRecord, known_hospitals: &[String]) -> Result<Patient, String> {
+    if record.len() > 3 {
+        return Err(format!("expected at most 3 columns, but found {}", record.len()));
+    }
+
+    let name = record.get(0).unwrap_or_default();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(format!("name must be between 1 and {} characters long", MAX_NAME_LENGTH));
+    }
+
+    let mut patient = Patient::new(name);
+
+    for disallowed in record.get(1).unwrap_or_default().split(';').map(str::trim).filter(|h| !h.is_empty()) {
+        // store hospitals as they are named, not as they were typed
+        let known = known_hospitals.iter()
+            .find(|k| k.eq_ignore_ascii_case(disallowed))
+            .ok_or_else(|| format!("no hospital named {}", disallowed))?;
+        patient.add_disallowed_hospital(known);
+    }
+
+    let priority = record.get(2).unwrap_or_default();
+    if !priority.is_empty() {
+        let priority = [Priority::Urgent, Priority::High, Priority::Normal].into_iter()
+            .find(|p| p.to_string().eq_ignore_ascii_case(priority))
+            .ok_or_else(|| format!("priority must be urgent, high, or normal, not {}", priority))?;
+        patient = patient.with_priority(priority);
+    }
+
+    Ok(patient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known() -> Vec<String> {
+        vec![String::from("Napa"), String::from("Atascadero")]
+    }
+
+    #[test]
+    fn read_patients_reads_every_column() {
+        let csv = "name,disallowed hospitals,priority\nFoo,napa; Atascadero,Urgent\n";
+
+        let result = read_patients(csv.as_bytes(), &known());
+
+        assert_eq!(1, result.len());
+        let (line, patient) = &result[0];
+        let patient = patient.as_ref().unwrap();
+        assert_eq!(2, *line);
+        assert_eq!("Foo", patient.name());
+        assert!(patient.disallowed_hospitals().contains("Napa"));
+        assert!(patient.disallowed_hospitals().contains("Atascadero"));
+        assert_eq!(Priority::Urgent, patient.priority());
+    }
+
+    #[test]
+    fn read_patients_given_only_names_uses_defaults() {
+        let csv = "name\nFoo\n";
+
+        let result = read_patients(csv.as_bytes(), &known());
+
+        let patient = result[0].1.as_ref().unwrap();
+        assert!(patient.disallowed_hospitals().is_empty());
+        assert_eq!(Priority::Normal, patient.priority());
+    }
+
+    #[test]
+    fn read_patients_rejects_invalid_lines_but_keeps_reading() {
+        let csv = "name,disallowed hospitals,priority\n,,\nFoo,Fresno,\nBar,,soon\nBaz,,high\n";
+
+        let result = read_patients(csv.as_bytes(), &known());
+
+        let lines: Vec<(u64, bool)> = result.iter()
+            .map(|(line, patient)| (*line, patient.is_ok()))
+            .collect();
+        assert_eq!(vec![(2, false), (3, false), (4, false), (5, true)], lines);
+    }
+}
@@ -1,37 +1,67 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Instant};
 
 use async_trait::async_trait;
-use common::{complement_service::ComplementProvider, hospital::GetHospitalNamesResponse};
-
+use common::{complement_service::{ComplementProvider, ComplementError}, hospital::GetHospitalNamesResponse};
 
+use crate::metrics::AdmissionMetrics;
 
 pub struct RemoteComplementProvider {
-    url: String
+    url: String,
+
+    /// records how long each complement request takes, and whether it failed
+    metrics: AdmissionMetrics
 }
 
 impl RemoteComplementProvider {
     pub fn new(url: &str) -> Self {
         Self {
-            url: String::from(url)
+            url: String::from(url),
+            metrics: AdmissionMetrics::default()
         }
     }
-}
 
-#[async_trait]
-impl ComplementProvider for RemoteComplementProvider {
-    async fn compute_complement(&self, set: HashSet<String>) -> HashSet<String> {
+    pub fn with_metrics(self, metrics: AdmissionMetrics) -> Self {
+        Self {
+            metrics,
+            ..self
+        }
+    }
+
+    /// makes sure the complement service is up, without computing anything
+    pub async fn check_reachable(&self) -> Result<(), ComplementError> {
+        reqwest::Client::new()
+            .get(self.url.to_owned() + "/healthz")
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(ComplementError::new)?;
+        Ok(())
+    }
+
+    /// asks the complement service for the complement of the given set
+    async fn request_complement(&self, set: HashSet<String>) -> Result<HashSet<String>, ComplementError> {
         let body = GetHospitalNamesResponse::new(&set);
         let response: GetHospitalNamesResponse = reqwest::Client::new()
             .get(self.url.to_owned() + "/complement")
             .json(&body)
             .send()
             .await
-            .expect("request should be OK") // "I don't want to be a responsible 
-                                            // programmer and handle the error,
-                                            // just fail here if it doesn't work"
+            .and_then(|response| response.error_for_status())
+            .map_err(ComplementError::new)?
             .json()
             .await
-            .expect("should be able to convert to GetHospitalNamesResponse");
-        response.hospital_names()
+            .map_err(ComplementError::new)?;
+        Ok(response.hospital_names())
     }
-}
\ No newline at end of file
+}
+
+#[async_trait]
+impl ComplementProvider for RemoteComplementProvider {
+    async fn compute_complement(&self, set: HashSet<String>) -> Result<HashSet<String>, ComplementError> {
+        let started = Instant::now();
+        let result = self.request_complement(set)
+            .await;
+        self.metrics.observe_complement_request(started.elapsed(), result.is_ok());
+        result
+    }
+}
@@ -1,13 +1,15 @@
 // routes connect HTTP verbs & URLs to backend services
 
-use std::collections::HashSet;
+use std::{collections::HashSet, future::Future};
 
-use actix_web::{web::{ServiceConfig, resource, get, Json, self, post, delete}, error::{ErrorInternalServerError, ErrorNotFound, ErrorBadRequest}, Responder, HttpResponse};
-use serde::Deserialize;
-use tokio::sync::Mutex;
+use actix_web::{web::{ServiceConfig, resource, get, Json, self, post, delete, patch, put}, HttpResponse, HttpRequest, http::{StatusCode, header::{ContentType, CacheControl, CacheDirective}}};
+use serde::{Deserialize, Serialize};
+use tracing::{info, error};
+use utoipa::{OpenApi, ToSchema, IntoParams, Modify, openapi::security::{SecurityScheme, HttpBuilder, HttpAuthScheme}};
+use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{hospital_services::HospitalService, patient_services::{PatientService, PatientError}};
-use common::{patient::Patient, hospital::{Hospital, GetHospitalNamesResponse}};
+use crate::{admission_scheduler::{AdmissionScheduler, ScheduleStatus, AdmissionRun}, api_error::ApiError, live_events::{EventBroadcaster, server_sent_events}, etags::{self, json_with_etag}, export::{self, ExportFormat, FormatQuery}, idempotency_services::{IdempotencyService, IdempotentRequest, IdempotencyError, IDEMPOTENCY_KEY_HEADER, IDEMPOTENT_REPLAYED_HEADER}, hospital_services::{HospitalService, RelocationPolicy, HospitalQuery, HospitalSort, RepositoryError}, patient_services::{PatientService, PatientError, PatientEvent, PatientEventKind, AdmissionResult, WaitlistQuery, WaitlistSort, WaitlistEntry, admission_strategy::AdmissionStrategyKind, waitlist_import::ImportReport}, pagination::{Page, page_size}, webhook_services::{WebhookService, WebhookSubscription, WebhookDelivery, WebhookError}};
+use common::{patient::{Patient, Priority}, hospital::{Hospital, GetHospitalNamesResponse}, user::User, logging::Redacted};
 
 /// sets up routing
 pub fn configure_hospital_routes(cfg: &mut ServiceConfig) {
@@ -20,53 +22,194 @@ pub fn configure_hospital_routes(cfg: &mut ServiceConfig) {
         resource("/hospitals")
             .name("hospitals")
             .route(get().to(get_all_hospitals))
+            .route(post().to(create_hospital))
     );
     cfg.service(
         resource("/hospitals/admit-from-waitlist")
             .name("admit from waitlist")
             .route(post().to(post_admit_from_waitlist_handler))
     );
+    cfg.service(
+        resource("/admission-schedule")
+            .name("admission schedule")
+            .route(get().to(admission_schedule_get_handler))
+            .route(put().to(admission_schedule_put_handler))
+    );
+    cfg.service(
+        resource("/admission-schedule/runs")
+            .name("admission schedule runs")
+            .route(get().to(admission_runs_get_handler))
+    );
     cfg.service(
         resource("/hospitals/{name}")
             .name("hospital")
             .route(get().to(get_hospital_by_name))
+            .route(put().to(rename_hospital))
+            .route(delete().to(close_hospital))
     );
     cfg.service(
         resource("/hospitals/{name}/{patient_id}")
             .name("hospital_patients")
             .route(delete().to(unadmit_patient))
     );
+    cfg.service(
+        resource("/hospitals/{name}/{patient_id}/transfer")
+            .name("hospital_patient_transfer")
+            .route(post().to(transfer_patient))
+    );
+    cfg.service(
+        resource("/events")
+            .name("events")
+            .route(get().to(events_get_handler))
+    );
+    cfg.service(
+        resource("/patients")
+            .name("patients")
+            .route(get().to(patients_get_handler))
+    );
+    cfg.service(
+        resource("/patients/{patient_id}")
+            .name("patient")
+            .route(get().to(patient_get_handler))
+            .route(patch().to(patient_patch_handler))
+    );
+    cfg.service(
+        resource("/patients/{patient_id}/history")
+            .name("patient history")
+            .route(get().to(patient_history_get_handler))
+    );
+    cfg.service(
+        resource("/discharged")
+            .name("discharged")
+            .route(get().to(discharged_get_handler))
+    );
     cfg.service(
         resource("/waitlist")
             .name("waitlist")
             .route(get().to(waitlist_get_handler))
             .route(post().to(waitlist_post_handler))  
     );
+    // must come before /waitlist/{patient_id}, which would otherwise match it
+    cfg.service(
+        resource("/waitlist/import")
+            .name("waitlist import")
+            .route(post().to(waitlist_import_post_handler))
+    );
+    cfg.service(
+        resource("/waitlist/{patient_id}")
+            .name("waitlisted patient")
+            .route(delete().to(waitlist_delete_handler))
+    );
+    cfg.service(
+        resource("/webhooks")
+            .name("webhooks")
+            .route(get().to(webhooks_get_handler))
+            .route(post().to(webhooks_post_handler))
+    );
+    cfg.service(
+        resource("/webhooks/{webhook_id}")
+            .name("webhook")
+            .route(get().to(webhook_get_handler))
+            .route(delete().to(webhook_delete_handler))
+    );
+    cfg.service(
+        resource("/webhooks/{webhook_id}/deliveries")
+            .name("webhook deliveries")
+            .route(get().to(webhook_deliveries_get_handler))
+    );
+}
+
+/// Describes every route in configure_hospital_routes. Schemas are derived from
+/// the types the handlers use, so they cannot drift from the code.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Admission API", description = "Manages hospitals, their patients, and the waitlist"),
+    servers((url = "/api/v1")),
+    paths(
+        get_hospital_names_handler,
+        get_all_hospitals,
+        create_hospital,
+        post_admit_from_waitlist_handler,
+        admission_schedule_get_handler,
+        admission_schedule_put_handler,
+        admission_runs_get_handler,
+        get_hospital_by_name,
+        rename_hospital,
+        close_hospital,
+        unadmit_patient,
+        transfer_patient,
+        events_get_handler,
+        patients_get_handler,
+        patient_get_handler,
+        patient_patch_handler,
+        patient_history_get_handler,
+        discharged_get_handler,
+        waitlist_get_handler,
+        waitlist_post_handler,
+        waitlist_import_post_handler,
+        waitlist_delete_handler,
+        webhooks_get_handler,
+        webhooks_post_handler,
+        webhook_get_handler,
+        webhook_delete_handler,
+        webhook_deliveries_get_handler
+    ),
+    // query parameter types are not collected automatically, so list them too
+    components(schemas(Hospital, Patient, NewPatientRequest, GetHospitalNamesResponse, ApiError, HospitalSort, WaitlistSort, RelocationPolicy, AdmissionStrategyKind, ExportFormat)),
+    modifiers(&BearerAuth),
+    security(("bearer" = []))
+)]
+pub struct ApiDoc;
+
+/// adds the JWT bearer scheme which the /api/v1 scope requires
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(HttpBuilder::new()
+            .scheme(HttpAuthScheme::Bearer)
+            .bearer_format("JWT")
+            .description(Some("a token from /jwt"))
+            .build()
+        ));
+    }
+}
+
+/// Serves the OpenAPI document at /api/v1/openapi.json, and a viewer for it at
+/// /swagger-ui/. Must be registered before the /api/v1 scope, so the document
+/// can be read without a token.
+pub fn configure_openapi_routes(cfg: &mut ServiceConfig) {
+    cfg.service(SwaggerUi::new("/swagger-ui/{_:.*}")
+        .url("/api/v1/openapi.json", ApiDoc::openapi()));
 }
 
 /// handles requests to GET /hospital-names
 /// must be async to work with actix
+#[utoipa::path(
+    get,
+    path = "/hospital-names",
+    tag = "hospitals",
+    responses(
+        (status = 200, description = "The names of every open hospital", body = GetHospitalNamesResponse)
+    )
+)]
 async fn get_hospital_names_handler(
     // web::Data grabs shared state registered in main.rs
     // register using web::Data<T>
     // grab using web::Data<T>
     // This is one way of doing dependency injection
-    hospitals: web::Data<Mutex<HospitalService>>
+    hospitals: web::Data<HospitalService>
 
     // routing functions can return a lot of different things, not just this
     // however, given how errors propogate up from lower layers, they usually
     // need to return a Result to account for the possibility of errors
-    // actix web has its own Result type, not to be confused with Rust's
-) -> actix_web::Result<Json<GetHospitalNamesResponse>> {
-    
-    // Rust is really picky about shared concurrency - if we want to modify a
-    // shared resource, it must be wrapped in a mutex.
-    // Note that although getting is supposed to be a non-mutable operation,
-    // the various Tiberius query methods all require mutablity for some reason,
-    // so that mutablity also propogates upward.
-    let mut getter = hospitals.lock().await;
-
-    getter.get_all_hospitals()
+    // each handler returns ApiError, so clients receive the same kind of error
+    // response no matter which handler fails
+) -> Result<Json<GetHospitalNamesResponse>, ApiError> {
+    // Services only need shared access, as each query borrows its own
+    // connection from the pool, so many requests can use them at once.
+    hospitals.get_all_hospitals()
         .await
         .map(|hospitals| {
             let names: HashSet<String> = hospitals.iter()
@@ -74,105 +217,1041 @@ async fn get_hospital_names_handler(
                 .collect();
             Json(GetHospitalNamesResponse::new(&names))
         })
-        .map_err(ErrorInternalServerError)
+        .map_err(ApiError::from)
 }
 
+/// handles GET requests to list a page of hospitals, such as
+/// /hospitals?name=a&sort=-capacity&limit=2&includePatients=false, or to
+/// download the roster of every patient in them as CSV
+#[utoipa::path(
+    get,
+    path = "/hospitals",
+    tag = "hospitals",
+    params(HospitalQuery, FormatQuery),
+    responses(
+        (status = 200, description = "A page of hospitals, or the roster of all of them as CSV", content(
+            (Page<Hospital> = "application/json"),
+            (String = "text/csv")
+        )),
+        (status = 400, description = "Malformed sort, cursor, or format", body = ApiError, content_type = "application/problem+json")
+    )
+)]
 async fn get_all_hospitals(
-    hospitals: web::Data<Mutex<HospitalService>>
-) -> actix_web::Result<Json<Vec<Hospital>>> {
+    hospitals: web::Data<HospitalService>,
+    query: web::Query<HospitalQuery>, // 400 if sort or cursor is malformed
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    if export::requested_format(&req)? == ExportFormat::Csv {
+        let roster = hospitals.export_roster(&query);
+        return export::csv_response("roster.csv", roster).await;
+    }
     
-    let mut getter = hospitals.lock().await;
+    hospitals.get_hospitals(&query)
+        .await
+        .map(|page| HttpResponse::Ok().json(page))
+        .map_err(ApiError::from)
+}
 
-    match getter.get_all_hospitals().await {
-        Ok(hospitals) => Ok(Json(hospitals)),
-        Err(error) => Err(ErrorInternalServerError(error))
-    }
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all="camelCase")]
+struct CreateHospitalRequest {
+    name: String,
+
+    /// how many beds the hospital has, or unlimited if omitted
+    capacity: Option<u32>
 }
 
+/// handles POST requests to open a new hospital
+#[utoipa::path(
+    post,
+    path = "/hospitals",
+    tag = "hospitals",
+    request_body = CreateHospitalRequest,
+    responses(
+        (status = 201, description = "The opened hospital", body = Hospital),
+        (status = 400, description = "Invalid name", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 409, description = "Name already taken", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn create_hospital(
+    hospitals: web::Data<HospitalService>,
+    posted: Json<CreateHospitalRequest>,
+    user: web::ReqData<User>
+) -> Result<HttpResponse, ApiError> {
+    hospitals.create_hospital(&posted.name, posted.capacity, &user)
+        .await
+        .map(|created| HttpResponse::Created().json(created))
+        .map_err(ApiError::from)
+}
+
+/// query parameters for POST /hospitals/admit-from-waitlist
+#[derive(Debug, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct AdmitFromWaitlistQuery {
+    /// which admission strategy to use, or the server's default if omitted
+    strategy: Option<AdmissionStrategyKind>,
+
+    /// if true, shows who would be admitted where without admitting anyone
+    dry_run: Option<bool>
+}
+
+/// handles POST requests to admit as many waitlisted patients as possible
+#[utoipa::path(
+    post,
+    path = "/hospitals/admit-from-waitlist",
+    tag = "waitlist",
+    params(AdmitFromWaitlistQuery, ("Idempotency-Key" = Option<String>, Header, description = "retries with the same key replay the first response")),
+    responses(
+        (status = 200, description = "Who was admitted, and who is still waiting and why. With dryRun, who would be", body = AdmissionResult),
+        (status = 400, description = "Unknown strategy or invalid idempotency key", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 422, description = "The idempotency key was used for a different request", body = ApiError, content_type = "application/problem+json")
+    )
+)]
 async fn post_admit_from_waitlist_handler(
-    patients: web::Data<Mutex<PatientService>>
-) -> actix_web::Result<Json<Vec<Patient>>> {
+    patients: web::Data<PatientService>,
+    idempotency: web::Data<IdempotencyService>,
+    query: web::Query<AdmitFromWaitlistQuery>, // 400 if strategy is unknown
+    user: web::ReqData<User>, // set by the JWT middleware
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+
+    // changes nothing, so there's nothing to protect from retries
+    if query.dry_run.unwrap_or(false) {
+        return patients.preview_admissions(query.strategy)
+            .await
+            .map(|preview| HttpResponse::Ok().json(preview))
+            .map_err(ApiError::from);
+    }
+
+    let admit = async {
+        let result = patients.admit_patients_from_waitlist(query.strategy, &user)
+            .await
+            .map_err(ApiError::from)?;
+        Ok((StatusCode::OK, to_json(&result)))
+    };
+
+    // the strategy is all that distinguishes one admission run from another
+    respond_idempotently(&idempotency, &req, &user, "POST /hospitals/admit-from-waitlist", req.query_string().as_bytes(), admit)
+        .await
+}
+
+/// handles GET requests for whether patients are admitted on a schedule
+#[utoipa::path(
+    get,
+    path = "/admission-schedule",
+    tag = "waitlist",
+    responses(
+        (status = 200, description = "The schedule, and when it next runs", body = ScheduleStatus)
+    )
+)]
+async fn admission_schedule_get_handler(
+    scheduler: web::Data<AdmissionScheduler> // only holds a flag, so no mutex needed
+) -> Json<ScheduleStatus> {
+    Json(scheduler.status())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all="camelCase")]
+struct UpdateScheduleRequest {
+    enabled: bool
+}
+
+/// handles PUT requests to turn scheduled admission runs on or off
+#[utoipa::path(
+    put,
+    path = "/admission-schedule",
+    tag = "waitlist",
+    request_body = UpdateScheduleRequest,
+    responses(
+        (status = 200, description = "The updated schedule", body = ScheduleStatus),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn admission_schedule_put_handler(
+    scheduler: web::Data<AdmissionScheduler>,
+    posted: Json<UpdateScheduleRequest>
+) -> Json<ScheduleStatus> {
+    scheduler.set_enabled(posted.enabled);
+    Json(scheduler.status())
+}
 
-    let mut admitter = patients.lock().await;
+/// query parameters for GET /admission-schedule/runs
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AdmissionRunsQuery {
+    /// how many runs to list
+    limit: Option<usize>
+}
 
-    admitter.admit_patients_from_waitlist()
-        .await 
+/// handles GET requests for how recent scheduled admission runs went
+#[utoipa::path(
+    get,
+    path = "/admission-schedule/runs",
+    tag = "waitlist",
+    params(AdmissionRunsQuery),
+    responses(
+        (status = 200, description = "The most recent scheduled runs, newest first", body = Vec<AdmissionRun>)
+    )
+)]
+async fn admission_runs_get_handler(
+    scheduler: web::Data<AdmissionScheduler>,
+    query: web::Query<AdmissionRunsQuery>
+) -> Result<Json<Vec<AdmissionRun>>, ApiError> {
+    scheduler.get_recent_runs(page_size(query.limit))
+        .await
         .map(Json)
-        .map_err(ErrorInternalServerError)
+        .map_err(ApiError::from)
 }
 
+/// handles GET requests for a single hospital
+#[utoipa::path(
+    get,
+    path = "/hospitals/{name}",
+    tag = "hospitals",
+    params(("name" = String, Path), FormatQuery),
+    responses(
+        (status = 200, description = "The hospital with that name, or its roster as CSV", content(
+            (Hospital = "application/json"),
+            (String = "text/csv")
+        ), headers(("ETag" = String, description = "Send as If-Match to change the hospital"))),
+        (status = 400, description = "Unknown format", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No open hospital has that name", body = ApiError, content_type = "application/problem+json")
+    )
+)]
 async fn get_hospital_by_name(
-    hospitals: web::Data<Mutex<HospitalService>>, // grab shared data
-    name: web::Path<String> // grab from URL path
-) -> actix_web::Result<Json<Hospital>> { // return as JSON
+    hospitals: web::Data<HospitalService>, // grab shared data
+    name: web::Path<String>, // grab from URL path
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> { // return as JSON, or CSV if asked for
+    if export::requested_format(&req)? == ExportFormat::Csv {
+        let roster = hospitals.export_hospital_roster(&name);
+        return export::csv_response(&format!("{}.csv", name), roster).await;
+    }
 
-    let mut getter = hospitals.lock().await;
+    hospitals.get_hospital_by_name(&name).await
+        .map_err(ApiError::from)? // 500 error if getter fails
+        .map(|hospital| json_with_etag(&hospital, hospital.version())) // 200 if found
+        .ok_or_else(|| ApiError::from(RepositoryError::invalid_hospital_name(&name))) // 404 if not found
+}
 
-    getter.get_hospital_by_name(&name).await
-        .map_err(ErrorInternalServerError)? // 500 error if getter fails
-        .map(Json)                          // 200 if found
-        .ok_or_else(|| ErrorNotFound(name)) // 404 if not found
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all="camelCase")]
+struct RenameHospitalRequest {
+    name: String
 }
 
+/// handles PUT requests to rename a hospital
+#[utoipa::path(
+    put,
+    path = "/hospitals/{name}",
+    tag = "hospitals",
+    params(("name" = String, Path), ("If-Match" = String, Header, description = "The ETag from a GET request, or * to skip the check")),
+    request_body = RenameHospitalRequest,
+    responses(
+        (status = 200, description = "The renamed hospital", body = Hospital, headers(("ETag" = String))),
+        (status = 400, description = "Invalid name", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No open hospital has that name", body = ApiError, content_type = "application/problem+json"),
+        (status = 409, description = "Name already taken", body = ApiError, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the If-Match ETag was sent", body = ApiError, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match header", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn rename_hospital(
+    hospitals: web::Data<HospitalService>,
+    name: web::Path<String>,
+    posted: Json<RenameHospitalRequest>,
+    req: HttpRequest,
+    user: web::ReqData<User>
+) -> Result<HttpResponse, ApiError> {
+    let expected_version = etags::expected_version(&req)?; // 428 if missing
+    hospitals.rename_hospital(&name, &posted.name, expected_version, &user)
+        .await
+        .map(|hospital| json_with_etag(&hospital, hospital.version()))
+        .map_err(ApiError::from)
+}
+
+/// query parameters for DELETE /hospitals/{name}
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CloseHospitalQuery {
+    /// where patients still admitted should go, or refuse to close if omitted
+    relocate: Option<RelocationPolicy>
+}
+
+/// handles DELETE requests to close a hospital
+#[utoipa::path(
+    delete,
+    path = "/hospitals/{name}",
+    tag = "hospitals",
+    params(("name" = String, Path), CloseHospitalQuery),
+    responses(
+        (status = 204, description = "The hospital was closed"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No open hospital has that name", body = ApiError, content_type = "application/problem+json"),
+        (status = 409, description = "Patients are still admitted", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn close_hospital(
+    hospitals: web::Data<HospitalService>,
+    name: web::Path<String>,
+    query: web::Query<CloseHospitalQuery>, // 400 if relocate is unknown
+    user: web::ReqData<User>
+) -> Result<HttpResponse, ApiError> {
+    hospitals.close_hospital(&name, query.relocate, &user)
+        .await
+        .map(|_| HttpResponse::NoContent().finish())
+        .map_err(ApiError::from)
+}
+
+/// query parameters for DELETE /hospitals/{name}/{patient_id}
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UnadmitQuery {
+    /// why the patient is being discharged
+    reason: Option<String>
+}
+
+/// handles DELETE requests to discharge a patient from a hospital
+#[utoipa::path(
+    delete,
+    path = "/hospitals/{name}/{patient_id}",
+    tag = "hospitals",
+    params(("name" = String, Path), ("patient_id" = uuid::Uuid, Path), UnadmitQuery, ("If-Match" = String, Header, description = "The ETag from a GET of the patient, or * to skip the check")),
+    responses(
+        (status = 204, description = "The patient was discharged"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "The patient is not admitted to that hospital", body = ApiError, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the If-Match ETag was sent", body = ApiError, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match header", body = ApiError, content_type = "application/problem+json")
+    )
+)]
 async fn unadmit_patient(
-    hospitals: web::Data<Mutex<HospitalService>>,
+    hospitals: web::Data<HospitalService>,
     path: web::Path<(String, uuid::Uuid)>,
-) -> impl Responder {
-    let mut deleter = hospitals.lock().await;
+    query: web::Query<UnadmitQuery>,
+    user: web::ReqData<User>,
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    let expected_version = etags::expected_version(&req)?;
     let hospital_name = &path.0;
     let patient_id = path.1;
+    let reason = query.reason.as_deref().unwrap_or("unspecified");
 
-    match deleter.unadmit_patient_from_hospital(patient_id, hospital_name).await {
-        Ok(_) => Ok(HttpResponse::NoContent().body("")),
-        Err(e) => Err(ErrorInternalServerError(e))
-    }
+    hospitals.unadmit_patient_from_hospital(patient_id, hospital_name, reason, expected_version, &user)
+        .await
+        .map(|_| HttpResponse::NoContent().finish())
+        .map_err(ApiError::from)
 }
 
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all="camelCase")]
+struct TransferRequest {
+    /// the name of the hospital to move the patient to
+    to: String
+}
+
+/// handles POST requests to move a patient to another hospital
+#[utoipa::path(
+    post,
+    path = "/hospitals/{name}/{patient_id}/transfer",
+    tag = "hospitals",
+    params(("name" = String, Path), ("patient_id" = uuid::Uuid, Path), ("If-Match" = String, Header, description = "The ETag from a GET of the patient, or * to skip the check")),
+    request_body = TransferRequest,
+    responses(
+        (status = 200, description = "The transferred patient", body = Patient, headers(("ETag" = String))),
+        (status = 400, description = "The patient is already in that hospital", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "Either hospital or the patient was not found", body = ApiError, content_type = "application/problem+json"),
+        (status = 409, description = "The patient may not go to that hospital, or it is full", body = ApiError, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the If-Match ETag was sent", body = ApiError, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match header", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn transfer_patient(
+    hospitals: web::Data<HospitalService>,
+    path: web::Path<(String, uuid::Uuid)>,
+    posted: Json<TransferRequest>,
+    user: web::ReqData<User>,
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    let expected_version = etags::expected_version(&req)?;
+    let (ref hospital_name, patient_id) = *path;
+
+    hospitals.transfer_patient(patient_id, hospital_name, &posted.to, expected_version, &user)
+        .await
+        .map(|patient| json_with_etag(&patient, patient.version()))
+        .map_err(ApiError::from)
+}
+
+/// handles GET requests to list a page of the waitlist, such as
+/// /waitlist?name=doe&sort=-waitlistedAt&limit=10, or to download all of it as
+/// CSV
+#[utoipa::path(
+    get,
+    path = "/waitlist",
+    tag = "waitlist",
+    params(WaitlistQuery, FormatQuery),
+    responses(
+        (status = 200, description = "A page of the waitlist, or all of it in order as CSV", content(
+            (Page<WaitlistEntry> = "application/json"),
+            (String = "text/csv")
+        )),
+        (status = 400, description = "Malformed sort, cursor, or format", body = ApiError, content_type = "application/problem+json")
+    )
+)]
 async fn waitlist_get_handler(
-    patients: web::Data<Mutex<PatientService>>
-) -> impl Responder {
-    let mut service = patients.lock().await;
+    patients: web::Data<PatientService>,
+    query: web::Query<WaitlistQuery>, // 400 if sort or cursor is malformed
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    if export::requested_format(&req)? == ExportFormat::Csv {
+        let waitlist = patients.export_waitlist(&query);
+        return export::csv_response("waitlist.csv", waitlist).await;
+    }
+
+    patients.get_waitlist(&query)
+        .await
+        .map(|page| HttpResponse::Ok().json(page))
+        .map_err(ApiError::from)
+}
+
+/// Handles GET requests to receive patient events as they happen, as
+/// Server-Sent Events. The response never ends on its own, so this is best
+/// read with an EventSource or similar.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    responses(
+        (status = 200, description = "patient-waitlisted, patient-admitted, patient-unadmitted, patient-transferred, patient-updated, and patient-withdrawn events, each with a patient event as its data", body = String, content_type = "text/event-stream")
+    )
+)]
+async fn events_get_handler(
+    broadcaster: web::Data<EventBroadcaster> // only sends, so no mutex needed
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(server_sent_events(broadcaster.subscribe()))
+}
+
+/// handles GET requests to list every patient
+#[utoipa::path(
+    get,
+    path = "/patients",
+    tag = "patients",
+    responses(
+        (status = 200, description = "Every patient", body = Vec<Patient>)
+    )
+)]
+async fn patients_get_handler(
+    patients: web::Data<PatientService>
+) -> Result<Json<Vec<Patient>>, ApiError> {
+    patients.get_all_patients()
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
 
-    service.get_waitlisted_patients()
+/// handles GET requests for a single patient
+#[utoipa::path(
+    get,
+    path = "/patients/{patient_id}",
+    tag = "patients",
+    params(("patient_id" = uuid::Uuid, Path)),
+    responses(
+        (status = 200, description = "The patient with that ID", body = Patient, headers(("ETag" = String, description = "Send as If-Match to change the patient"))),
+        (status = 404, description = "No patient has that ID", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn patient_get_handler(
+    patients: web::Data<PatientService>,
+    patient_id: web::Path<uuid::Uuid>
+) -> Result<HttpResponse, ApiError> {
+    patients.get_patient_by_id(*patient_id)
         .await
-        .map(|ps| HttpResponse::Ok().json(ps))
-        .map_err(ErrorInternalServerError)
+        .map_err(ApiError::from)?
+        .map(|patient| json_with_etag(&patient, patient.version()))
+        .ok_or_else(|| ApiError::from(PatientError::NotFound(*patient_id)))
+}
+
+/// the fields of a patient which can be changed after they are created
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all="camelCase", deny_unknown_fields)] // 400 if trying to change anything else
+struct UpdatePatientRequest {
+    name: Option<String>,
+    disallow_admission_to: Option<HashSet<String>>
+}
+
+/// handles PATCH requests to change a patient's name or disallowed hospitals
+#[utoipa::path(
+    patch,
+    path = "/patients/{patient_id}",
+    tag = "patients",
+    params(("patient_id" = uuid::Uuid, Path), ("If-Match" = String, Header, description = "The ETag from a GET request, or * to skip the check")),
+    request_body = UpdatePatientRequest,
+    responses(
+        (status = 200, description = "The updated patient", body = Patient, headers(("ETag" = String))),
+        (status = 400, description = "Invalid name or unknown hospital", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No patient has that ID", body = ApiError, content_type = "application/problem+json"),
+        (status = 412, description = "Changed since the If-Match ETag was sent", body = ApiError, content_type = "application/problem+json"),
+        (status = 428, description = "No If-Match header", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn patient_patch_handler(
+    patients: web::Data<PatientService>,
+    patient_id: web::Path<uuid::Uuid>,
+    posted: Json<UpdatePatientRequest>,
+    user: web::ReqData<User>,
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    let expected_version = etags::expected_version(&req)?;
+    patients.update_patient(*patient_id, posted.name.as_deref(), posted.disallow_admission_to.as_ref(), expected_version, &user)
+        .await
+        .map(|patient| json_with_etag(&patient, patient.version()))
+        .map_err(ApiError::from)
+}
+
+/// handles DELETE requests to withdraw a patient from the waitlist
+#[utoipa::path(
+    delete,
+    path = "/waitlist/{patient_id}",
+    tag = "waitlist",
+    params(("patient_id" = uuid::Uuid, Path)),
+    responses(
+        (status = 204, description = "The patient was withdrawn"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No patient has that ID", body = ApiError, content_type = "application/problem+json"),
+        (status = 409, description = "The patient is not on the waitlist", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn waitlist_delete_handler(
+    patients: web::Data<PatientService>,
+    patient_id: web::Path<uuid::Uuid>,
+    user: web::ReqData<User>
+) -> Result<HttpResponse, ApiError> {
+    patients.withdraw_patient_from_waitlist(*patient_id, &user)
+        .await
+        .map(|_| HttpResponse::NoContent().finish())
+        .map_err(ApiError::from)
+}
+
+/// handles GET requests to replay every change made to a patient
+#[utoipa::path(
+    get,
+    path = "/patients/{patient_id}/history",
+    tag = "patients",
+    params(("patient_id" = uuid::Uuid, Path)),
+    responses(
+        (status = 200, description = "Every change made to the patient, oldest first", body = Vec<PatientEvent>),
+        (status = 404, description = "No patient has that ID", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn patient_history_get_handler(
+    patients: web::Data<PatientService>,
+    patient_id: web::Path<uuid::Uuid>
+) -> Result<Json<Vec<PatientEvent>>, ApiError> {
+    patients.get_patient_history(*patient_id)
+        .await
+        .map_err(ApiError::from)?
+        .map(Json)
+        .ok_or_else(|| ApiError::from(PatientError::NotFound(*patient_id)))
+}
+
+/// handles GET requests to list patients discharged from hospitals
+#[utoipa::path(
+    get,
+    path = "/discharged",
+    tag = "patients",
+    responses(
+        (status = 200, description = "Every discharged patient", body = Vec<Patient>)
+    )
+)]
+async fn discharged_get_handler(
+    patients: web::Data<PatientService>
+) -> Result<Json<Vec<Patient>>, ApiError> {
+    patients.get_discharged_patients()
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
 }
 
 // Can't use the full patient struct, as then the poster could provide the
 // patient ID or other details, which we don't want. It's oftentimes helpful to
 // create structures such as this that only contain a subset of another struct's
 // fields.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 #[serde(rename_all="camelCase")]
 struct NewPatientRequest {
     name: String,
-    disallow_admission_to: Option<HashSet<String>>
+    disallow_admission_to: Option<HashSet<String>>,
+    prefer_admission_to: Option<Vec<String>>,
+    priority: Option<Priority>
 }
 
-/// handles POST requests to add a new patient to the waitlist
+/// handles POST requests to add a new patient to the waitlist. The raw body is
+/// taken instead of Json, so retries can be compared byte-for-byte
+#[utoipa::path(
+    post,
+    path = "/waitlist",
+    tag = "waitlist",
+    params(("Idempotency-Key" = Option<String>, Header, description = "retries with the same key replay the first response")),
+    request_body = NewPatientRequest,
+    responses(
+        (status = 201, description = "The waitlisted patient", body = Patient),
+        (status = 400, description = "Invalid name or idempotency key", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 409, description = "The patient already exists", body = ApiError, content_type = "application/problem+json"),
+        (status = 422, description = "The idempotency key was used for a different request", body = ApiError, content_type = "application/problem+json")
+    )
+)]
 async fn waitlist_post_handler(
-    patients: web::Data<Mutex<PatientService>>,
-    posted: Json<NewPatientRequest>
-) -> impl Responder {
+    patients: web::Data<PatientService>,
+    idempotency: web::Data<IdempotencyService>,
+    body: web::Bytes,
+    user: web::ReqData<User>,
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    let posted: NewPatientRequest = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::bad_request("invalid-body", "Invalid request body", e))?;
     
     let mut patient = Patient::new(&posted.name);
 
     if let Some(ref disallowed_hospitals) = posted.disallow_admission_to {
         patient = patient.with_disallowed_hospitals(disallowed_hospitals);
     }
-    println!("Patient: {:#?}", patient);
+    if let Some(ref preferred_hospitals) = posted.prefer_admission_to {
+        patient = patient.with_preferred_hospitals(preferred_hospitals);
+    }
+    if let Some(priority) = posted.priority {
+        patient = patient.with_priority(priority);
+    }
+    info!(name = %Redacted(patient.name()), priority = ?patient.priority(), "Adding patient to waitlist");
+
+    let waitlist = async {
+        let stored = patients.add_patient_to_waitlist(&patient, &user)
+            .await
+            .map_err(ApiError::from)?;
+        Ok((StatusCode::CREATED, to_json(&stored)))
+    };
+
+    respond_idempotently(&idempotency, &req, &user, "POST /waitlist", &body, waitlist)
+        .await
+}
 
-    // Wait as long as possible before locking - this minimizes the chance of
-    // this request blocking another. (keep a small Critical Section)
-    let mut waitlister = patients.lock().await;
+/// handles POST requests to add every patient in a CSV file to the waitlist,
+/// such as
+/// name,disallowed hospitals,priority
+/// John Doe,Napa;Patton,urgent
+#[utoipa::path(
+    post,
+    path = "/waitlist/import",
+    tag = "waitlist",
+    params(("Idempotency-Key" = Option<String>, Header, description = "retries with the same key replay the first response")),
+    request_body(content = String, content_type = "text/csv", description = "a header row, then a name, semicolon-separated disallowed hospitals, and priority per line"),
+    responses(
+        (status = 200, description = "Which lines were waitlisted, and why any others were not", body = ImportReport),
+        (status = 400, description = "Invalid idempotency key", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 422, description = "The idempotency key was used for a different request", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn waitlist_import_post_handler(
+    patients: web::Data<PatientService>,
+    idempotency: web::Data<IdempotencyService>,
+    body: web::Bytes,
+    user: web::ReqData<User>,
+    req: HttpRequest
+) -> Result<HttpResponse, ApiError> {
+    let import = async {
+        let report = patients.import_patients_to_waitlist(&body, &user)
+            .await
+            .map_err(ApiError::from)?;
+        Ok((StatusCode::OK, to_json(&report)))
+    };
 
-    match waitlister.add_patient_to_waitlist(&patient).await {
-        Ok(stored) => Ok(HttpResponse::Created().json(stored)),
-        Err(e) => match e {
-            PatientError::AlreadyExists(id) => Err(ErrorBadRequest(format!("patient with ID {} already exists", id))),
-            PatientError::Repository(inner) => Err(ErrorInternalServerError(inner)),
-            PatientError::Unsupported => Err(ErrorInternalServerError("?"))
+    respond_idempotently(&idempotency, &req, &user, "POST /waitlist/import", &body, import)
+        .await
+}
+
+/// handles GET requests to list every webhook subscription
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "Every webhook subscription, oldest first", body = Vec<WebhookSubscription>),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn webhooks_get_handler(
+    webhooks: web::Data<WebhookService>
+) -> Result<Json<Vec<WebhookSubscription>>, ApiError> {
+    webhooks.get_subscriptions()
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all="camelCase")]
+struct SubscribeWebhookRequest {
+    /// where to POST events to
+    url: String,
+    event_kinds: Vec<PatientEventKind>
+}
+
+/// a new subscription, along with the secret its deliveries are signed with
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all="camelCase")]
+struct SubscribeWebhookResponse {
+    #[serde(flatten)]
+    subscription: WebhookSubscription,
+
+    /// only sent this once, so keep it somewhere safe
+    secret: String
+}
+
+/// handles POST requests to subscribe a URL to patient events
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = SubscribeWebhookRequest,
+    responses(
+        (status = 201, description = "The new subscription and its secret", body = SubscribeWebhookResponse),
+        (status = 400, description = "Invalid URL or no event kinds", body = ApiError, content_type = "application/problem+json"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn webhooks_post_handler(
+    webhooks: web::Data<WebhookService>,
+    posted: Json<SubscribeWebhookRequest>,
+    user: web::ReqData<User>
+) -> Result<HttpResponse, ApiError> {
+    webhooks.subscribe(&posted.url, &posted.event_kinds, &user)
+        .await
+        .map(|(subscription, secret)| HttpResponse::Created().json(SubscribeWebhookResponse { subscription, secret }))
+        .map_err(ApiError::from)
+}
+
+/// handles GET requests for a single webhook subscription
+#[utoipa::path(
+    get,
+    path = "/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(("webhook_id" = uuid::Uuid, Path)),
+    responses(
+        (status = 200, description = "The webhook subscription with that ID", body = WebhookSubscription),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No webhook subscription has that ID", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn webhook_get_handler(
+    webhooks: web::Data<WebhookService>,
+    webhook_id: web::Path<uuid::Uuid>
+) -> Result<Json<WebhookSubscription>, ApiError> {
+    webhooks.get_subscription(*webhook_id)
+        .await
+        .map_err(ApiError::from)?
+        .map(Json)
+        .ok_or_else(|| ApiError::from(WebhookError::NotFound(*webhook_id)))
+}
+
+/// handles DELETE requests to stop sending events to a webhook
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{webhook_id}",
+    tag = "webhooks",
+    params(("webhook_id" = uuid::Uuid, Path)),
+    responses(
+        (status = 204, description = "The subscription and its delivery log were removed"),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No webhook subscription has that ID", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn webhook_delete_handler(
+    webhooks: web::Data<WebhookService>,
+    webhook_id: web::Path<uuid::Uuid>
+) -> Result<HttpResponse, ApiError> {
+    webhooks.unsubscribe(*webhook_id)
+        .await
+        .map(|_| HttpResponse::NoContent().finish())
+        .map_err(ApiError::from)
+}
+
+/// handles GET requests for the log of attempts to deliver to a webhook
+#[utoipa::path(
+    get,
+    path = "/webhooks/{webhook_id}/deliveries",
+    tag = "webhooks",
+    params(("webhook_id" = uuid::Uuid, Path)),
+    responses(
+        (status = 200, description = "Every delivery attempt to the webhook, newest first", body = Vec<WebhookDelivery>),
+        (status = 403, description = "Not in the admin group", body = ApiError, content_type = "application/problem+json"),
+        (status = 404, description = "No webhook subscription has that ID", body = ApiError, content_type = "application/problem+json")
+    )
+)]
+async fn webhook_deliveries_get_handler(
+    webhooks: web::Data<WebhookService>,
+    webhook_id: web::Path<uuid::Uuid>
+) -> Result<Json<Vec<WebhookDelivery>>, ApiError> {
+    webhooks.get_deliveries(*webhook_id)
+        .await
+        .map(Json)
+        .map_err(ApiError::from)
+}
+
+/// Runs the given action and responds with the JSON it produces. If the client
+/// sent an Idempotency-Key, the response is stored, and retries with the same
+/// key get the stored response back rather than running the action again.
+async fn respond_idempotently(
+    idempotency: &IdempotencyService,
+    req: &HttpRequest,
+    user: &User,
+    endpoint: &str,
+    contents: &[u8],
+    action: impl Future<Output = Result<(StatusCode, String), ApiError>>
+) -> Result<HttpResponse, ApiError> {
+    let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => value.to_str()
+            .map_err(|_| IdempotencyError::InvalidKey(String::from_utf8_lossy(value.as_bytes()).into_owned()))?,
+        None => {
+            let (status, body) = action.await?;
+            return Ok(json_response(status, body));
         }
+    };
+    let request = IdempotentRequest::new(key, user, endpoint, contents)?;
+
+    // held until the response is stored, so a retry sent while the original
+    // is still running waits for it rather than running alongside it
+    let _key = idempotency.lock_key(&request)
+        .await;
+
+    if let Some(stored) = idempotency.find_response(&request).await? {
+        let status = StatusCode::from_u16(stored.status()).map_err(ApiError::internal)?;
+        let mut response = json_response(status, stored.body());
+        response.headers_mut().insert(IDEMPOTENT_REPLAYED_HEADER.parse().unwrap(), "true".parse().unwrap());
+        return Ok(response);
     }
-}
\ No newline at end of file
+
+    let (status, body) = action.await?;
+    // the action already happened, so report its result even if it can't be
+    // stored
+    if let Err(e) = idempotency.store_response(&request, status.as_u16(), &body).await {
+        error!(%key, error = %e, "Failed to store response to idempotency key");
+    }
+    Ok(json_response(status, body))
+}
+
+fn to_json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string(value).expect("response bodies should always serialize")
+}
+
+fn json_response(status: StatusCode, body: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::json())
+        .body(body)
+}
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::Arc, time::Duration};
+
+    use actix_web::{App, HttpMessage, dev::Service, test::{init_service, call_service, TestRequest}};
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use common::complement_service::ComplementService;
+    use futures_util::{future::join_all, stream::LocalBoxStream};
+    use tokio::sync::Barrier;
+
+    use crate::{hospital_services::{HospitalRepository, HospitalEvent, RosterLine}, idempotency_services::tests::MockResponses, patient_services::tests::{MockPatients, MockComplements, events_accepting}};
+
+    use super::*;
+
+    /// the (method, path) of each route registered by configure_hospital_routes,
+    /// read from this file so new routes cannot be forgotten
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        let source = include_str!("routes.rs");
+        let mut routes = BTreeSet::new();
+        let mut path = String::new();
+        for line in source.lines().map(str::trim) {
+            if let Some(rest) = line.strip_prefix("resource(\"") {
+                path = rest.split('"').next().unwrap().to_owned();
+            } else if let Some(rest) = line.strip_prefix(".route(") {
+                let method = rest.split('(').next().unwrap().to_owned();
+                routes.insert((method, path.clone()));
+            }
+        }
+        routes
+    }
+
+    fn documented_routes() -> BTreeSet<(String, String)> {
+        let doc = ApiDoc::openapi();
+        let mut routes = BTreeSet::new();
+        for (path, item) in doc.paths.paths {
+            let methods = [
+                ("get", item.get.is_some()),
+                ("post", item.post.is_some()),
+                ("put", item.put.is_some()),
+                ("patch", item.patch.is_some()),
+                ("delete", item.delete.is_some())
+            ];
+            for (method, _) in methods.iter().filter(|(_, present)| *present) {
+                routes.insert((method.to_string(), path.clone()));
+            }
+        }
+        routes
+    }
+
+    #[test]
+    fn openapi_document_describes_every_route() {
+        let registered = registered_routes();
+
+        assert!(!registered.is_empty());
+        assert_eq!(registered, documented_routes());
+    }
+
+    #[test]
+    fn openapi_document_describes_models_and_bearer_auth() {
+        let components = ApiDoc::openapi().components.unwrap();
+
+        for schema in ["Hospital", "Patient", "NewPatientRequest", "GetHospitalNamesResponse"] {
+            assert!(components.schemas.contains_key(schema), "missing schema {}", schema);
+        }
+        assert!(components.security_schemes.contains_key("bearer"));
+    }
+
+    #[test]
+    fn openapi_document_has_no_dangling_references() {
+        let json = ApiDoc::openapi().to_json().unwrap();
+        let components = ApiDoc::openapi().components.unwrap();
+
+        for reference in json.split("\"#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap();
+            assert!(components.schemas.contains_key(name), "missing schema {}", name);
+        }
+    }
+
+    /// how long the slow repository waits for every transfer to arrive
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    /// stands in for a database which holds each transfer open until every
+    /// transfer has arrived, with every patient admitted to Foo, and Bar empty
+    struct SlowHospitals {
+        patients: Vec<Patient>,
+        transfers: Arc<Barrier>
+    }
+
+    impl SlowHospitals {
+        fn foo(&self) -> Hospital {
+            let mut foo = Hospital::new("Foo").with_id(1);
+            for patient in &self.patients {
+                foo.add_patient(patient.admit_to("Foo"));
+            }
+            foo
+        }
+    }
+
+    #[async_trait]
+    impl HospitalRepository for SlowHospitals {
+        async fn get_all_hospitals(&self) -> Result<Vec<Hospital>, RepositoryError> {
+            Ok(vec![self.foo(), Hospital::new("Bar").with_id(2)])
+        }
+
+        async fn get_hospitals(&self, _query: &HospitalQuery) -> Result<Page<Hospital>, RepositoryError> {
+            Err(RepositoryError::other("not used by the load test"))
+        }
+
+        async fn get_hospital(&self, name: &str) -> Result<Option<Hospital>, RepositoryError> {
+            Ok(match name {
+                "Foo" => Some(self.foo()),
+                "Bar" => Some(Hospital::new("Bar").with_id(2)),
+                _ => None
+            })
+        }
+
+        fn stream_roster(&self, _name_filter: Option<String>, _hospital_name: Option<String>) -> LocalBoxStream<'static, Result<RosterLine, RepositoryError>> {
+            Box::pin(futures_util::stream::once(async { Err(RepositoryError::other("not used by the load test")) }))
+        }
+
+        async fn discharge_patient(&self, _patient_id: uuid::Uuid, _hospital_name: &str, _reason: &str, _discharged_at: DateTime<Utc>, _version: Option<u64>) -> Result<Hospital, RepositoryError> {
+            Err(RepositoryError::other("not used by the load test"))
+        }
+
+        async fn transfer_patient(&self, patient_id: uuid::Uuid, _from: &str, to: &str, _version: Option<u64>) -> Result<Hospital, RepositoryError> {
+            tokio::time::timeout(PATIENCE, self.transfers.wait())
+                .await
+                .map_err(|_| RepositoryError::other("transfers were handled one at a time"))?;
+            let mut target = Hospital::new(to).with_id(2);
+            target.add_patient(Patient::new("Foo").with_id(patient_id).admit_to(to));
+            Ok(target)
+        }
+
+        async fn create_hospital(&self, _name: &str, _capacity: Option<u32>) -> Result<Hospital, RepositoryError> {
+            Err(RepositoryError::other("not used by the load test"))
+        }
+
+        async fn rename_hospital(&self, _name: &str, _new_name: &str, _version: Option<u64>) -> Result<Hospital, RepositoryError> {
+            Err(RepositoryError::other("not used by the load test"))
+        }
+
+        async fn close_hospital(&self, _name: &str, _relocation: Option<RelocationPolicy>, _patient_ids: &[uuid::Uuid], _closed_at: DateTime<Utc>) -> Result<(), RepositoryError> {
+            Err(RepositoryError::other("not used by the load test"))
+        }
+
+        async fn append_event(&self, _event: &HospitalEvent) -> Result<(), RepositoryError> {
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn concurrent_transfers_are_handled_at_the_same_time() {
+        let transfers = 20;
+        let patients: Vec<Patient> = (0..transfers)
+            .map(|_| Patient::new("Foo").with_random_id())
+            .collect();
+        let barrier = Arc::new(Barrier::new(transfers));
+        let mut waitlist = MockPatients::new();
+        waitlist.expect_get_waitlisted_patients()
+            .returning(|| Ok(Vec::new()));
+        let hospital_service = HospitalService::new(SlowHospitals { patients: patients.clone(), transfers: barrier.clone() }, events_accepting());
+        let patient_service = PatientService::new(
+            waitlist,
+            SlowHospitals { patients: patients.clone(), transfers: barrier },
+            events_accepting(),
+            ComplementService::new(MockComplements::new())
+        );
+
+        let app = init_service(App::new()
+            .app_data(web::Data::new(hospital_service))
+            .app_data(web::Data::new(patient_service))
+            .app_data(web::Data::new(IdempotencyService::new(MockResponses::new())))
+            .wrap_fn(|req, srv| {
+                req.extensions_mut().insert(User::new("foo@bar.baz"));
+                srv.call(req)
+            })
+            .configure(configure_hospital_routes)
+        ).await;
+
+        // each transfer waits in the repository until all of them are there,
+        // so they can only succeed if none waits for another to finish
+        let transfer_requests = patients.iter().map(|patient| TestRequest::post()
+            .uri(&format!("/hospitals/Foo/{}/transfer", patient.id().unwrap()))
+            .insert_header(("If-Match", "*"))
+            .set_json(serde_json::json!({ "to": "Bar" }))
+            .to_request());
+        let admission_requests = (0..3).map(|_| TestRequest::post()
+            .uri("/hospitals/admit-from-waitlist")
+            .to_request());
+
+        let responses = join_all(transfer_requests.chain(admission_requests).map(|req| call_service(&app, req)))
+            .await;
+
+        assert!(responses.iter().all(|res| res.status().is_success()));
+    }
+}
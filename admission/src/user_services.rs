@@ -42,7 +42,7 @@ impl UserService {
         }
     }
 
-    pub async fn get_user_by_email(&mut self, email: &str) -> Result<User, UserError> {
+    pub async fn get_user_by_email(&self, email: &str) -> Result<User, UserError> {
         let mut user = User::new(email);
         let groups = self.group_repository.get_groups_by_email(email)
             .await?;
@@ -62,11 +62,11 @@ pub trait GroupRepository: Send + Sync {
     /// Attempts to add a mapping between the given email and group.
     /// It is an error to add a group to a email who already belongs to that 
     /// group.
-    async fn add_email_to_group(&mut self, email: &str, group: &str) -> Result<(), UserError>;
+    async fn add_email_to_group(&self, email: &str, group: &str) -> Result<(), UserError>;
 
     /// Returns all groups associated with the given email.
     /// Note that an email can be associated with no groups.
-    async fn get_groups_by_email(&mut self, email: &str) -> Result<Vec<String>, UserError>;
+    async fn get_groups_by_email(&self, email: &str) -> Result<Vec<String>, UserError>;
 }
 
 #[cfg(test)]
@@ -83,8 +83,8 @@ pub mod tests {
 
         #[async_trait]
         impl GroupRepository for GroupDummy {
-            async fn add_email_to_group(&mut self, email: &str, group: &str) -> Result<(), UserError>;
-            async fn get_groups_by_email(&mut self, email: &str) -> Result<Vec<String>, UserError>;
+            async fn add_email_to_group(&self, email: &str, group: &str) -> Result<(), UserError>;
+            async fn get_groups_by_email(&self, email: &str) -> Result<Vec<String>, UserError>;
         }
     }
 
@@ -101,7 +101,7 @@ pub mod tests {
         group_repo
             .expect_get_groups_by_email()
             .returning(|_email| Ok(Vec::new()));
-        let mut sut = UserService::new(group_repo);
+        let sut = UserService::new(group_repo);
         let email = "foo.bar@baz.qux";
 
         let result = sut.get_user_by_email(email).await;
@@ -116,7 +116,7 @@ pub mod tests {
         group_repo
             .expect_get_groups_by_email()
             .returning(|_email| Ok(vec![String::from("foo"), String::from("bar")]));
-        let mut sut = UserService::new(group_repo);
+        let sut = UserService::new(group_repo);
 
         let result = sut.get_user_by_email("foo.bar@baz.qux").await;
 
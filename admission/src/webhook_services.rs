@@ -0,0 +1,560 @@
+// Webhooks tell other systems, such as billing and transport, about patient
+// events as they happen. Admins subscribe a URL to the kinds of events it
+// wants, then each such event is POSTed to it as JSON, signed with the
+// subscription's secret so the receiver can tell it came from this service.
+// Failed deliveries are retried with exponential backoff, and every attempt is
+// logged so admins can see what each subscriber received.
+
+use std::{collections::BTreeSet, error::Error, fmt::Display, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use common::user::User;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use reqwest::{Client, Url, header::CONTENT_TYPE};
+use serde::Serialize;
+use sha2::Sha256;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use tracing::{warn, error};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::patient_services::{PatientEvent, PatientEventKind};
+
+/// holds "sha256=" followed by the hex HMAC-SHA256 of the body, keyed with the
+/// subscription's secret
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// holds the kind of event being delivered
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+
+/// holds an ID which stays the same across retries of the same delivery, so
+/// receivers can ignore duplicates
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// how many times to try delivering an event if not otherwise configured
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// the most times an event can be configured to be tried
+pub const MAX_ATTEMPTS: u32 = 20;
+
+/// how long to wait before the first retry if not otherwise configured. Each
+/// retry after that waits twice as long as the one before.
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// the longest to wait between retries, however many there have been
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// how long a subscriber has to respond before the attempt counts as failed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct WebhookService {
+    repository: Box<dyn WebhookRepository>
+}
+
+impl WebhookService {
+    pub fn new<T>(repository: T) -> Self
+    where
+        T: WebhookRepository + 'static
+    {
+        Self {
+            repository: Box::new(repository)
+        }
+    }
+
+    /// Subscribes the given URL to the given kinds of events, with a new
+    /// secret to sign them with. This is the only time the secret is returned.
+    pub async fn subscribe(&self, url: &str, event_kinds: &[PatientEventKind], actor: &User) -> Result<(WebhookSubscription, String), WebhookError> {
+        let parsed = Url::parse(url)
+            .map_err(|e| WebhookError::Invalid(format!("Invalid URL {}: {}", url, e)))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(WebhookError::Invalid(format!("URL must use http or https, not {}", parsed.scheme())));
+        }
+        if event_kinds.is_empty() {
+            return Err(WebhookError::Invalid(String::from("Must subscribe to at least one kind of event")));
+        }
+
+        let kinds: Vec<PatientEventKind> = event_kinds.iter()
+            .copied()
+            .collect::<BTreeSet<PatientEventKind>>()
+            .into_iter()
+            .collect();
+        let secret = new_secret();
+        let subscription = WebhookSubscription::restore(Uuid::new_v4(), url, &kinds, &secret, &actor.email(), Utc::now());
+        self.repository.store_subscription(&subscription).await?;
+        Ok((subscription, secret))
+    }
+
+    pub async fn get_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookError> {
+        self.repository.get_all_subscriptions().await
+    }
+
+    pub async fn get_subscription(&self, id: Uuid) -> Result<Option<WebhookSubscription>, WebhookError> {
+        self.repository.get_subscription(id).await
+    }
+
+    /// stops sending events to the given subscription, and forgets its deliveries
+    pub async fn unsubscribe(&self, id: Uuid) -> Result<(), WebhookError> {
+        if self.repository.get_subscription(id).await?.is_none() {
+            return Err(WebhookError::NotFound(id));
+        }
+        self.repository.delete_subscription(id).await
+    }
+
+    /// returns every attempt to deliver an event to the given subscription,
+    /// newest first
+    pub async fn get_deliveries(&self, id: Uuid) -> Result<Vec<WebhookDelivery>, WebhookError> {
+        if self.repository.get_subscription(id).await?.is_none() {
+            return Err(WebhookError::NotFound(id));
+        }
+        self.repository.get_deliveries(id).await
+    }
+}
+
+/// Delivers events to the subscriptions wanting them. Each delivery retries in
+/// the background, so a slow subscriber doesn't hold up the others.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    // shared by every delivery in progress
+    repository: Arc<dyn WebhookRepository>,
+    client: Client,
+    max_attempts: u32,
+    initial_delay: Duration
+}
+
+impl WebhookDispatcher {
+    pub fn new<T>(repository: T) -> Self
+    where
+        T: WebhookRepository + 'static
+    {
+        Self {
+            repository: Arc::new(repository),
+            client: Client::new(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_delay: DEFAULT_INITIAL_DELAY
+        }
+    }
+
+    /// returns a copy of this dispatcher, except it tries each delivery up to
+    /// the given number of times, first waiting the given delay to retry
+    pub fn with_retries(self, max_attempts: u32, initial_delay: Duration) -> Self {
+        Self {
+            repository: self.repository,
+            client: self.client,
+            max_attempts: max_attempts.clamp(1, MAX_ATTEMPTS),
+            initial_delay
+        }
+    }
+
+    /// delivers each event the given receiver gets until it closes
+    pub async fn run(self, mut events: Receiver<PatientEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(event) => self.dispatch(event).await,
+                Err(RecvError::Lagged(missed)) => warn!(missed, "Webhooks fell behind and did not deliver some events"),
+                Err(RecvError::Closed) => break
+            }
+        }
+    }
+
+    /// starts delivering the given event to every subscription wanting it
+    async fn dispatch(&self, event: PatientEvent) {
+        let subscriptions = self.repository.get_all_subscriptions()
+            .await;
+        match subscriptions {
+            Ok(subscriptions) => {
+                for subscription in subscriptions.into_iter().filter(|s| s.event_kinds.contains(&event.kind())) {
+                    actix_web::rt::spawn(self.clone().deliver(subscription, event.clone()));
+                }
+            },
+            Err(e) => error!(error = %e, "Failed to get webhook subscriptions, so cannot deliver event")
+        }
+    }
+
+    /// POSTs the given event to the given subscription until it succeeds or
+    /// runs out of attempts, logging each attempt
+    async fn deliver(self, subscription: WebhookSubscription, event: PatientEvent) {
+        let delivery_id = Uuid::new_v4();
+        let body = serde_json::to_vec(&WebhookPayload { delivery_id, event: &event })
+            .expect("webhook payloads should always serialize");
+        let signature = sign(&subscription.secret, &body);
+
+        for attempt in 1..=self.max_attempts {
+            if attempt > 1 {
+                tokio::time::sleep(retry_delay(self.initial_delay, attempt)).await;
+            }
+
+            let response = self.client.post(&subscription.url)
+                .timeout(REQUEST_TIMEOUT)
+                .header(CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &signature)
+                .header(EVENT_HEADER, event.kind().to_string())
+                .header(DELIVERY_HEADER, delivery_id.to_string())
+                .body(body.clone())
+                .send()
+                .await;
+            let (status_code, error) = match response {
+                Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+                Ok(response) => (Some(response.status().as_u16()), Some(format!("Responded {}", response.status()))),
+                Err(e) => (None, Some(e.to_string()))
+            };
+
+            let succeeded = error.is_none();
+            let delivery = WebhookDelivery::restore(delivery_id, subscription.id, event.kind(), event.patient_id(), attempt, Utc::now(), status_code, error);
+            let logged = self.repository.store_delivery(&delivery)
+                .await;
+            if let Err(e) = logged {
+                error!(%delivery_id, error = %e, "Failed to log webhook delivery");
+            }
+
+            if succeeded {
+                return;
+            }
+        }
+        warn!(%delivery_id, webhook_id = %subscription.id, attempts = self.max_attempts, "Gave up delivering to webhook");
+    }
+}
+
+/// what subscribers receive
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    delivery_id: Uuid,
+    event: &'a PatientEvent
+}
+
+/// how long to wait before the given attempt, doubling the initial delay for
+/// each retry after the first, but never waiting longer than MAX_RETRY_DELAY
+fn retry_delay(initial_delay: Duration, attempt: u32) -> Duration {
+    initial_delay.checked_mul(2u32.saturating_pow(attempt.saturating_sub(2)))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// returns the value of SIGNATURE_HEADER for the given body
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take keys of any size");
+    mac.update(body);
+    let hex: String = mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+/// returns 32 random bytes, hex-encoded
+fn new_secret() -> String {
+    rand::thread_rng()
+        .gen::<[u8; 32]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// a URL which is sent the kinds of events it subscribed to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookSubscription {
+    id: Uuid,
+    url: String,
+    event_kinds: Vec<PatientEventKind>,
+
+    /// signs deliveries, so is never sent back out after subscribing
+    #[serde(skip)]
+    secret: String,
+
+    /// the email of the admin who subscribed
+    created_by: String,
+    created_at: DateTime<Utc>
+}
+
+impl WebhookSubscription {
+    /// recreates a subscription which was previously stored
+    pub fn restore(id: Uuid, url: &str, event_kinds: &[PatientEventKind], secret: &str, created_by: &str, created_at: DateTime<Utc>) -> Self {
+        Self {
+            id,
+            url: url.to_owned(),
+            event_kinds: event_kinds.to_vec(),
+            secret: secret.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn url(&self) -> String {
+        self.url.to_owned()
+    }
+
+    pub fn event_kinds(&self) -> Vec<PatientEventKind> {
+        self.event_kinds.clone()
+    }
+
+    pub fn secret(&self) -> String {
+        self.secret.to_owned()
+    }
+
+    pub fn created_by(&self) -> String {
+        self.created_by.to_owned()
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+/// a single attempt to deliver an event to a subscription
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    /// the same for every attempt to deliver the same event
+    delivery_id: Uuid,
+    subscription_id: Uuid,
+    event_kind: PatientEventKind,
+    patient_id: Uuid,
+
+    /// starts at 1
+    attempt: u32,
+    attempted_at: DateTime<Utc>,
+
+    /// None if the subscriber could not be reached
+    status_code: Option<u16>,
+
+    /// why the attempt failed, or None if it succeeded
+    error: Option<String>
+}
+
+impl WebhookDelivery {
+    /// recreates an attempt which was previously stored
+    #[allow(clippy::too_many_arguments)]
+    pub fn restore(
+        delivery_id: Uuid,
+        subscription_id: Uuid,
+        event_kind: PatientEventKind,
+        patient_id: Uuid,
+        attempt: u32,
+        attempted_at: DateTime<Utc>,
+        status_code: Option<u16>,
+        error: Option<String>
+    ) -> Self {
+        Self {
+            delivery_id,
+            subscription_id,
+            event_kind,
+            patient_id,
+            attempt,
+            attempted_at,
+            status_code,
+            error
+        }
+    }
+
+    pub fn delivery_id(&self) -> Uuid {
+        self.delivery_id
+    }
+
+    pub fn subscription_id(&self) -> Uuid {
+        self.subscription_id
+    }
+
+    pub fn event_kind(&self) -> PatientEventKind {
+        self.event_kind
+    }
+
+    pub fn patient_id(&self) -> Uuid {
+        self.patient_id
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn attempted_at(&self) -> DateTime<Utc> {
+        self.attempted_at
+    }
+
+    pub fn status_code(&self) -> Option<u16> {
+        self.status_code
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+/// backing store for webhook subscriptions and their delivery log
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn get_all_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookError>;
+    async fn get_subscription(&self, id: Uuid) -> Result<Option<WebhookSubscription>, WebhookError>;
+    async fn store_subscription(&self, subscription: &WebhookSubscription) -> Result<(), WebhookError>;
+
+    /// removes the given subscription along with its deliveries
+    async fn delete_subscription(&self, id: Uuid) -> Result<(), WebhookError>;
+    async fn store_delivery(&self, delivery: &WebhookDelivery) -> Result<(), WebhookError>;
+
+    /// returns every delivery to the given subscription, newest first
+    async fn get_deliveries(&self, subscription_id: Uuid) -> Result<Vec<WebhookDelivery>, WebhookError>;
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    NotFound(Uuid),
+    Invalid(String),
+    Repository(Box<dyn Error + 'static>)
+}
+
+impl WebhookError {
+    pub fn repository(inner: impl Error + 'static) -> Self {
+        Self::Repository(Box::new(inner))
+    }
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(id) => write!(f, "No webhook subscription has ID {}", id),
+            Self::Invalid(message) => write!(f, "{}", message),
+            Self::Repository(inner) => write!(f, "Repository error: {}", inner)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as SyncMutex;
+
+    use actix_web::{App, HttpServer, HttpRequest, HttpResponse, web};
+    use common::patient::Patient;
+    use mockall::mock;
+
+    use super::*;
+
+    mock! {
+        Webhooks {
+
+        }
+
+        #[async_trait]
+        impl WebhookRepository for Webhooks {
+            async fn get_all_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookError>;
+            async fn get_subscription(&self, id: Uuid) -> Result<Option<WebhookSubscription>, WebhookError>;
+            async fn store_subscription(&self, subscription: &WebhookSubscription) -> Result<(), WebhookError>;
+            async fn delete_subscription(&self, id: Uuid) -> Result<(), WebhookError>;
+            async fn store_delivery(&self, delivery: &WebhookDelivery) -> Result<(), WebhookError>;
+            async fn get_deliveries(&self, subscription_id: Uuid) -> Result<Vec<WebhookDelivery>, WebhookError>;
+        }
+    }
+
+    #[test]
+    fn sign_uses_hmac_sha256() {
+        // test case 2 from RFC 4231
+        let signature = sign("Jefe", b"what do ya want for nothing?");
+
+        assert_eq!("sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843", signature);
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_limit() {
+        let initial = Duration::from_secs(1);
+
+        assert_eq!(Duration::from_secs(1), retry_delay(initial, 2));
+        assert_eq!(Duration::from_secs(4), retry_delay(initial, 4));
+        assert_eq!(MAX_RETRY_DELAY, retry_delay(initial, 40));
+        assert_eq!(MAX_RETRY_DELAY, retry_delay(Duration::from_secs(u64::MAX), 3));
+    }
+
+    #[tokio::test]
+    async fn subscribe_given_invalid_url_fails() {
+        let mut repo = MockWebhooks::new();
+        repo.expect_store_subscription()
+            .never();
+        let sut = WebhookService::new(repo);
+        let user = User::new("foo.bar@baz.qux");
+
+        assert!(sut.subscribe("not a url", &[PatientEventKind::Admitted], &user).await.is_err());
+        assert!(sut.subscribe("ftp://foo.bar/baz", &[PatientEventKind::Admitted], &user).await.is_err());
+        assert!(sut.subscribe("http://foo.bar/baz", &[], &user).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn subscribe_given_repeated_kinds_subscribes_to_each_once() {
+        let mut repo = MockWebhooks::new();
+        repo.expect_store_subscription()
+            .once()
+            .returning(|_| Ok(()));
+        let sut = WebhookService::new(repo);
+        let kinds = [PatientEventKind::Admitted, PatientEventKind::Withdrawn, PatientEventKind::Admitted];
+
+        let (subscription, _) = sut.subscribe("http://foo.bar/baz", &kinds, &User::new("foo.bar@baz.qux")).await.unwrap();
+
+        assert_eq!(vec![PatientEventKind::Admitted, PatientEventKind::Withdrawn], subscription.event_kinds());
+    }
+
+    /// what the stand-in subscriber received
+    type Received = Arc<SyncMutex<Vec<(String, web::Bytes)>>>;
+
+    /// fails the first request it receives, then succeeds
+    async fn flaky_subscriber(req: HttpRequest, body: web::Bytes, received: web::Data<Received>) -> HttpResponse {
+        let signature = req.headers()
+            .get(SIGNATURE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        let mut received = received.lock().unwrap();
+        received.push((signature, body));
+        if received.len() == 1 {
+            HttpResponse::ServiceUnavailable().finish()
+        } else {
+            HttpResponse::NoContent().finish()
+        }
+    }
+
+    #[actix_web::test]
+    async fn deliver_retries_signed_payload_until_it_succeeds() {
+        let received: Received = Default::default();
+        let data = web::Data::new(received.clone());
+        let server = HttpServer::new(move || App::new()
+                .app_data(data.clone())
+                .default_service(web::to(flaky_subscriber)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let address = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let logged: Arc<SyncMutex<Vec<WebhookDelivery>>> = Default::default();
+        let log = logged.clone();
+        let mut repo = MockWebhooks::new();
+        repo.expect_store_delivery()
+            .returning(move |delivery| {
+                log.lock().unwrap().push(delivery.clone());
+                Ok(())
+            });
+        let sut = WebhookDispatcher::new(repo)
+            .with_retries(3, Duration::from_millis(1));
+        let subscription = WebhookSubscription::restore(Uuid::new_v4(), &format!("http://{}/hook", address), &[PatientEventKind::Admitted], "secret", "foo.bar@baz.qux", Utc::now());
+        let event = PatientEvent::new(PatientEventKind::Admitted, &User::new("foo.bar@baz.qux"), None, &Patient::new("Foo").with_random_id());
+
+        sut.deliver(subscription, event).await;
+        handle.stop(false).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(2, received.len());
+        for (signature, body) in received.iter() {
+            assert_eq!(&sign("secret", body), signature);
+        }
+        let logged = logged.lock().unwrap();
+        let outcomes: Vec<(u32, Option<u16>, bool)> = logged.iter()
+            .map(|delivery| (delivery.attempt(), delivery.status_code(), delivery.error().is_none()))
+            .collect();
+        assert_eq!(vec![(1, Some(503), false), (2, Some(204), true)], outcomes);
+    }
+}
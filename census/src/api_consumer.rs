@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use common::hospital::{HospitalDataProvider, Hospital, Error};
+use common::hospital::{HospitalDataProvider, Hospital, HospitalPage, Error};
 
 use common::http_client::HttpClient;
 
@@ -20,14 +20,25 @@ impl ExternalHospitalDataProvider {
 impl HospitalDataProvider for ExternalHospitalDataProvider {
     async fn get_all_hospitals(&self) ->  Result<Vec<Hospital>, Error> {
 
-        // consume the API provided by the admission project
-        let response: Vec<Hospital> = self.http_client.get("/api/v1/hospitals")
-            .await
-            .map_err(Error::external_service_error)?
-            .json()
-            .await
-            .map_err(Error::external_service_error)?;
+        // consume the API provided by the admission project, which lists
+        // hospitals a page at a time
+        let mut hospitals: Vec<Hospital> = Vec::new();
+        let mut endpoint = String::from("/api/v1/hospitals?limit=200");
+        loop {
+            let page: HospitalPage = self.http_client.get(&endpoint)
+                .await
+                .map_err(Error::external_service_error)?
+                .json()
+                .await
+                .map_err(Error::external_service_error)?;
+            hospitals.extend(page.hospitals());
+
+            match page.next_cursor() {
+                Some(cursor) => endpoint = format!("/api/v1/hospitals?limit=200&cursor={}", cursor),
+                None => break
+            }
+        }
         
-        Ok(response)
+        Ok(hospitals)
     }
 }
\ No newline at end of file
@@ -0,0 +1,58 @@
+// Everything census can be configured with, read from the [census] and
+// [logging] sections of the configuration file. Each setting can be overridden
+// by the environment variable named beside it.
+
+use common::config::{Settings, Overrides, Problems, LoggingConfig};
+use serde::Deserialize;
+
+/// other binaries' sections are ignored, so they can share a file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub census: CensusConfig
+}
+
+impl Settings for Config {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        self.logging.override_from(environment);
+        self.census.override_from(environment);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        self.logging.validate(problems);
+        self.census.validate(problems);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CensusConfig {
+    /// where hospitals are fetched from (ADMISSION_URL)
+    pub admission_url: String,
+
+    /// who conducts the census, which must be allowed to read every hospital
+    /// (CENSUS_LOGIN)
+    pub login: String
+}
+
+impl Default for CensusConfig {
+    fn default() -> Self {
+        Self {
+            admission_url: String::from("http://localhost:8080"),
+            login: String::from("admin@dsh.ca.gov")
+        }
+    }
+}
+
+impl Settings for CensusConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set("ADMISSION_URL", &mut self.admission_url);
+        environment.set("CENSUS_LOGIN", &mut self.login);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        problems.require_url("census.admission_url", "ADMISSION_URL", &self.admission_url);
+        problems.require("census.login", "CENSUS_LOGIN", &self.login);
+    }
+}
@@ -1,18 +1,26 @@
-use common::{user::LoginRequest, http_client::HttpClient};
+use common::{user::LoginRequest, http_client::HttpClient, config::load as load_config, logging::init_logging};
+use tracing::{info, error};
 
-use crate::{census::CensusService, api_consumer::ExternalHospitalDataProvider};
+use crate::{census::CensusService, api_consumer::ExternalHospitalDataProvider, config::Config};
 
 mod api_consumer;
 mod census;
+mod config;
 
 #[tokio::main]
 async fn main() {
-    println!("Conducting census...");
+    // nothing can be logged until the configuration says how, so report any
+    // problems with it by crashing
+    let config: Config = load_config()
+        .unwrap_or_else(|e| panic!("{}", e));
+    init_logging(&config.logging);
+    let config = config.census;
 
-    let root_url = "http://localhost:8080"; // todo read from somewhere
-    let conductor = LoginRequest::new("admin@dsh.ca.gov"); // todo read from somewhere
+    info!("Conducting census...");
 
-    let mut http_client = HttpClient::new(root_url);
+    let conductor = LoginRequest::new(&config.login);
+
+    let mut http_client = HttpClient::new(&config.admission_url);
     http_client.authenticate_as(&conductor)
         .await
         .expect("should be able to authenticate");
@@ -22,7 +30,7 @@ async fn main() {
 
     let result = census_service.conduct_census().await;
     match result {
-        Ok(census) => println!("Done with census! Result: \n{}", census),
-        Err(error) => println!("Failed to conduct census: {:#?}", error)
+        Ok(census) => info!("Done with census! Result: \n{}", census),
+        Err(error) => error!(?error, "Failed to conduct census")
     };
 }
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, error::Error, fmt::Display};
 
 use async_trait::async_trait;
 
@@ -13,7 +13,7 @@ impl ComplementService {
         Self(Box::new(inner))
     }
 
-    pub async fn compute_complement(&self, set: HashSet<String>) -> HashSet<String> {
+    pub async fn compute_complement(&self, set: HashSet<String>) -> Result<HashSet<String>, ComplementError> {
         self.0.compute_complement(set).await
     }
 }
@@ -21,6 +21,24 @@ impl ComplementService {
 #[async_trait]
 pub trait ComplementProvider: Send + Sync {
     /// returns the complement of the given set according to the universal set
-    /// this service is configured for 
-    async fn compute_complement(&self, set: HashSet<String>) -> HashSet<String>;
-}
\ No newline at end of file
+    /// this service is configured for, or an error if it cannot be computed,
+    /// such as if a remote provider is unavailable
+    async fn compute_complement(&self, set: HashSet<String>) -> Result<HashSet<String>, ComplementError>;
+}
+
+#[derive(Debug)]
+pub struct ComplementError(String);
+
+impl ComplementError {
+    pub fn new(cause: impl Display) -> Self {
+        Self(cause.to_string())
+    }
+}
+
+impl Display for ComplementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not compute complement: {}", self.0)
+    }
+}
+
+impl Error for ComplementError {}
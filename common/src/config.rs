@@ -0,0 +1,188 @@
+// Loads typed configuration from a TOML file, then lets environment variables
+// override any of it, so secrets never need to be written to the file. Each
+// binary validates its configuration as it starts, reporting every problem at
+// once, rather than one problem per restart.
+
+use std::{env, fmt::Display, fs, io::ErrorKind, str::FromStr};
+
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing_subscriber::EnvFilter;
+
+use crate::logging::LogFormat;
+
+/// where configuration is read from, unless CONFIG_FILE names another file
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// names the configuration file to read instead of the default
+pub const CONFIG_FILE_ENV: &str = "CONFIG_FILE";
+
+/// configuration, or a section of it, which can be loaded
+pub trait Settings {
+    /// replaces settings with any environment variables set for them
+    fn override_from(&mut self, environment: &mut Overrides);
+
+    /// adds a problem for each setting which is missing or invalid
+    fn validate(&self, problems: &mut Problems);
+}
+
+/// Reads the configuration file, then applies overrides from the environment.
+/// The default file does not need to exist, as everything has a default or
+/// can be set from the environment, but one named by CONFIG_FILE does.
+pub fn load<T>() -> Result<T, ConfigError>
+where
+    T: Settings + DeserializeOwned
+{
+    let (path, required) = match env::var(CONFIG_FILE_ENV) {
+        Ok(path) => (path, true),
+        Err(_) => (String::from(DEFAULT_CONFIG_FILE), false)
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound && !required => String::new(),
+        Err(e) => return Err(ConfigError(vec![format!("Cannot read {}: {}", path, e)]))
+    };
+
+    parse(&text, |name| env::var(name).ok())
+}
+
+/// parses the given TOML, then applies overrides from the given lookup rather
+/// than the environment, so configuration can be tested
+pub fn parse<T, F>(text: &str, lookup: F) -> Result<T, ConfigError>
+where
+    T: Settings + DeserializeOwned,
+    F: Fn(&str) -> Option<String> + 'static
+{
+    let mut config: T = toml::from_str(text)
+        .map_err(|e| ConfigError(vec![format!("Invalid configuration file: {}", e)]))?;
+
+    let mut environment = Overrides::new(lookup);
+    config.override_from(&mut environment);
+
+    let mut problems = environment.problems;
+    config.validate(&mut problems);
+    if problems.0.is_empty() {
+        Ok(config)
+    } else {
+        Err(ConfigError(problems.0))
+    }
+}
+
+/// finds the value of an environment variable
+type Lookup = Box<dyn Fn(&str) -> Option<String>>;
+
+/// reads environment variables over settings, collecting every one which
+/// cannot be parsed
+pub struct Overrides {
+    lookup: Lookup,
+    problems: Problems
+}
+
+impl Overrides {
+    fn new(lookup: impl Fn(&str) -> Option<String> + 'static) -> Self {
+        Self {
+            lookup: Box::new(lookup),
+            problems: Problems::default()
+        }
+    }
+
+    /// replaces the given setting with the named variable, if it is set
+    pub fn set<T>(&mut self, name: &str, setting: &mut T)
+    where
+        T: FromStr,
+        T::Err: Display
+    {
+        if let Some(value) = self.read(name) {
+            *setting = value;
+        }
+    }
+
+    /// like set, except for settings which can be left out
+    pub fn set_optional<T>(&mut self, name: &str, setting: &mut Option<T>)
+    where
+        T: FromStr,
+        T::Err: Display
+    {
+        if let Some(value) = self.read(name) {
+            *setting = Some(value);
+        }
+    }
+
+    fn read<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display
+    {
+        let value = (self.lookup)(name)?;
+        value.parse()
+            .map_err(|e| self.problems.add(format!("{} is invalid: {}", name, e)))
+            .ok()
+    }
+}
+
+/// everything wrong with some configuration
+#[derive(Debug, Default)]
+pub struct Problems(Vec<String>);
+
+impl Problems {
+    pub fn add(&mut self, problem: impl ToString) {
+        self.0.push(problem.to_string());
+    }
+
+    /// adds a problem if the given setting is blank
+    pub fn require(&mut self, setting: &str, env: &str, value: &str) {
+        if value.trim().is_empty() {
+            self.add(format!("{} must be set, either in the file or with {}", setting, env));
+        }
+    }
+
+    /// adds a problem if the given setting is not an absolute HTTP(S) URL
+    pub fn require_url(&mut self, setting: &str, env: &str, value: &str) {
+        if value.trim().is_empty() {
+            return self.require(setting, env, value);
+        }
+        match Url::parse(value) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {},
+            Ok(_) => self.add(format!("{} must be an http or https URL, not {}", setting, value)),
+            Err(e) => self.add(format!("{} must be a URL, not {}: {}", setting, value, e))
+        }
+    }
+}
+
+/// lists every problem with the configuration, one per line
+#[derive(Debug)]
+pub struct ConfigError(Vec<String>);
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n- {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// the [logging] section, which every binary reads
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// which logs are written, such as "info" or "admission=debug,info"
+    pub filter: Option<String>,
+    pub format: LogFormat
+}
+
+impl Settings for LoggingConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set_optional("RUST_LOG", &mut self.filter);
+        environment.set("LOG_FORMAT", &mut self.format);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        if let Some(Err(e)) = self.filter.as_deref().map(EnvFilter::try_new) {
+            problems.add(format!("logging.filter is invalid: {}", e));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Liveness and readiness reports, shared by every service so orchestrators can
+// read them the same way. Liveness only says the process is responding, while
+// readiness checks each dependency the service needs to do its job.
+
+use std::{fmt::Display, future::Future, time::{Duration, Instant}};
+
+use serde::Serialize;
+
+/// how long a dependency has to respond before it counts as down
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down
+}
+
+/// how a single dependency responded to a readiness check
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DependencyStatus {
+    name: String,
+    status: Status,
+    latency_ms: u64,
+
+    /// why the dependency is down, or None if it is up
+    error: Option<String>
+}
+
+impl DependencyStatus {
+    pub fn status(&self) -> Status {
+        self.status
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// down if any dependency is down
+    status: Status,
+    dependencies: Vec<DependencyStatus>
+}
+
+impl HealthReport {
+    /// a report for a process which is responding, without checking anything
+    pub fn live() -> Self {
+        Self::new(Vec::new())
+    }
+
+    pub fn new(dependencies: Vec<DependencyStatus>) -> Self {
+        let status = if dependencies.iter().all(|d| d.status() == Status::Up) {
+            Status::Up
+        } else {
+            Status::Down
+        };
+        Self {
+            status,
+            dependencies
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+}
+
+/// Runs the given check on the dependency with the given name, timing how long
+/// it takes. Checks which take longer than the given timeout are abandoned and
+/// count as down.
+pub async fn check<F, E>(name: &str, timeout: Duration, check: F) -> DependencyStatus
+where
+    F: Future<Output = Result<(), E>>,
+    E: Display
+{
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, check).await;
+    let latency_ms = start.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("Timed out after {}ms", timeout.as_millis()))
+    };
+    DependencyStatus {
+        name: name.to_owned(),
+        status: if error.is_none() { Status::Up } else { Status::Down },
+        latency_ms,
+        error
+    }
+}
@@ -1,16 +1,28 @@
 use async_trait::async_trait;
 use std::{fmt::{Debug, Display}, collections::HashSet};
 use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
 
 use crate::patient::Patient;
 
 #[derive(Debug)]
 #[derive(Serialize, Deserialize)] // allows this to be converted to & from JSON
+#[derive(ToSchema)] // allows this to be described in the OpenAPI document
 #[serde(rename_all = "camelCase")]
 pub struct Hospital {
     id: Option<u32>, // Option means this could potentially have no ID 
     name: String,
-    patients: Vec<Patient>
+
+    /// the number of beds this hospital has, or None if its capacity is not
+    /// tracked, in which case it can accept any number of patients
+    capacity: Option<u32>,
+    patients: Vec<Patient>,
+
+    /// changes whenever this hospital's own details are stored, so stale
+    /// copies can be detected. Sent to clients as an ETag rather than in the
+    /// body.
+    #[serde(skip)]
+    version: Option<u64>
 }
 
 impl Hospital {
@@ -18,7 +30,9 @@ impl Hospital {
         Self {
             id: None,
             name: name.to_owned(),
-            patients: Vec::new()
+            capacity: None,
+            patients: Vec::new(),
+            version: None
         }
     }
 
@@ -26,7 +40,31 @@ impl Hospital {
         Self {
             id: Some(id),
             name: self.name.to_owned(),
-            patients: self.patients.clone()
+            capacity: self.capacity,
+            patients: self.patients.clone(),
+            version: self.version
+        }
+    }
+
+    /// returns a copy of this hospital, except with the given number of beds
+    pub fn with_capacity(&self, capacity: u32) -> Self {
+        Self {
+            id: self.id,
+            name: self.name.to_owned(),
+            capacity: Some(capacity),
+            patients: self.patients.clone(),
+            version: self.version
+        }
+    }
+
+    /// returns a copy of this hospital, except as of the given stored version
+    pub fn with_version(&self, version: u64) -> Self {
+        Self {
+            id: self.id,
+            name: self.name.to_owned(),
+            capacity: self.capacity,
+            patients: self.patients.clone(),
+            version: Some(version)
         }
     }
 
@@ -45,6 +83,31 @@ impl Hospital {
     pub fn patients(&self) -> Vec<Patient> {
         self.patients.clone()
     }
+
+    /// returns the number of beds this hospital has, or None if its capacity
+    /// is not tracked
+    pub fn capacity(&self) -> Option<u32> {
+        self.capacity
+    }
+
+    /// returns how many more patients this hospital can accept, or None if
+    /// there is no limit
+    pub fn available_beds(&self) -> Option<u32> {
+        self.capacity.map(|capacity| {
+            let occupied = u32::try_from(self.patients.len()).unwrap_or(u32::MAX);
+            capacity.saturating_sub(occupied)
+        })
+    }
+
+    /// returns whether this hospital can accept at least one more patient
+    pub fn has_room(&self) -> bool {
+        self.available_beds() != Some(0)
+    }
+
+    /// returns the version of this hospital as last stored, or None if unknown
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
 }
 
 impl Clone for Hospital {
@@ -52,7 +115,9 @@ impl Clone for Hospital {
         Self {
             id: self.id,
             name: self.name.to_string(),
-            patients: self.patients.iter().map(|p| p.to_owned()).collect()
+            capacity: self.capacity,
+            patients: self.patients.iter().map(|p| p.to_owned()).collect(),
+            version: self.version
         }
     }
 }
@@ -107,7 +172,7 @@ impl Default for GetHospitalNamesRequest {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct GetHospitalNamesResponse {
     hospital_names: HashSet<String>
@@ -125,6 +190,27 @@ impl GetHospitalNamesResponse {
     }
 }
 
+/// a single page of hospitals, as listed by the admission API
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HospitalPage {
+    items: Vec<Hospital>,
+
+    /// pass this as the cursor query parameter to get the next page, or None
+    /// if this is the last page
+    next_cursor: Option<String>
+}
+
+impl HospitalPage {
+    pub fn hospitals(&self) -> Vec<Hospital> {
+        self.items.clone()
+    }
+
+    pub fn next_cursor(&self) -> Option<String> {
+        self.next_cursor.clone()
+    }
+}
+
 #[derive(Debug)]
 pub enum HospitalError {
     Other,
@@ -148,5 +234,5 @@ impl Display for HospitalError {
 
 #[async_trait]
 pub trait GetHospitalNames {
-    async fn get_hospital_names(&mut self, request: GetHospitalNamesRequest) -> Result<GetHospitalNamesResponse, HospitalError>;
+    async fn get_hospital_names(&self, request: GetHospitalNamesRequest) -> Result<GetHospitalNamesResponse, HospitalError>;
 }
\ No newline at end of file
@@ -1,10 +1,11 @@
 // sets up authenticated & authorized client to consume APIs
 
-use crate::user::LoginRequest;
+use crate::{user::LoginRequest, logging::Redacted};
 use reqwest::{
     Result,
     Response
 };
+use tracing::debug;
 
 /// basic HTTP client wrapper
 pub struct HttpClient {
@@ -39,6 +40,7 @@ impl HttpClient {
             .await?
             .text()
             .await?;
+        debug!(url = %self.root_url, token = %Redacted(&token), "Authenticated");
         self.bearer = Some(token.to_owned());
 
         Ok(token)
@@ -55,7 +57,7 @@ impl HttpClient {
         }
 
         let result = builder.send().await?;
-        println!("HTTP client received {:#?}", result);
+        debug!(url = %result.url(), status = %result.status(), "HTTP client received response");
 
         Ok(result)
     }
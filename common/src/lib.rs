@@ -1,5 +1,9 @@
 pub mod complement_service;
+pub mod config;
+pub mod health;
 pub mod hospital;
 pub mod http_client;
+pub mod logging;
+pub mod metrics;
 pub mod patient;
 pub mod user;
\ No newline at end of file
@@ -0,0 +1,94 @@
+// Sets up structured logging the same way for every service. The filter decides
+// which levels are logged, such as "info" or "admission=debug,info", and the
+// format is either human-readable or one JSON object per line.
+// https://docs.rs/tracing-subscriber/latest/tracing_subscriber/filter/struct.EnvFilter.html
+
+use std::{fmt::{Debug, Display}, str::FromStr};
+
+use serde::Deserialize;
+use tracing::{Span, info_span};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::config::LoggingConfig;
+
+/// what is logged if no filter is configured
+pub const DEFAULT_FILTER: &str = "info";
+
+/// Lets clients and other services pass along the ID of the request which
+/// caused theirs, so logs can be followed across services. Responses always
+/// include it.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// longer request IDs from clients are replaced, so they can't flood the logs
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+/// how log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown log format {}, expected pretty or json", s))
+        }
+    }
+}
+
+/// Starts writing logs to stdout as configured. Must only be called once,
+/// before anything is logged.
+pub fn init_logging(config: &LoggingConfig) {
+    let filter = EnvFilter::new(config.filter.as_deref().unwrap_or(DEFAULT_FILTER));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter);
+
+    match config.format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder.json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init()
+    }
+}
+
+/// Returns the request ID the client sent, or a new one if they did not send a
+/// usable one.
+pub fn request_id(given: Option<&str>) -> String {
+    given
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// everything logged while handling a request is logged within this span, so
+/// each line says which request it was for
+pub fn request_span(request_id: &str, method: &str, route: &str) -> Span {
+    info_span!("request", request_id, method, route)
+}
+
+/// Wraps a value which must never be logged, such as a token or a patient's
+/// name, so it can still be passed to log fields without leaking.
+pub struct Redacted<T>(pub T);
+
+impl<T> Debug for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+impl<T> Display for Redacted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
@@ -0,0 +1,67 @@
+// Prometheus metrics shared by every service, so their dashboards can be built
+// the same way. Each service registers its own metrics alongside these.
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::time::Duration;
+
+use prometheus::{Registry, HistogramVec, HistogramOpts, Encoder, TextEncoder};
+
+/// the media type of the text exposition format
+pub use prometheus::TEXT_FORMAT;
+
+/// what requests which did not match any route are labelled as, so unknown
+/// paths cannot make endless label values
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// records how long each request takes, labelled by method, route pattern, and
+/// status
+#[derive(Clone)]
+pub struct HttpMetrics {
+    registry: Registry,
+    request_duration: HistogramVec
+}
+
+impl HttpMetrics {
+    pub fn new() -> Self {
+        let request_duration = HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "How long HTTP requests take to respond to"),
+                &["method", "route", "status"]
+            )
+            .expect("HTTP request metric should be valid");
+        let registry = Registry::new();
+        registry.register(Box::new(request_duration.clone()))
+            .expect("HTTP request metric should only be registered once");
+
+        Self {
+            registry,
+            request_duration
+        }
+    }
+
+    /// where services register their own metrics
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// records a request to the given route pattern, such as /hospitals/{name}
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.request_duration
+            .with_label_values(&[method, route, &status.to_string()])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// returns every registered metric in the text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics should always encode");
+        String::from_utf8(buffer).expect("metrics should be UTF-8")
+    }
+}
+
+impl Default for HttpMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
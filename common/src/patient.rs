@@ -1,17 +1,123 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt::Display};
+use chrono::{DateTime, Utc, Duration};
 use serde::{Serialize, Deserialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// how urgently a patient needs to be admitted to a hospital. Sorting
+/// priorities puts the most urgent first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Priority {
+    Urgent,
+    High,
+    #[default]
+    Normal
+}
+
+impl Priority {
+    /// returns this priority as a number, where lower is more urgent
+    pub fn rank(&self) -> u8 {
+        match self {
+            Self::Urgent => 0,
+            Self::High => 1,
+            Self::Normal => 2
+        }
+    }
+
+    /// the inverse of rank, or None if there is no priority with that rank
+    pub fn from_rank(rank: u8) -> Option<Self> {
+        match rank {
+            0 => Some(Self::Urgent),
+            1 => Some(Self::High),
+            2 => Some(Self::Normal),
+            _ => None
+        }
+    }
+}
+
+impl Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Urgent => write!(f, "urgent"),
+            Self::High => write!(f, "high"),
+            Self::Normal => write!(f, "normal")
+        }
+    }
+}
+
+/// records a patient leaving the hospital they were admitted to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Discharge {
+    /// the name of the hospital the patient was discharged from
+    hospital: String,
+    reason: String,
+    discharged_at: DateTime<Utc>
+}
+
+impl Discharge {
+    pub fn new(hospital: &str, reason: &str, discharged_at: DateTime<Utc>) -> Self {
+        Self {
+            hospital: hospital.to_owned(),
+            reason: reason.to_owned(),
+            discharged_at
+        }
+    }
+
+    pub fn hospital(&self) -> String {
+        self.hospital.to_owned()
+    }
+
+    pub fn reason(&self) -> String {
+        self.reason.to_owned()
+    }
+
+    pub fn discharged_at(&self) -> DateTime<Utc> {
+        self.discharged_at
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Patient {
     id: Option<Uuid>,
     name: String,
     disallow_admission_to: HashSet<String>,
 
+    /// the hospitals this patient would like to be admitted to, most preferred
+    /// first
+    #[serde(default)]
+    prefer_admission_to: Vec<String>,
+
+    /// how urgently this patient needs to be admitted
+    #[serde(default)]
+    priority: Priority,
+
+    /// when this patient was added to the waitlist
+    waitlisted_at: Option<DateTime<Utc>>,
+
+    /// when this patient was admitted to a hospital, or None if they are on
+    /// the waitlist
+    admitted_at: Option<DateTime<Utc>>,
+
     /// the name of the hospital this patient is admitted to, or none if they
     /// are on the waitlist to get into a hospital
-    admitted_to: Option<String>
+    admitted_to: Option<String>,
+
+    /// how this patient left the hospital, or None if they have not been
+    /// discharged
+    discharge: Option<Discharge>,
+
+    /// when this patient was withdrawn from the waitlist, or None if they
+    /// have not been withdrawn
+    #[serde(default)]
+    withdrawn_at: Option<DateTime<Utc>>,
+
+    /// changes whenever this patient is stored, so stale copies can be
+    /// detected. Sent to clients as an ETag rather than in the body.
+    #[serde(skip)]
+    version: Option<u64>
 }
 
 impl PartialEq for Patient {
@@ -26,16 +132,21 @@ impl Patient {
             id: None,
             name: name.to_owned(),
             disallow_admission_to: HashSet::new(),
-            admitted_to: None
+            prefer_admission_to: Vec::new(),
+            priority: Priority::default(),
+            waitlisted_at: None,
+            admitted_at: None,
+            admitted_to: None,
+            discharge: None,
+            withdrawn_at: None,
+            version: None
         }
     }
 
     pub fn with_id(&self, id: Uuid) -> Self {
         Self {
             id: Some(id),
-            name: self.name.to_owned(),
-            disallow_admission_to: self.disallow_admission_to.to_owned(),
-            admitted_to: self.admitted_to()
+            ..self.clone()
         }
     }
 
@@ -43,35 +154,101 @@ impl Patient {
         self.with_id(Uuid::new_v4())
     }
 
+    /// returns a copy of this patient, except with the given name
+    pub fn with_name(&self, name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..self.clone()
+        }
+    }
+
     pub fn with_disallowed_hospitals(
         &self, 
         disallowed_hospitals: &HashSet<String>
     ) -> Self {
         Self {
-            id: self.id.to_owned(),
-            name: self.name.to_owned(),
             disallow_admission_to: disallowed_hospitals.to_owned(),
-            admitted_to: self.admitted_to()
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except preferring the given hospitals,
+    /// most preferred first
+    pub fn with_preferred_hospitals(&self, preferred_hospitals: &[String]) -> Self {
+        Self {
+            prefer_admission_to: preferred_hospitals.to_vec(),
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except with the given priority
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self {
+            priority,
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except added to the waitlist at the
+    /// given time
+    pub fn with_waitlisted_at(&self, waitlisted_at: DateTime<Utc>) -> Self {
+        Self {
+            waitlisted_at: Some(waitlisted_at),
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except admitted at the given time
+    pub fn with_admitted_at(&self, admitted_at: DateTime<Utc>) -> Self {
+        Self {
+            admitted_at: Some(admitted_at),
+            ..self.clone()
         }
     }
 
     /// returns a copy of this patient except admitted to the given hospital
     pub fn admit_to(&self, hospital: &str) -> Self {
         Self {
-            id: self.id(),
-            name: self.name(),
-            disallow_admission_to: self.disallowed_hospitals(),
-            admitted_to: Some(hospital.to_owned())
+            admitted_to: Some(hospital.to_owned()),
+            discharge: None,
+            ..self.clone()
         }
     }
 
     /// returns a copy of this patient, but on the waitlist
     pub fn waitlisted(&self) -> Self {
         Self {
-            id: self.id(),
-            name: self.name(),
-            disallow_admission_to: self.disallowed_hospitals(),
-            admitted_to: None
+            admitted_to: None,
+            admitted_at: None,
+            discharge: None,
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except discharged from the hospital
+    /// they were admitted to. Their admission time is kept for their records.
+    pub fn discharged(&self, discharge: Discharge) -> Self {
+        Self {
+            admitted_to: None,
+            discharge: Some(discharge),
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except withdrawn from the waitlist at
+    /// the given time
+    pub fn withdrawn(&self, withdrawn_at: DateTime<Utc>) -> Self {
+        Self {
+            withdrawn_at: Some(withdrawn_at),
+            ..self.clone()
+        }
+    }
+
+    /// returns a copy of this patient, except as of the given stored version
+    pub fn with_version(&self, version: u64) -> Self {
+        Self {
+            version: Some(version),
+            ..self.clone()
         }
     }
 
@@ -79,6 +256,11 @@ impl Patient {
         self.disallow_admission_to.insert(String::from(hospital));
     }
 
+    /// adds the given hospital as this patient's least preferred hospital
+    pub fn add_preferred_hospital(&mut self, hospital: &str) {
+        self.prefer_admission_to.push(String::from(hospital));
+    }
+
     pub fn name(&self) -> String {
         self.name.to_owned()
     }
@@ -91,6 +273,32 @@ impl Patient {
         self.disallow_admission_to.to_owned()
     }
 
+    /// returns the hospitals this patient would like to be admitted to, most
+    /// preferred first
+    pub fn preferred_hospitals(&self) -> Vec<String> {
+        self.prefer_admission_to.to_owned()
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn waitlisted_at(&self) -> Option<DateTime<Utc>> {
+        self.waitlisted_at
+    }
+
+    pub fn admitted_at(&self) -> Option<DateTime<Utc>> {
+        self.admitted_at
+    }
+
+    /// Returns how long this patient waited to be admitted, or if they are
+    /// still on the waitlist, how long they have waited as of now. Returns
+    /// None if it is unknown when they were added to the waitlist.
+    pub fn wait_time(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let until = self.admitted_at.unwrap_or(now);
+        self.waitlisted_at.map(|since| until - since)
+    }
+
     /// returns the name of the hospital this patient is admitted to, or None if
     /// they are not yet admitted to a hospital, and are thus on the waitlist.
     pub fn admitted_to(&self) -> Option<String> {
@@ -105,7 +313,30 @@ impl Patient {
     /// returns whether this patient is on the waitlist to be admitted to a
     /// hospital
     pub fn is_waitlisted(&self) -> bool {
-        self.admitted_to().is_none()
+        self.admitted_to().is_none() && !self.is_discharged() && !self.is_withdrawn()
+    }
+
+    pub fn discharge(&self) -> Option<Discharge> {
+        self.discharge.to_owned()
+    }
+
+    /// returns whether this patient has been discharged from a hospital
+    pub fn is_discharged(&self) -> bool {
+        self.discharge.is_some()
+    }
+
+    pub fn withdrawn_at(&self) -> Option<DateTime<Utc>> {
+        self.withdrawn_at
+    }
+
+    /// returns whether this patient has been withdrawn from the waitlist
+    pub fn is_withdrawn(&self) -> bool {
+        self.withdrawn_at.is_some()
+    }
+
+    /// returns the version of this patient as last stored, or None if unknown
+    pub fn version(&self) -> Option<u64> {
+        self.version
     }
 }
 
@@ -115,7 +346,14 @@ impl Clone for Patient {
             id: self.id,
             name: self.name.to_string(),
             disallow_admission_to: self.disallow_admission_to.to_owned(),
-            admitted_to: self.admitted_to.clone()
+            prefer_admission_to: self.prefer_admission_to.to_owned(),
+            priority: self.priority,
+            waitlisted_at: self.waitlisted_at,
+            admitted_at: self.admitted_at,
+            admitted_to: self.admitted_to.clone(),
+            discharge: self.discharge.clone(),
+            withdrawn_at: self.withdrawn_at,
+            version: self.version
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+// Everything complement can be configured with, read from the [complement] and
+// [logging] sections of the configuration file. Each setting can be overridden
+// by the environment variable named beside it.
+
+use common::config::{Settings, Overrides, Problems, LoggingConfig};
+use serde::Deserialize;
+
+/// other binaries' sections are ignored, so they can share a file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub complement: ComplementConfig
+}
+
+impl Settings for Config {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        self.logging.override_from(environment);
+        self.complement.override_from(environment);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        self.logging.validate(problems);
+        self.complement.validate(problems);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ComplementConfig {
+    /// where to listen for requests (COMPLEMENT_HOST)
+    pub host: String,
+
+    /// (COMPLEMENT_PORT)
+    pub port: u16,
+
+    /// where hospital names are fetched from (ADMISSION_URL)
+    pub admission_url: String,
+
+    /// who to log in to admission as (COMPLEMENT_LOGIN)
+    pub login: String
+}
+
+impl Default for ComplementConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("127.0.0.1"),
+            port: 8081,
+            admission_url: String::from("http://localhost:8080"),
+            login: String::from("Complement Demo")
+        }
+    }
+}
+
+impl Settings for ComplementConfig {
+    fn override_from(&mut self, environment: &mut Overrides) {
+        environment.set("COMPLEMENT_HOST", &mut self.host);
+        environment.set("COMPLEMENT_PORT", &mut self.port);
+        environment.set("ADMISSION_URL", &mut self.admission_url);
+        environment.set("COMPLEMENT_LOGIN", &mut self.login);
+    }
+
+    fn validate(&self, problems: &mut Problems) {
+        problems.require("complement.host", "COMPLEMENT_HOST", &self.host);
+        problems.require_url("complement.admission_url", "ADMISSION_URL", &self.admission_url);
+        problems.require("complement.login", "COMPLEMENT_LOGIN", &self.login);
+    }
+}
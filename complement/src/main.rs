@@ -1,26 +1,66 @@
-use std::{collections::HashSet, sync::Arc};
+mod config;
 
-use actix_web::{get, Responder, HttpServer, App, HttpResponse, web::{Json, self}, error::ErrorInternalServerError};
+use std::{collections::HashSet, sync::Arc, time::Instant};
+
+use actix_web::{get, Responder, HttpServer, App, HttpResponse, web::{Json, self}, error::ErrorInternalServerError, http::header::{CacheControl, CacheDirective, ContentType}, dev::Service, http::header::{HeaderName, HeaderValue}};
 use async_trait::async_trait;
-use common::{hospital::{GetHospitalNamesResponse, GetHospitalNames, HospitalError, GetHospitalNamesRequest}, user::LoginRequest, http_client::HttpClient};
-use tokio::sync::Mutex;
+use common::{hospital::{GetHospitalNamesResponse, GetHospitalNames, HospitalError, GetHospitalNamesRequest}, user::LoginRequest, http_client::HttpClient, health::{HealthReport, Status, check, DEFAULT_CHECK_TIMEOUT}, metrics::{HttpMetrics, UNMATCHED_ROUTE, TEXT_FORMAT}, config::load as load_config, logging::{init_logging, request_id, request_span, REQUEST_ID_HEADER}};
+use futures_util::FutureExt;
+use tracing::{info, Instrument};
+
+use crate::config::Config;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mut client = HttpClient::new("http://localhost:8080"); // todo read URL from env
-    let user = LoginRequest::new("Complement Demo");
+    // nothing can be logged until the configuration says how, so report any
+    // problems with it by crashing
+    let config: Config = load_config()
+        .unwrap_or_else(|e| panic!("{}", e));
+    init_logging(&config.logging);
+    let config = config.complement;
+
+    let mut client = HttpClient::new(&config.admission_url);
+    let user = LoginRequest::new(&config.login);
     client.authenticate_as(&user)
         .await
         .expect("should be able to authenticate");
-    let shared_state = web::Data::new(Mutex::new(RemoteHospitalNameProvider::new(client)));
+    let shared_state = web::Data::new(RemoteHospitalNameProvider::new(client));
+    let metrics = web::Data::new(HttpMetrics::new());
 
-    println!("Starting complement service on localhost:8081");
+    info!(host = %config.host, port = config.port, "Starting complement service");
     HttpServer::new(move || {
         App::new()
             .app_data(shared_state.clone())
+            .app_data(metrics.clone())
+            // times and logs every request within a span carrying its ID
+            .wrap_fn({
+                let metrics = metrics.clone();
+                move |req, srv| {
+                    let method = req.method().to_string();
+                    let route = req.match_pattern().unwrap_or_else(|| String::from(UNMATCHED_ROUTE));
+                    let request_id = request_id(req.headers().get(REQUEST_ID_HEADER).and_then(|id| id.to_str().ok()));
+                    let span = request_span(&request_id, &method, &route);
+                    let started = Instant::now();
+                    let metrics = metrics.clone();
+                    srv.call(req).map(move |res| {
+                        res.map(|mut res| {
+                            let elapsed = started.elapsed();
+                            metrics.observe_request(&method, &route, res.status().as_u16(), elapsed);
+                            info!(status = res.status().as_u16(), elapsed_ms = elapsed.as_millis() as u64, "Finished request");
+
+                            let id = HeaderValue::from_str(&request_id).expect("request IDs should be valid header values");
+                            res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), id);
+                            res
+                        })
+                    }).instrument(span)
+                }
+            })
             .service(complement_handler)
+            .service(healthz_handler)
+            .service(readyz_handler)
+            .service(metrics_handler)
         })
-        .bind(("127.0.0.1", 8081))?
+        .bind((config.host.as_str(), config.port))?
         .run()
         .await
 }
@@ -40,7 +80,7 @@ impl RemoteHospitalNameProvider {
 
 #[async_trait]
 impl GetHospitalNames for RemoteHospitalNameProvider {
-    async fn get_hospital_names(&mut self, _request: GetHospitalNamesRequest) -> Result<GetHospitalNamesResponse, HospitalError> {
+    async fn get_hospital_names(&self, _request: GetHospitalNamesRequest) -> Result<GetHospitalNamesResponse, HospitalError> {
         let r: GetHospitalNamesResponse = self.client.get("/api/v1/hospital-names")
             .await
             .map_err(HospitalError::external_service_error)?
@@ -55,7 +95,7 @@ impl GetHospitalNames for RemoteHospitalNameProvider {
 /// complement of the body
 #[get("/complement")]
 async fn complement_handler(
-    name_provider: web::Data<Mutex<RemoteHospitalNameProvider>>,
+    name_provider: web::Data<RemoteHospitalNameProvider>,
     complement_me: Json<GetHospitalNamesResponse>
 ) -> impl Responder {
     complement_hospitals(name_provider.into_inner(), &complement_me.hospital_names())
@@ -64,14 +104,54 @@ async fn complement_handler(
         .map_err(ErrorInternalServerError)
 }
 
+/// handles GET requests to localhost:8081/healthz, responding as long as the
+/// process can serve requests at all
+#[get("/healthz")]
+async fn healthz_handler() -> impl Responder {
+    respond(HealthReport::live())
+}
+
+/// handles GET requests to localhost:8081/readyz, responding 503 if the
+/// hospital names cannot be fetched from admission
+#[get("/readyz")]
+async fn readyz_handler(
+    name_provider: web::Data<RemoteHospitalNameProvider>
+) -> impl Responder {
+    let admission = check("admission", DEFAULT_CHECK_TIMEOUT, async {
+        name_provider.get_hospital_names(GetHospitalNamesRequest::new())
+            .await
+            .map(|_| ())
+    }).await;
+
+    respond(HealthReport::new(vec![admission]))
+}
+
+/// handles GET requests to localhost:8081/metrics, responding with metrics in
+/// the Prometheus text format
+#[get("/metrics")]
+async fn metrics_handler(metrics: web::Data<HttpMetrics>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(ContentType(TEXT_FORMAT.parse().expect("text format should be a valid media type")))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .body(metrics.render())
+}
+
+fn respond(report: HealthReport) -> HttpResponse {
+    let mut response = match report.status() {
+        Status::Up => HttpResponse::Ok(),
+        Status::Down => HttpResponse::ServiceUnavailable()
+    };
+    response.insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(report)
+}
+
 /// queries another service for hospital names, uses those as the universal set,
 /// then returns the complement of the input
 async fn complement_hospitals(
-    name_provider: Arc<Mutex<RemoteHospitalNameProvider>>,
+    name_provider: Arc<RemoteHospitalNameProvider>,
     hospitals: &HashSet<String>)
 -> Result<HashSet<String>, HospitalError> {
-    let mut lock = name_provider.lock().await;
-    let u = lock.get_hospital_names(GetHospitalNamesRequest::new())
+    let u = name_provider.get_hospital_names(GetHospitalNamesRequest::new())
         .await?
         .hospital_names();
     let complement = u.difference(hospitals) // A' = U - A
@@ -6,6 +6,12 @@ public class CreatePatient
 
     public List<HospitalAllowed> AllowedHospitals { get; set; } = new();
 
+    /// <summary>
+    /// generated when the form is shown, so resubmitting it is not mistaken
+    /// for a new patient
+    /// </summary>
+    public Guid IdempotencyKey { get; set; } = Guid.NewGuid();
+
     public Patient ToPatient()
     {
         var disallowedHospitals = AllowedHospitals
@@ -6,5 +6,7 @@ public class Hospital
 
     public string Name { get; set; } = string.Empty;
 
+    public int? Capacity { get; set; }
+
     public List<Patient> Patients { get; set; } = new();
 }
\ No newline at end of file
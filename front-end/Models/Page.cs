@@ -0,0 +1,14 @@
+namespace Admission.FrontEnd.Models;
+
+/// <summary>
+/// a single page of a list returned by the admissions API
+/// </summary>
+public class Page<T>
+{
+    public List<T> Items { get; set; } = new();
+
+    /// <summary>
+    /// pass as the cursor to get the next page, or null on the last page
+    /// </summary>
+    public string? NextCursor { get; set; }
+}
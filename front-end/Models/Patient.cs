@@ -8,5 +8,13 @@ public class Patient
 
     public HashSet<string> DisallowAdmissionTo { get; set; } = new();
 
+    public List<string> PreferAdmissionTo { get; set; } = new();
+
+    public string Priority { get; set; } = "normal";
+
     public string? AdmittedTo { get; set; }
+
+    public DateTimeOffset? WaitlistedAt { get; set; }
+
+    public DateTimeOffset? AdmittedAt { get; set; }
 }
\ No newline at end of file
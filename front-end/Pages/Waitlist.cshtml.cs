@@ -26,13 +26,13 @@ public class WaitlistModel : PageModel
         Patients.Sort(ComparePatients);
     }
 
-    public async Task<ActionResult> OnPostAdmitFromWaitlist()
+    public async Task<ActionResult> OnPostAdmitFromWaitlist(Guid idempotencyKey)
     {
         await _client.AuthenticateAs(new LoginRequest()
         {
             Email = "admin@dsh.ca.gov"
         });
-        await _client.AdmitFromWaitlist();
+        await _client.AdmitFromWaitlist(idempotencyKey);
         return RedirectToPage();
     }
 
@@ -44,7 +44,7 @@ public class CreatePatientModel : PageModel
         {
             Email = "admin@dsh.ca.gov"
         });
-        await _client.CreatePatient(patient);
+        await _client.CreatePatient(patient, Form.IdempotencyKey);
 
         return Redirect("~/Waitlist");
     }
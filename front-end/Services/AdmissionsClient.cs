@@ -35,12 +35,7 @@ public class AdmissionsClient
 
     public async Task<List<Hospital>> GetAllHospitals()
     {
-        var result = await _httpClient.GetFromJsonAsync<List<Hospital>>("api/v1/hospitals");
-        if (result is null)
-        {
-            throw new Exception("get all hospitals failed to deserialize server response");
-        }
-        return result;
+        return await GetAllPages<Hospital>("api/v1/hospitals", "get all hospitals");
     }
 
     public async Task<Hospital?> GetHospitalByName(string name)
@@ -63,26 +58,61 @@ public class AdmissionsClient
 
     public async Task<List<Patient>> GetWaitlist()
     {
-        var result = await _httpClient.GetFromJsonAsync<List<Patient>>("api/v1/waitlist");
-        if (result is null)
+        return await GetAllPages<Patient>("api/v1/waitlist", "get waitlist");
+    }
+
+    /// <summary>
+    /// follows the cursors of a paged list until reaching its last page
+    /// </summary>
+    private async Task<List<T>> GetAllPages<T>(string endpoint, string description)
+    {
+        var all = new List<T>();
+        var url = $"{endpoint}?limit=200";
+        while (true)
         {
-            throw new Exception("get waitlist failed to deserialize server response");
+            var page = await _httpClient.GetFromJsonAsync<Page<T>>(url);
+            if (page is null)
+            {
+                throw new Exception($"{description} failed to deserialize server response");
+            }
+            all.AddRange(page.Items);
+            if (page.NextCursor is null)
+            {
+                return all;
+            }
+            url = $"{endpoint}?limit=200&cursor={page.NextCursor}";
         }
-        return result;
     }
 
-    public async Task CreatePatient(Patient patient)
+    /// <summary>
+    /// sending the same idempotency key again, such as when retrying after a
+    /// timeout, will not create a duplicate patient
+    /// </summary>
+    public async Task CreatePatient(Patient patient, Guid idempotencyKey)
     {
-        await _httpClient.PostAsJsonAsync("api/v1/waitlist", patient);
+        var request = new HttpRequestMessage(HttpMethod.Post, "api/v1/waitlist")
+        {
+            Content = JsonContent.Create(patient)
+        };
+        request.Headers.Add("Idempotency-Key", idempotencyKey.ToString());
+        await _httpClient.SendAsync(request);
     }
 
-    public async Task AdmitFromWaitlist()
+    public async Task AdmitFromWaitlist(Guid idempotencyKey)
     {
-        await _httpClient.PostAsync("api/v1/hospitals/admit-from-waitlist", null);
+        var request = new HttpRequestMessage(HttpMethod.Post, "api/v1/hospitals/admit-from-waitlist");
+        request.Headers.Add("Idempotency-Key", idempotencyKey.ToString());
+        await _httpClient.SendAsync(request);
     }
 
+    /// <summary>
+    /// the hospital page does not show patients' ETags, so this discharges the
+    /// patient regardless of whether they changed since the page loaded
+    /// </summary>
     public async Task Unadmit(string hospital, Guid patientId)
     {
-        await _httpClient.DeleteAsync($"api/v1/hospitals/{hospital}/{patientId}");
+        var request = new HttpRequestMessage(HttpMethod.Delete, $"api/v1/hospitals/{hospital}/{patientId}");
+        request.Headers.Add("If-Match", "*");
+        await _httpClient.SendAsync(request);
     }
 }
\ No newline at end of file